@@ -1,13 +1,15 @@
 use tauri::{Emitter, Manager, Window};
 use tauri_plugin_oauth::start;
 
+use crate::error::{CapError, ErrorCode};
+
 #[tauri::command]
 #[specta::specta]
-pub async fn start_server(window: Window) -> Result<u16, String> {
+pub async fn start_server(window: Window) -> Result<u16, CapError> {
     start(move |url| {
         let _ = window.emit("redirect_uri", url);
     })
-    .map_err(|err| err.to_string())
+    .map_err(|err| CapError::io("Failed to start the sign in server", &err))
 }
 
 #[tauri::command]
@@ -18,85 +20,94 @@ pub fn has_screen_capture_access() -> bool {
 
 #[tauri::command]
 #[specta::specta]
-pub fn open_screen_capture_preferences() {
+pub fn open_screen_capture_preferences() -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
-    std::process::Command::new("open")
-        .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_ScreenCapture")
-        .spawn()
-        .expect("failed to open system preferences");
+    run(
+        "open",
+        &["x-apple.systempreferences:com.apple.preference.security?Privacy_ScreenCapture"],
+        "Failed to open system preferences",
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn open_mic_preferences() {
+pub fn open_mic_preferences() -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
-    std::process::Command::new("open")
-        .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_Microphone")
-        .spawn()
-        .expect("failed to open system preferences");
+    run(
+        "open",
+        &["x-apple.systempreferences:com.apple.preference.security?Privacy_Microphone"],
+        "Failed to open system preferences",
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn open_camera_preferences() {
+pub fn open_camera_preferences() -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
-    std::process::Command::new("open")
-        .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_Camera")
-        .spawn()
-        .expect("failed to open system preferences");
+    run(
+        "open",
+        &["x-apple.systempreferences:com.apple.preference.security?Privacy_Camera"],
+        "Failed to open system preferences",
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn reset_screen_permissions() {
+pub fn reset_screen_permissions() -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
-    std::process::Command::new("tccutil")
-        .arg("reset")
-        .arg("ScreenCapture")
-        .arg("so.cap.desktop")
-        .spawn()
-        .expect("failed to reset screen permissions");
+    run(
+        "tccutil",
+        &["reset", "ScreenCapture", "so.cap.desktop"],
+        "Failed to reset screen permissions",
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn reset_microphone_permissions() {
+pub fn reset_microphone_permissions() -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
-    std::process::Command::new("tccutil")
-        .arg("reset")
-        .arg("Microphone")
-        .arg("so.cap.desktop")
-        .spawn()
-        .expect("failed to reset microphone permissions");
+    run(
+        "tccutil",
+        &["reset", "Microphone", "so.cap.desktop"],
+        "Failed to reset microphone permissions",
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn reset_camera_permissions() {
+pub fn reset_camera_permissions() -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
-    std::process::Command::new("tccutil")
-        .arg("reset")
-        .arg("Camera")
-        .arg("so.cap.desktop")
-        .spawn()
-        .expect("failed to reset camera permissions");
+    run(
+        "tccutil",
+        &["reset", "Camera", "so.cap.desktop"],
+        "Failed to reset camera permissions",
+    )?;
+    Ok(())
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn close_webview(app_handle: tauri::AppHandle, label: String) -> Result<(), String> {
+pub fn close_webview(app_handle: tauri::AppHandle, label: String) -> Result<(), CapError> {
     match app_handle.get_webview_window(&label) {
         Some(window) => {
             let _ = window.close();
             Ok(())
         }
-        None => Err(format!("No window found with label {}", &label).to_string()),
+        None => Err(window_not_found(&label)),
     }
 }
 
 #[tauri::command]
 #[specta::specta]
-pub fn make_webview_transparent(app_handle: tauri::AppHandle, label: String) -> Result<(), String> {
+pub fn make_webview_transparent(
+    app_handle: tauri::AppHandle,
+    label: String,
+) -> Result<(), CapError> {
     #[cfg(target_os = "macos")]
     {
         use tauri_plugin_decorum::WebviewWindowExt;
@@ -106,9 +117,31 @@ pub fn make_webview_transparent(app_handle: tauri::AppHandle, label: String) ->
                 let _ = window.make_transparent();
                 Ok(())
             }
-            None => Err(format!("No window found with label {}", &label).to_string()),
+            None => Err(window_not_found(&label)),
         }
     }
     #[cfg(not(target_os = "macos"))]
-    "This command is only available on macOS."
+    {
+        let _ = (app_handle, label);
+        Err(CapError::new(
+            ErrorCode::Unsupported,
+            "This command is only available on macOS.",
+        ))
+    }
+}
+
+fn window_not_found(label: &str) -> CapError {
+    CapError::new(
+        ErrorCode::NotFound,
+        format!("No window found with label {}", label),
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn run(program: &str, args: &[&str], message: &str) -> Result<(), CapError> {
+    std::process::Command::new(program)
+        .args(args)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| CapError::io(message, &e))
 }
@@ -0,0 +1,104 @@
+//! The error every command returns, so the frontend can tell what went wrong from its
+//! code rather than by matching on messages.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+/// Stable across releases: the frontend switches on these.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Screen recording (or another system permission) hasn't been granted.
+    PermissionMissing,
+    /// The display, window or audio device went away.
+    DeviceGone,
+    DiskFull,
+    /// FFmpeg (or the encoder standing in for it) wouldn't start, or exited before it
+    /// was done.
+    FfmpegCrashed,
+    /// The server or storage refused an upload.
+    UploadRejected,
+    /// An upload didn't make it to the server.
+    UploadFailed,
+    InvalidOptions,
+    /// The recording can't do that in its current phase, like stopping one that's still
+    /// starting.
+    IllegalTransition,
+    RecordingInProgress,
+    /// A capture queue went over its budget with the `abort` overflow policy, as the
+    /// encoder couldn't keep up.
+    QueueOverflow,
+    /// The app quit before the recording (or its upload) was done.
+    Interrupted,
+    NotFound,
+    /// Not available on this platform.
+    Unsupported,
+    Io,
+    Internal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CapError {
+    pub code: ErrorCode,
+    /// Fit to show the user.
+    pub message: String,
+    /// What led to the error, outermost first. Meant for logs and bug reports.
+    pub causes: Vec<String>,
+}
+
+impl CapError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            causes: vec![],
+        }
+    }
+
+    pub fn caused_by(mut self, cause: impl fmt::Display) -> Self {
+        self.causes.push(cause.to_string());
+        self
+    }
+
+    /// Classifies an I/O error by its kind, for running out of space in particular.
+    pub fn io(message: impl Into<String>, error: &io::Error) -> Self {
+        let code = match error.kind() {
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => ErrorCode::DiskFull,
+            io::ErrorKind::PermissionDenied => ErrorCode::PermissionMissing,
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            // Whatever reads from the pipe has gone away
+            io::ErrorKind::BrokenPipe => ErrorCode::FfmpegCrashed,
+            _ => ErrorCode::Io,
+        };
+
+        Self::new(code, message).caused_by(error)
+    }
+}
+
+impl fmt::Display for CapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        for cause in &self.causes {
+            write!(f, ": {cause}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for CapError {}
+
+/// Most of the recording pipeline still reports errors as plain strings.
+impl From<String> for CapError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
+
+impl From<&str> for CapError {
+    fn from(message: &str) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
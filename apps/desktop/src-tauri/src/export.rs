@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tauri::{AppHandle, State};
+use tauri_specta::Event;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+use crate::error::{CapError, ErrorCode};
+use crate::media::{hls, HlsSegmentType, ProgressParser, VideoCodec};
+use crate::metadata::RecordingMeta;
+use crate::recording::{recording_dir, RecordingPhase, RecordingState};
+use crate::utils::ffmpeg_path_as_str;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Mp4,
+    Webm,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type, tauri_specta::Event)]
+pub struct ExportProgress {
+    pub video_id: String,
+    /// Between 0 and 1.
+    pub progress: f64,
+    pub exported_secs: f64,
+    pub duration_secs: f64,
+}
+
+fn recording_not_found(video_id: &str) -> CapError {
+    CapError::new(
+        ErrorCode::NotFound,
+        format!("There's no recording of {video_id} to export"),
+    )
+}
+
+/// Minimum time between two progress events, so a fast remux doesn't flood the webview.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[tauri::command]
+#[specta::specta]
+#[tracing::instrument(skip(app, state))]
+pub async fn export_recording(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+    destination: PathBuf,
+    format: ExportFormat,
+) -> Result<(), CapError> {
+    let recording_dir = {
+        let state = state.lock().await;
+
+        let is_recording =
+            state.is_underway(&video_id) && state.lifecycle.phase != RecordingPhase::Uploading;
+        if is_recording {
+            return Err(CapError::new(
+                ErrorCode::RecordingInProgress,
+                "The recording can't be exported while it's being recorded",
+            ));
+        }
+
+        recording_dir(&state.data_dir, &video_id)
+    };
+
+    let meta = RecordingMeta::load(&recording_dir)
+        .map_err(|error| recording_not_found(&video_id).caused_by(error))?;
+    if meta.video_id != video_id {
+        return Err(recording_not_found(&video_id));
+    }
+
+    // Separate audio renditions are only tied together by the master playlist, and
+    // every one of them becomes its own track in the exported file
+    let has_renditions = !meta.audio_renditions.is_empty();
+    let (input_path, playlist_path) = if has_renditions {
+        (
+            recording_dir.join(hls::MASTER_PLAYLIST_NAME),
+            recording_dir.join(hls::playlist_name(Some(hls::VIDEO_RENDITION))),
+        )
+    } else {
+        let playlist_path = recording_dir.join(hls::playlist_name(None));
+        (playlist_path.clone(), playlist_path)
+    };
+    let duration_secs = playlist_duration(&playlist_path)
+        .await
+        .ok_or_else(|| recording_not_found(&video_id))?;
+
+    if destination.is_dir() || destination.file_name().is_none() {
+        return Err(CapError::new(
+            ErrorCode::InvalidOptions,
+            format!("{} is not a file path", destination.display()),
+        ));
+    }
+
+    let ffmpeg_binary_path_str = ffmpeg_path_as_str()
+        .map_err(|e| CapError::new(ErrorCode::NotFound, "FFmpeg is missing").caused_by(e))?;
+    let mut ffmpeg_command = Command::new(ffmpeg_binary_path_str);
+    ffmpeg_command
+        .args(["-hide_banner", "-nostats", "-y"])
+        .args(["-progress", "pipe:1"])
+        .arg("-i")
+        .arg(&input_path);
+    if has_renditions {
+        ffmpeg_command.args(["-map", "0:v:0", "-map", "0:a"]);
+    }
+    ffmpeg_command
+        .args(codec_args(&meta, format))
+        .arg(&destination);
+
+    tracing::info!("Exporting {video_id} to {destination:?}");
+
+    let mut process = ffmpeg_command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            CapError::new(ErrorCode::FfmpegCrashed, "Failed to start FFmpeg").caused_by(e)
+        })?;
+
+    // Only the tail of FFmpeg's log is worth reporting when something goes wrong
+    let stderr_tail = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+    if let Some(process_stderr) = process.stderr.take() {
+        let stderr_tail = stderr_tail.clone();
+        tokio::spawn(async move {
+            let mut process_reader = BufReader::new(process_stderr).lines();
+            while let Ok(Some(line)) = process_reader.next_line().await {
+                tracing::debug!("FFmpeg export process: {}", line);
+                let mut tail = stderr_tail.lock().unwrap_or_else(|e| e.into_inner());
+                if tail.len() == 10 {
+                    tail.remove(0);
+                }
+                tail.push(line);
+            }
+        });
+    }
+
+    if let Some(process_stdout) = process.stdout.take() {
+        let mut process_reader = BufReader::new(process_stdout).lines();
+        let mut parser = ProgressParser::default();
+        let mut last_emitted: Option<Instant> = None;
+
+        while let Ok(Some(line)) = process_reader.next_line().await {
+            let Some(progress) = parser.push(&line) else {
+                continue;
+            };
+
+            if last_emitted.is_some_and(|instant| instant.elapsed() < PROGRESS_INTERVAL) {
+                continue;
+            }
+            last_emitted = Some(Instant::now());
+
+            let exported_secs = (progress.out_time_us as f64 / 1_000_000.0).min(duration_secs);
+            emit_progress(&app, &video_id, exported_secs, duration_secs);
+        }
+    }
+
+    let status = process
+        .wait()
+        .await
+        .map_err(|e| CapError::io("Couldn't check on FFmpeg", &e))?;
+
+    if !status.success() {
+        let tail = stderr_tail
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .join("\n");
+        tracing::error!("Export failed with {status}: {tail}");
+        return Err(CapError::new(
+            ErrorCode::FfmpegCrashed,
+            format!("Export failed with {status}"),
+        )
+        .caused_by(tail));
+    }
+
+    emit_progress(&app, &video_id, duration_secs, duration_secs);
+    tracing::info!("Export finished");
+
+    Ok(())
+}
+
+fn emit_progress(app: &AppHandle, video_id: &str, exported_secs: f64, duration_secs: f64) {
+    let progress = ExportProgress {
+        video_id: video_id.to_string(),
+        progress: if duration_secs > 0.0 {
+            exported_secs / duration_secs
+        } else {
+            0.0
+        },
+        exported_secs,
+        duration_secs,
+    };
+
+    if let Err(error) = progress.emit(app) {
+        tracing::warn!("Failed to emit export progress: {error}");
+    }
+}
+
+/// Streams are copied as-is whenever the container can hold them, and only transcoded
+/// when it can't (WebM only accepts VP8/VP9/AV1 video with Vorbis/Opus audio).
+fn codec_args(meta: &RecordingMeta, format: ExportFormat) -> Vec<&'static str> {
+    match format {
+        ExportFormat::Mp4 => {
+            let mut args = vec!["-codec", "copy", "-movflags", "+faststart"];
+            if meta.segment_type == HlsSegmentType::MpegTs {
+                // MPEG-TS carries AAC with ADTS headers, which MP4 doesn't allow
+                args.extend(["-bsf:a", "aac_adtstoasc"]);
+            }
+            args.extend(["-f", "mp4"]);
+            args
+        }
+        ExportFormat::Webm => {
+            let mut args = match meta.video_codec {
+                VideoCodec::Vp9 | VideoCodec::Av1 => vec!["-codec:v", "copy"],
+                VideoCodec::H264 | VideoCodec::Hevc => vec![
+                    "-codec:v",
+                    "libvpx-vp9",
+                    "-deadline",
+                    "good",
+                    "-cpu-used",
+                    "4",
+                    "-row-mt",
+                    "1",
+                    "-crf",
+                    "32",
+                    "-b:v",
+                    "0",
+                ],
+            };
+            args.extend(["-codec:a", "libopus", "-b:a", "128k", "-f", "webm"]);
+            args
+        }
+    }
+}
+
+async fn playlist_duration(playlist_path: &Path) -> Option<f64> {
+    let playlist = tokio::fs::read_to_string(playlist_path).await.ok()?;
+
+    let duration = playlist
+        .lines()
+        .filter_map(|line| line.strip_prefix("#EXTINF:"))
+        .filter_map(|value| value.split(',').next()?.trim().parse::<f64>().ok())
+        .sum();
+
+    Some(duration)
+}
@@ -1,7 +1,7 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use sentry_tracing::EventFilter;
-use specta_typescript::Typescript;
+use specta_typescript::{BigIntExportBehavior, Typescript};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::vec;
@@ -9,21 +9,36 @@ use tauri::{
     tray::{MouseButton, MouseButtonState},
     Emitter, Manager,
 };
-use tauri_specta::{collect_commands, Builder};
+use tauri_specta::{collect_commands, collect_events, Builder};
 use tokio::sync::Mutex;
 use tracing::Level;
 use tracing_subscriber::prelude::*;
 
 #[macro_use]
 mod app;
+mod error;
+mod export;
 mod media;
+mod metadata;
 mod recording;
+mod recovery;
 mod upload;
 mod utils;
 
 use app::commands::*;
-use media::enumerate_audio_devices;
-use recording::{start_dual_recording, stop_all_recordings, RecordingState};
+use export::{export_recording, ExportProgress};
+use media::{
+    enumerate_audio_devices, enumerate_displays, enumerate_video_codecs, enumerate_windows,
+    RecordingStatus, UploadStatus,
+};
+use recording::{
+    get_encoder_progress, get_queue_depths, get_recording_status, pause_recording,
+    resume_recording, start_dual_recording, stop_all_recordings, RecordingState,
+    RecordingStateChanged,
+};
+use recovery::{discard_recording, list_unfinished_recordings, resume_upload};
+use upload::s3::{get_storage_config, set_storage_config};
+use upload::{get_upload_queue, UploadConnectivity, UploadItem, UploadItemProgress, UploadQueue};
 
 use ffmpeg_sidecar::{
     command::ffmpeg_is_installed,
@@ -101,10 +116,13 @@ fn main() {
         Ok(())
     }
 
-    if let Err(error) = handle_ffmpeg_installation() {
-        tracing::error!(error);
-        // TODO: UI message instead
-        panic!("Failed to install FFmpeg, which is required for Cap to function. Shutting down now")
+    let ffmpeg_installed = match handle_ffmpeg_installation() {
+        Ok(()) => true,
+        Err(error) => {
+            tracing::error!(error);
+            tracing::warn!("FFmpeg couldn't be installed, recording with the built-in encoder");
+            false
+        }
     };
 
     let event_loop = winit::event_loop::EventLoop::new().expect("Failed to create event loop");
@@ -128,25 +146,54 @@ fn main() {
         }
     };
 
-    let specta_builder = Builder::<tauri::Wry>::new().commands(collect_commands![
-        start_dual_recording,
-        stop_all_recordings,
-        enumerate_audio_devices,
-        start_server,
-        open_screen_capture_preferences,
-        open_mic_preferences,
-        open_camera_preferences,
-        has_screen_capture_access,
-        reset_screen_permissions,
-        reset_microphone_permissions,
-        reset_camera_permissions,
-        close_webview,
-        make_webview_transparent
-    ]);
+    let specta_builder = Builder::<tauri::Wry>::new()
+        .commands(collect_commands![
+            start_dual_recording,
+            stop_all_recordings,
+            pause_recording,
+            resume_recording,
+            get_queue_depths,
+            get_encoder_progress,
+            get_recording_status,
+            enumerate_audio_devices,
+            enumerate_displays,
+            enumerate_windows,
+            enumerate_video_codecs,
+            start_server,
+            open_screen_capture_preferences,
+            open_mic_preferences,
+            open_camera_preferences,
+            has_screen_capture_access,
+            reset_screen_permissions,
+            reset_microphone_permissions,
+            reset_camera_permissions,
+            close_webview,
+            make_webview_transparent,
+            export_recording,
+            list_unfinished_recordings,
+            resume_upload,
+            discard_recording,
+            get_upload_queue,
+            get_storage_config,
+            set_storage_config
+        ])
+        .events(collect_events![
+            ExportProgress,
+            RecordingStateChanged,
+            RecordingStatus,
+            UploadConnectivity,
+            UploadItem,
+            UploadItemProgress,
+            UploadStatus
+        ]);
 
     #[cfg(debug_assertions)] // <- Only export on non-release builds
     specta_builder
-        .export(Typescript::default(), "../src/utils/commands.ts")
+        .export(
+            // Byte counts and the like never get anywhere near 2^53
+            Typescript::default().bigint(BigIntExportBehavior::Number),
+            "../src/utils/commands.ts",
+        )
         .expect("Failed to export typescript bindings");
 
     tauri::Builder::default()
@@ -156,6 +203,8 @@ fn main() {
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .invoke_handler(specta_builder.invoke_handler())
         .setup(move |app| {
+            specta_builder.mount_events(app);
+
             let handle = app.handle();
 
             if let Some(main_window) = app.get_webview_window("main") {
@@ -171,14 +220,23 @@ fn main() {
                 .path()
                 .app_data_dir()
                 .unwrap_or_else(|_| PathBuf::new());
+            upload::s3::load(&data_directory);
+            let upload_queue = UploadQueue::load(&data_directory);
+            recovery::recover(&data_directory, &upload_queue);
+            upload_queue.start(handle.clone());
 
             let recording_state = RecordingState {
+                lifecycle: Default::default(),
                 active_recording: None,
                 data_dir: data_directory,
                 max_screen_width: max_width as usize,
                 max_screen_height: max_height as usize,
+                ffmpeg_installed,
+                upload_queue,
             };
-            app.manage(Arc::new(Mutex::new(recording_state)));
+            let recording_state = Arc::new(Mutex::new(recording_state));
+            app.manage(recording_state.clone());
+            recovery::follow_queued_uploads(handle.clone(), recording_state);
 
             if let Some(main_tray) = app.tray_by_id("cap_main") {
                 main_tray.on_tray_icon_event(move |tray, event| match event {
@@ -1,27 +1,156 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, SampleFormat, SizedSample, Stream, SupportedStreamConfig};
+use cpal::{
+    BuildStreamError, Device, PauseStreamError, PlayStreamError, SampleFormat, SizedSample, Stream,
+    SupportedStreamConfig,
+};
 use indexmap::IndexMap;
 use num_traits::ToBytes;
-use std::{future::Future, path::PathBuf, sync::Arc};
-use tokio::sync::mpsc::error::TrySendError;
-use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc};
-
-use super::{Instant, SharedFlag, SharedInstant};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    future::Future,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+use super::encoder::EncoderSender;
+use super::pool::{Buffer, BufferPool};
+use super::queue::{self, QueueMetrics, QueueOptions, QueueReceiver, QueueSender, SendError};
+use super::sync::{DriftCorrector, SharedTimeline};
+use super::{Captured, Instant, SharedFlag, SharedInstant};
+use crate::error::{CapError, ErrorCode};
+use crate::metadata::{ChildProcess, StreamSync};
 use crate::utils;
 
-type SampleReceiver = mpsc::Receiver<Arc<Vec<u8>>>;
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AudioSource {
+    Microphone {
+        name: String,
+    },
+    /// Whatever the computer is playing, which can't be recorded on macOS yet. On Linux,
+    /// `monitor` is the PulseAudio/PipeWire monitor source to record from, defaulting to
+    /// the one of the default sink.
+    SystemAudio {
+        monitor: Option<String>,
+    },
+}
+
+impl AudioSource {
+    /// Fails for sources there's no way to record on this platform, so the recording
+    /// isn't started without them.
+    pub fn check_supported(&self) -> Result<(), CapError> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Self::SystemAudio { .. } => Err(system_audio_unsupported()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn system_audio_unsupported() -> CapError {
+    CapError::new(
+        ErrorCode::Unsupported,
+        "System audio can't be recorded on macOS yet",
+    )
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+pub struct AudioInputOptions {
+    pub source: AudioSource,
+    /// Linear gain applied before mixing, where 1.0 leaves the input untouched.
+    pub gain: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioMixMode {
+    /// All inputs are mixed down into a single audio track.
+    #[default]
+    Mixed,
+    /// Every input is written as its own alternate audio rendition in the HLS output,
+    /// so they can be rebalanced after the fact.
+    SeparateTracks,
+}
+
+enum AudioBackend {
+    Device {
+        device: Device,
+        config: SupportedStreamConfig,
+        stream: Option<Stream>,
+    },
+    /// Records a PulseAudio/PipeWire monitor source through `parec`, as cpal only
+    /// sees ALSA devices on Linux.
+    #[cfg(target_os = "linux")]
+    Monitor {
+        source: String,
+        process: Option<std::process::Child>,
+    },
+}
 
 pub struct AudioCapturer {
-    device: Device,
+    backend: AudioBackend,
     pub device_name: String,
-    config: SupportedStreamConfig,
+    pub gain: f32,
     should_stop: SharedFlag,
-    sample_receiver: Option<SampleReceiver>,
-    stream: Option<Stream>,
+    is_paused: SharedFlag,
+    sample_receiver: Option<QueueReceiver>,
+    level: LevelMeter,
+}
+
+/// The loudest sample handed to the encoder since it was last read, for showing input
+/// levels.
+#[derive(Clone, Default)]
+pub struct LevelMeter(Arc<AtomicU32>);
+
+impl LevelMeter {
+    fn measure(&self, data: &[u8], sample_format: &str, gain: f32) {
+        let level = (peak(data, sample_format) * gain).min(1.0);
+        // Non-negative floats sort the same way as their bits
+        self.0.fetch_max(level.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Between 0 and 1, starting over from silence.
+    pub fn take(&self) -> f32 {
+        f32::from_bits(self.0.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// The largest absolute sample value, where full scale is 1.
+fn peak(data: &[u8], sample_format: &str) -> f32 {
+    fn max<const N: usize>(data: &[u8], sample: impl Fn([u8; N]) -> f64) -> f32 {
+        data.chunks_exact(N)
+            .map(|bytes| sample(bytes.try_into().unwrap()).abs() as f32)
+            .fold(0.0, f32::max)
+    }
+
+    match sample_format {
+        "s8" => max::<1>(data, |b| i8::from_le_bytes(b) as f64 / 128.0),
+        "s16le" => max(data, |b| i16::from_le_bytes(b) as f64 / 32768.0),
+        "s32le" => max(data, |b| i32::from_le_bytes(b) as f64 / 2147483648.0),
+        "u8" => max::<1>(data, |[b]| (b as f64 - 128.0) / 128.0),
+        "u16le" => max(data, |b| (u16::from_le_bytes(b) as f64 - 32768.0) / 32768.0),
+        "u32le" => max(data, |b| {
+            (u32::from_le_bytes(b) as f64 - 2147483648.0) / 2147483648.0
+        }),
+        "f32le" => max(data, |b| f32::from_le_bytes(b) as f64),
+        "f64le" => max(data, f64::from_le_bytes),
+        _ => 0.0,
+    }
 }
 
 impl AudioCapturer {
-    pub fn init(custom_device: Option<&str>, should_stop: SharedFlag) -> Option<Self> {
+    pub fn init(
+        custom_device: Option<&str>,
+        should_stop: SharedFlag,
+        is_paused: SharedFlag,
+    ) -> Option<Self> {
         tracing::debug!("Custom device: {:?}", custom_device);
 
         if custom_device == Some("None") {
@@ -42,62 +171,179 @@ impl AudioCapturer {
             tracing::info!("Using audio device: {}", name);
 
             Self {
-                config,
-                device,
+                backend: AudioBackend::Device {
+                    device,
+                    config,
+                    stream: None,
+                },
                 device_name: name,
+                gain: 1.0,
                 should_stop,
+                is_paused,
                 sample_receiver: None,
-                stream: None,
+                level: LevelMeter::default(),
             }
         })
     }
 
+    /// Captures the audio output of the computer. WASAPI can record any output device
+    /// in loopback mode, while Linux goes through a PulseAudio/PipeWire monitor source.
+    pub fn system_audio(
+        monitor: Option<&str>,
+        should_stop: SharedFlag,
+        is_paused: SharedFlag,
+    ) -> Result<Self, CapError> {
+        #[cfg(windows)]
+        let (backend, device_name) = {
+            let _ = monitor;
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| {
+                    CapError::new(ErrorCode::DeviceGone, "No audio output device to capture")
+                })?;
+            let config = device.default_output_config().map_err(|e| {
+                CapError::new(ErrorCode::DeviceGone, "Failed to get output device config")
+                    .caused_by(e)
+            })?;
+            let name = device.name().unwrap_or_else(|_| "System audio".to_string());
+
+            (
+                AudioBackend::Device {
+                    device,
+                    config,
+                    stream: None,
+                },
+                name,
+            )
+        };
+
+        #[cfg(target_os = "linux")]
+        let (backend, device_name) = {
+            let source = monitor.unwrap_or("@DEFAULT_MONITOR@").to_string();
+            (
+                AudioBackend::Monitor {
+                    source: source.clone(),
+                    process: None,
+                },
+                source,
+            )
+        };
+
+        #[cfg(target_os = "macos")]
+        {
+            let _ = (monitor, should_stop, is_paused);
+            return Err(system_audio_unsupported());
+        }
+
+        #[cfg(not(target_os = "macos"))]
+        {
+            tracing::info!("Using system audio source: {}", device_name);
+
+            Ok(Self {
+                backend,
+                device_name,
+                gain: 1.0,
+                should_stop,
+                is_paused,
+                sample_receiver: None,
+                level: LevelMeter::default(),
+            })
+        }
+    }
+
     pub fn log_info(&self) {
         tracing::info!("Sample rate: {}", self.sample_rate());
         tracing::info!("Channels: {}", self.channels());
         tracing::info!("Sample format: {}", self.sample_format());
     }
 
-    pub fn start(&mut self, start_time: SharedInstant) -> Result<(), String> {
-        tracing::trace!("Building input stream...");
+    pub fn start(
+        &mut self,
+        start_time: SharedInstant,
+        queue_options: &QueueOptions,
+    ) -> Result<QueueMetrics, CapError> {
+        let (sender, receiver) = queue::channel(
+            &self.device_name,
+            queue::AUDIO_BUDGET_BYTES,
+            queue_options,
+            self.should_stop.clone(),
+        );
+
+        match &mut self.backend {
+            AudioBackend::Device {
+                device,
+                config,
+                stream,
+            } => {
+                tracing::trace!("Building input stream...");
+
+                let (producer, consumer) = ring(
+                    config.sample_rate().0 as usize
+                        * config.channels() as usize
+                        * config.sample_format().sample_size(),
+                );
+                drain_ring(consumer, sender, start_time, self.should_stop.clone());
+
+                let is_paused = self.is_paused.clone();
+                let input_stream = (match config.sample_format() {
+                    SampleFormat::I8 => build_stream::<i8>(device, config, producer, is_paused),
+                    SampleFormat::I16 => build_stream::<i16>(device, config, producer, is_paused),
+                    SampleFormat::I32 => build_stream::<i32>(device, config, producer, is_paused),
+                    SampleFormat::U8 => build_stream::<u8>(device, config, producer, is_paused),
+                    SampleFormat::U16 => build_stream::<u16>(device, config, producer, is_paused),
+                    SampleFormat::U32 => build_stream::<u32>(device, config, producer, is_paused),
+                    SampleFormat::F32 => build_stream::<f32>(device, config, producer, is_paused),
+                    SampleFormat::F64 => build_stream::<f64>(device, config, producer, is_paused),
+                    _ => unreachable!(),
+                })
+                .map_err(|error| match error {
+                    BuildStreamError::DeviceNotAvailable => device_gone(&self.device_name),
+                    error => CapError::from("Failed to build audio input stream").caused_by(error),
+                })?;
 
-        let (receiver, stream) = (match self.config.sample_format() {
-            SampleFormat::I8 => self.build_stream::<i8>(start_time),
-            SampleFormat::I16 => self.build_stream::<i16>(start_time),
-            SampleFormat::I32 => self.build_stream::<i32>(start_time),
-            SampleFormat::U8 => self.build_stream::<u8>(start_time),
-            SampleFormat::U16 => self.build_stream::<u16>(start_time),
-            SampleFormat::U32 => self.build_stream::<u32>(start_time),
-            SampleFormat::F32 => self.build_stream::<f32>(start_time),
-            SampleFormat::F64 => self.build_stream::<f64>(start_time),
-            _ => unreachable!(),
-        })?;
+                input_stream.play().map_err(|error| match error {
+                    PlayStreamError::DeviceNotAvailable => device_gone(&self.device_name),
+                    error => CapError::from("Failed to start audio recording").caused_by(error),
+                })?;
+
+                *stream = Some(input_stream);
+            }
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor { source, process } => {
+                let child = monitor::start(source, sender, self.is_paused.clone(), start_time)?;
+
+                *process = Some(child);
+            }
+        };
 
-        stream
-            .play()
-            .map_err(|_| "Failed to start audio recording")?;
         tracing::info!("Audio recording started.");
 
-        self.stream = Some(stream);
+        let metrics = receiver.metrics();
         self.sample_receiver = Some(receiver);
-        Ok(())
+        Ok(metrics)
     }
 
-    pub fn collect_samples(&mut self, destination: PathBuf) -> impl Future<Output = ()> + 'static {
+    pub fn collect_samples(
+        &mut self,
+        destination: PathBuf,
+        mut drift: DriftCorrector,
+    ) -> impl Future<Output = Result<StreamSync, CapError>> + 'static {
         tracing::trace!("Starting audio channel senders...");
         let mut receiver = self
             .sample_receiver
             .take()
             .expect("Audio sample collection already started!");
         let should_stop = self.should_stop.clone();
+        let (level, sample_format, gain) = self.level_source();
 
         async move {
-            let mut pipe = File::create(destination).await.unwrap();
+            let mut pipe = File::create(destination).await.map_err(write_error)?;
 
-            while let Some(bytes) = receiver.recv().await {
-                pipe.write_all(&bytes)
+            while let Some(samples) = receiver.recv().await {
+                level.measure(&samples.data, &sample_format, gain);
+                pipe.write_all(&drift.correct_samples(&samples.data, samples.captured_at))
                     .await
-                    .expect("Failed to write audio data to FFmpeg stdin");
+                    .map_err(write_error)?;
 
                 if should_stop.get() {
                     receiver.close();
@@ -105,19 +351,105 @@ impl AudioCapturer {
             }
 
             let _ = pipe.sync_all().await;
+
+            Ok(drift.stats())
         }
     }
 
-    pub fn stop(&mut self) -> Result<(), String> {
-        if let Some(ref mut stream) = self.stream {
-            stream.pause().map_err(|_| "Failed to pause stream")?;
-            tracing::info!("Audio capturing stopped.");
-            Ok(())
-        } else {
-            return Err("Original recording was not started".to_string());
+    /// In-process encoders time samples by counting them too, so they get corrected
+    /// the same way.
+    pub fn encode_samples(
+        &mut self,
+        input: usize,
+        encoder: EncoderSender,
+        mut drift: DriftCorrector,
+    ) -> impl Future<Output = Result<StreamSync, CapError>> + 'static {
+        tracing::trace!("Starting audio encoder senders...");
+        let mut receiver = self
+            .sample_receiver
+            .take()
+            .expect("Audio sample collection already started!");
+        let should_stop = self.should_stop.clone();
+        let (level, sample_format, gain) = self.level_source();
+
+        async move {
+            while let Some(samples) = receiver.recv().await {
+                level.measure(&samples.data, &sample_format, gain);
+                let data = match drift.correct_samples(&samples.data, samples.captured_at) {
+                    Cow::Borrowed(_) => samples.data.clone(),
+                    Cow::Owned(corrected) => Buffer::from(corrected),
+                };
+                let samples = Captured {
+                    data,
+                    captured_at: samples.captured_at,
+                };
+                if !encoder.send_audio(input, samples).await {
+                    tracing::error!("Encoder has shut down. Dropping audio samples.");
+                    break;
+                }
+
+                if should_stop.get() {
+                    receiver.close();
+                }
+            }
+
+            Ok(drift.stats())
         }
     }
 
+    pub fn level(&self) -> LevelMeter {
+        self.level.clone()
+    }
+
+    fn level_source(&self) -> (LevelMeter, String, f32) {
+        (self.level(), self.sample_format().to_string(), self.gain)
+    }
+
+    pub fn drift_corrector(&self, timeline: SharedTimeline) -> DriftCorrector {
+        let frame_size = match &self.backend {
+            AudioBackend::Device { config, .. } => {
+                config.sample_format().sample_size() * config.channels() as usize
+            }
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor { .. } => monitor::FRAME_SIZE,
+        };
+
+        DriftCorrector::new(
+            &self.device_name,
+            self.sample_rate() as f64,
+            frame_size,
+            timeline,
+        )
+    }
+
+    pub fn stop(&mut self) -> Result<(), CapError> {
+        match &mut self.backend {
+            AudioBackend::Device {
+                stream: Some(stream),
+                ..
+            } => {
+                stream.pause().map_err(|error| match error {
+                    PauseStreamError::DeviceNotAvailable => device_gone(&self.device_name),
+                    error => CapError::from("Failed to pause stream").caused_by(error),
+                })?;
+            }
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor {
+                process: Some(process),
+                ..
+            } => {
+                process
+                    .kill()
+                    .map_err(|e| CapError::io("Failed to stop parec", &e))?;
+                process.wait().ok();
+            }
+            _ => return Err("Original recording was not started".into()),
+        }
+
+        tracing::info!("Audio capturing stopped.");
+        Ok(())
+    }
+
     // TODO: Where to add these...?
     pub fn ffmpeg_filters(&self) -> Vec<&str> {
         let mut audio_filters = Vec::new();
@@ -130,17 +462,43 @@ impl AudioCapturer {
         audio_filters
     }
 
+    /// Set for inputs captured through a process of their own, once they've started.
+    pub fn child_process(&self) -> Option<ChildProcess> {
+        match &self.backend {
+            AudioBackend::Device { .. } => None,
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor { process, .. } => process.as_ref().map(|process| ChildProcess {
+                pid: process.id(),
+                name: "parec".to_string(),
+            }),
+        }
+    }
+
     pub fn sample_rate(&self) -> u32 {
-        self.config.sample_rate().0
+        match &self.backend {
+            AudioBackend::Device { config, .. } => config.sample_rate().0,
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor { .. } => monitor::SAMPLE_RATE,
+        }
     }
 
     pub fn channels(&self) -> u16 {
-        self.config.channels()
+        match &self.backend {
+            AudioBackend::Device { config, .. } => config.channels(),
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor { .. } => monitor::CHANNELS,
+        }
     }
 
     // Returns ffmpeg sample format ID and sample size in bytes
     pub fn sample_format(&self) -> &str {
-        match self.config.sample_format() {
+        let config = match &self.backend {
+            AudioBackend::Device { config, .. } => config,
+            #[cfg(target_os = "linux")]
+            AudioBackend::Monitor { .. } => return monitor::SAMPLE_FORMAT,
+        };
+
+        match config.sample_format() {
             SampleFormat::I8 => "s8",
             SampleFormat::I16 => "s16le",
             SampleFormat::I32 => "s32le",
@@ -152,54 +510,259 @@ impl AudioCapturer {
             _ => unreachable!(),
         }
     }
+}
+
+/// How much audio the real-time callback can get ahead of the thread draining it.
+const RING_SECONDS: usize = 2;
+/// Callbacks deliver anywhere from a few dozen to a few hundred buffers a second.
+const RING_CHUNKS: usize = 1024;
+/// How often the ring buffers are drained.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(5);
+/// Sample buffers kept around for reuse.
+const SAMPLE_POOL_SIZE: usize = 64;
+
+/// The real-time end of the capture path. Samples are written to lock-free ring buffers,
+/// along with when they were captured, as the cpal callback must not allocate or block.
+struct RingProducer {
+    bytes: rtrb::Producer<u8>,
+    chunks: rtrb::Producer<(usize, Instant)>,
+    /// Samples dropped because the ring buffers were full.
+    overruns: Arc<AtomicU64>,
+}
+
+struct RingConsumer {
+    bytes: rtrb::Consumer<u8>,
+    chunks: rtrb::Consumer<(usize, Instant)>,
+    overruns: Arc<AtomicU64>,
+}
+
+fn ring(bytes_per_second: usize) -> (RingProducer, RingConsumer) {
+    let (bytes_producer, bytes_consumer) = rtrb::RingBuffer::new(bytes_per_second * RING_SECONDS);
+    let (chunks_producer, chunks_consumer) = rtrb::RingBuffer::new(RING_CHUNKS);
+    let overruns = Arc::new(AtomicU64::new(0));
+
+    (
+        RingProducer {
+            bytes: bytes_producer,
+            chunks: chunks_producer,
+            overruns: overruns.clone(),
+        },
+        RingConsumer {
+            bytes: bytes_consumer,
+            chunks: chunks_consumer,
+            overruns,
+        },
+    )
+}
+
+/// Moves samples from the ring buffers into the queue feeding the encoder, on a thread
+/// where allocating, locking and spilling to disk are fine.
+fn drain_ring(
+    mut consumer: RingConsumer,
+    sender: QueueSender,
+    start_time: SharedInstant,
+    should_stop: SharedFlag,
+) {
+    std::thread::spawn(move || {
+        let pool = BufferPool::new(SAMPLE_POOL_SIZE);
+        let mut reported_overruns = 0;
+
+        loop {
+            let overruns = consumer.overruns.load(Ordering::Relaxed);
+            if overruns > reported_overruns {
+                tracing::warn!(
+                    "Dropped {} audio samples, as they weren't taken from the ring buffer in time",
+                    overruns - reported_overruns
+                );
+                reported_overruns = overruns;
+            }
+
+            let Ok((size, captured_at)) = consumer.chunks.pop() else {
+                if should_stop.get() || consumer.chunks.is_abandoned() {
+                    break;
+                }
+                std::thread::sleep(DRAIN_INTERVAL);
+                continue;
+            };
+
+            // The callback writes the samples before their chunk, so they're all there
+            let Ok(chunk) = consumer.bytes.read_chunk(size) else {
+                tracing::error!("Audio ring buffer is missing samples");
+                break;
+            };
+            let (first, second) = chunk.as_slices();
+            let mut data = pool.take();
+            data.extend_from_slice(first);
+            data.extend_from_slice(second);
+            chunk.commit_all();
+
+            let samples = Captured {
+                data: pool.wrap(data),
+                captured_at,
+            };
+            match sender.send(samples) {
+                Ok(_) => {
+                    if let Ok(mut start_time_option) = start_time.try_lock() {
+                        if start_time_option.is_none() {
+                            // When the first samples were captured, rather than when
+                            // they made it here
+                            *start_time_option = Some(captured_at);
+
+                            tracing::info!("Audio sample size: {size}");
+                            tracing::trace!("Audio start time captured");
+                        }
+                    }
+                }
+                Err(SendError::Full) => {}
+                Err(SendError::Overflow(overflow)) => {
+                    tracing::error!("{overflow}");
+                    break;
+                }
+                Err(SendError::Closed) => {
+                    tracing::trace!("Recording has been stopped. Dropping data.");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn write_error(error: std::io::Error) -> CapError {
+    CapError::io("Failed to write audio data to FFmpeg", &error)
+}
+
+fn device_gone(device_name: &str) -> CapError {
+    CapError::new(
+        ErrorCode::DeviceGone,
+        format!("{device_name} is no longer available"),
+    )
+}
 
-    fn build_stream<T>(&self, start_time: SharedInstant) -> Result<(SampleReceiver, Stream), String>
-    where
-        T: SizedSample + ToBytes<Bytes: AsRef<[u8]>>,
-    {
-        let (sender, receiver) = mpsc::channel(2048);
+fn build_stream<T>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    mut producer: RingProducer,
+    is_paused: SharedFlag,
+) -> Result<Stream, BuildStreamError>
+where
+    T: SizedSample + ToBytes<Bytes: AsRef<[u8]>>,
+{
+    let sample_size = std::mem::size_of::<T>();
+
+    device.build_input_stream(
+        &config.clone().into(),
+        move |data: &[T], _| {
+            if is_paused.get() {
+                return;
+            }
+
+            let captured_at = Instant::now();
+            let size = data.len() * sample_size;
+            if producer.bytes.slots() < size || producer.chunks.is_full() {
+                producer
+                    .overruns
+                    .fetch_add(data.len() as u64, Ordering::Relaxed);
+                return;
+            }
 
-        self.device
-            .build_input_stream(
-                &self.config.clone().into(),
-                move |data: &[T], _| {
-                    let mut first_frame_time_guard = start_time.try_lock();
+            if let Ok(chunk) = producer.bytes.write_chunk_uninit(size) {
+                chunk.fill_from_iter(data.iter().flat_map(|sample| {
+                    let bytes = sample.to_le_bytes();
+                    (0..sample_size).map(move |index| bytes.as_ref()[index])
+                }));
+                producer.chunks.push((size, captured_at)).ok();
+            }
+        },
+        |err| {
+            tracing::error!("An error occurred on the audio stream: {}", err);
+        },
+        None,
+    )
+}
 
-                    let sample_size = std::mem::size_of::<T>();
-                    let mut bytes = vec![0; data.len() * sample_size];
-                    let size = bytes.len();
-                    for (dest, source) in bytes.chunks_exact_mut(sample_size).zip(data.iter()) {
-                        dest.copy_from_slice(source.to_le_bytes().as_ref());
+#[cfg(target_os = "linux")]
+mod monitor {
+    use super::{
+        BufferPool, CapError, Captured, Instant, QueueSender, SendError, SharedFlag, SharedInstant,
+        SAMPLE_POOL_SIZE,
+    };
+    use std::io::Read;
+    use std::process::{Child, Command, Stdio};
+
+    pub const SAMPLE_RATE: u32 = 48_000;
+    pub const CHANNELS: u16 = 2;
+    pub const SAMPLE_FORMAT: &str = "s16le";
+    pub const FRAME_SIZE: usize = 2 * CHANNELS as usize;
+
+    /// 20ms of 16-bit stereo samples.
+    const CHUNK_SIZE: usize = (SAMPLE_RATE as usize / 50) * CHANNELS as usize * 2;
+
+    pub fn start(
+        source: &str,
+        sender: QueueSender,
+        is_paused: SharedFlag,
+        start_time: SharedInstant,
+    ) -> Result<Child, CapError> {
+        let mut process = Command::new("parec")
+            .args(["--device", source, "--raw", "--latency-msec=20"])
+            .args(["--format", SAMPLE_FORMAT])
+            .arg(format!("--rate={SAMPLE_RATE}"))
+            .arg(format!("--channels={CHANNELS}"))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| CapError::io(format!("Failed to start parec for {source}"), &e))?;
+
+        let mut stdout = process
+            .stdout
+            .take()
+            .ok_or_else(|| CapError::from("Failed to take parec stdout"))?;
+
+        std::thread::spawn(move || {
+            let pool = BufferPool::new(SAMPLE_POOL_SIZE);
+            let mut buffer = vec![0u8; CHUNK_SIZE];
+
+            loop {
+                let size = match stdout.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(size) => size,
+                    Err(error) => {
+                        tracing::error!("Failed to read from monitor source: {error}");
+                        break;
                     }
+                };
 
-                    let sample_data = Arc::new(bytes);
-                    match sender.try_send(sample_data) {
-                        Ok(_) => {
-                            if let Ok(ref mut start_time_option) = first_frame_time_guard {
-                                if start_time_option.is_none() {
-                                    **start_time_option = Some(Instant::now());
+                if is_paused.get() {
+                    continue;
+                }
 
-                                    tracing::info!("Audio sample size: {size}");
-                                    tracing::trace!("Audio start time captured");
-                                }
+                let samples = Captured {
+                    data: pool.copy_from(&buffer[..size]),
+                    captured_at: Instant::now(),
+                };
+                match sender.send(samples) {
+                    Ok(_) => {
+                        if let Ok(mut start_time_option) = start_time.try_lock() {
+                            if start_time_option.is_none() {
+                                *start_time_option = Some(Instant::now());
+                                tracing::trace!("System audio start time captured");
                             }
                         }
-                        Err(TrySendError::Full(_)) => {
-                            // TODO: Consider panicking? This should *never* happen
-                            tracing::error!("Channel buffer is full!");
-                        }
-                        _ => {
-                            tracing::trace!("Recording has been stopped. Dropping data.")
-                        }
                     }
-                },
-                |err| {
-                    tracing::error!("An error occurred on the audio stream: {}", err);
-                },
-                None,
-            )
-            .map(|stream| (receiver, stream))
-            .map_err(|_| "Failed to build audio input stream".into())
+                    Err(SendError::Full) => {}
+                    Err(SendError::Overflow(overflow)) => {
+                        tracing::error!("{overflow}");
+                        break;
+                    }
+                    Err(SendError::Closed) => {
+                        tracing::trace!("Recording has been stopped. Dropping data.");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(process)
     }
 }
 
@@ -251,3 +814,84 @@ pub fn get_input_devices() -> IndexMap<String, (Device, SupportedStreamConfig)>
 
     device_map
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::queue::OverflowPolicy;
+    use super::*;
+    use std::process::Command;
+    use tokio::sync::Mutex;
+
+    /// A sink that plays nowhere, loaded for as long as it's around. Its monitor source
+    /// produces a continuous stream of silence.
+    struct NullSink(String);
+
+    impl NullSink {
+        fn load(name: &str) -> Self {
+            let output = Command::new("pactl")
+                .args(["load-module", "module-null-sink"])
+                .arg(format!("sink_name={name}"))
+                .output()
+                .expect("Failed to run pactl");
+            assert!(
+                output.status.success(),
+                "Failed to load a null sink: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+
+            Self(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+    }
+
+    impl Drop for NullSink {
+        fn drop(&mut self) {
+            let _ = Command::new("pactl")
+                .args(["unload-module", &self.0])
+                .status();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a PulseAudio or PipeWire server, with pactl and parec"]
+    fn records_null_sink_monitor() {
+        let _sink = NullSink::load("cap_test");
+        let mut capturer = AudioCapturer::system_audio(
+            Some("cap_test.monitor"),
+            SharedFlag::default(),
+            SharedFlag::default(),
+        )
+        .unwrap();
+        let queue_options = QueueOptions {
+            policy: OverflowPolicy::Drop,
+            spill_dir: std::env::temp_dir(),
+        };
+        capturer
+            .start(Arc::new(Mutex::new(None)), &queue_options)
+            .unwrap();
+        let mut receiver = capturer.sample_receiver.take().unwrap();
+
+        let one_second = monitor::SAMPLE_RATE as usize * monitor::FRAME_SIZE;
+        let (received, loudest) = tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let (mut received, mut loudest) = (0, 0.0f32);
+            let _ = tokio::time::timeout(Duration::from_secs(5), async {
+                while received < one_second {
+                    let Some(samples) = receiver.recv().await else {
+                        break;
+                    };
+                    received += samples.data.len();
+                    loudest = loudest.max(peak(&samples.data, monitor::SAMPLE_FORMAT));
+                }
+            })
+            .await;
+
+            (received, loudest)
+        });
+        capturer.stop().unwrap();
+
+        assert!(
+            received >= one_second,
+            "Only got {received} bytes from the monitor source"
+        );
+        assert_eq!(loudest, 0.0, "Nothing plays to the null sink");
+    }
+}
@@ -0,0 +1,224 @@
+use audiopus::{coder::Encoder, Application, Bitrate, Channels, SampleRate};
+use std::{collections::VecDeque, time::Duration};
+
+use super::fmp4::{AudioTrack, Sample, AUDIO_TIMESCALE};
+use crate::error::{CapError, ErrorCode};
+use crate::media::encoder::{encoder_error, AudioInputFormat, EncoderConfig};
+
+const CHANNELS: usize = 2;
+/// 20ms, the frame size Opus is most efficient at.
+const FRAME_SIZE: usize = 960;
+/// How far behind the most advanced input mixing stays, so inputs delivering their
+/// samples in larger chunks still make it into the mix.
+const MIX_LATENCY: u64 = AUDIO_TIMESCALE as u64 / 5;
+
+/// Mixes every input down to a single 48kHz stereo track and encodes it to Opus.
+pub struct AudioEncoder {
+    encoder: Encoder,
+    inputs: Vec<Input>,
+    /// Mixed samples, interleaved, not yet encoded.
+    mix: VecDeque<f32>,
+    /// Timeline position of the first frame in `mix`, in samples.
+    mix_start: u64,
+    /// Timeline position of the next packet handed out, in samples.
+    next_pts: Option<u64>,
+    output: Vec<u8>,
+}
+
+struct Input {
+    format: AudioInputFormat,
+    /// Where the next sample from this input lands, in 48kHz samples.
+    next_position: Option<f64>,
+}
+
+impl AudioEncoder {
+    pub fn new(config: &EncoderConfig) -> Result<Self, CapError> {
+        let mut encoder = Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio)
+            .map_err(|e| encoder_error("Failed to create Opus encoder", e))?;
+        let bitrate = (config.quality.audio_bitrate_kbps * 1000) as i32;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(bitrate))
+            .map_err(|e| encoder_error("Failed to set Opus bitrate", e))?;
+
+        let inputs = config
+            .audio_inputs
+            .iter()
+            .map(|format| Input {
+                format: format.clone(),
+                next_position: None,
+            })
+            .collect();
+
+        Ok(Self {
+            encoder,
+            inputs,
+            mix: VecDeque::new(),
+            mix_start: 0,
+            next_pts: None,
+            output: vec![0; 4000],
+        })
+    }
+
+    pub fn track(&self) -> Result<AudioTrack, CapError> {
+        let lookahead = self
+            .encoder
+            .lookahead()
+            .map_err(|e| encoder_error("Failed to get Opus lookahead", e))?;
+
+        Ok(AudioTrack {
+            channels: CHANNELS as u16,
+            pre_skip: lookahead as u16,
+        })
+    }
+
+    /// Mixes in a chunk of samples from an input, returning whatever can be encoded.
+    pub fn encode(
+        &mut self,
+        index: usize,
+        data: &[u8],
+        position: Duration,
+    ) -> Result<Vec<(u64, Sample)>, CapError> {
+        let input = &mut self.inputs[index];
+        let frames = to_stereo(data, &input.format)?;
+        if frames.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let ratio = AUDIO_TIMESCALE as f64 / input.format.sample_rate as f64;
+
+        // Like with libav, samples are timed by how many came before them, anchored to
+        // when the first chunk was captured, which marks the end of that chunk
+        let start = *input.next_position.get_or_insert_with(|| {
+            let end = position.as_secs_f64() * AUDIO_TIMESCALE as f64;
+            (end - frames.len() as f64 * ratio).max(0.0)
+        });
+        let end = start + frames.len() as f64 * ratio;
+        input.next_position = Some(end);
+
+        // Linear interpolation is plenty for the rates audio devices run at
+        let sample_at = |offset: f64| -> [f32; CHANNELS] {
+            let index = offset.floor() as usize;
+            let fraction = offset.fract() as f32;
+            let a = frames[index.min(frames.len() - 1)];
+            let b = frames[(index + 1).min(frames.len() - 1)];
+            [0, 1].map(|c| a[c] + (b[c] - a[c]) * fraction)
+        };
+
+        let gain = input.format.gain;
+        let first = (start.ceil() as u64).max(self.mix_start);
+        for position in first..end.ceil() as u64 {
+            let offset = (position - self.mix_start) as usize * CHANNELS;
+            if self.mix.len() < offset + CHANNELS {
+                self.mix.resize(offset + CHANNELS, 0.0);
+            }
+
+            let frame = sample_at((position as f64 - start) / ratio);
+            for (channel, value) in frame.into_iter().enumerate() {
+                self.mix[offset + channel] += value * gain;
+            }
+        }
+
+        let most_advanced = self
+            .inputs
+            .iter()
+            .filter_map(|input| input.next_position)
+            .fold(0.0, f64::max) as u64;
+        self.encode_mix(most_advanced.saturating_sub(MIX_LATENCY))
+    }
+
+    /// Encodes what's left of the mix, padded to a whole frame.
+    pub fn finish(&mut self) -> Result<Vec<(u64, Sample)>, CapError> {
+        let end = self.mix_start + (self.mix.len() / CHANNELS) as u64;
+        let padded_end = end.div_ceil(FRAME_SIZE as u64) * FRAME_SIZE as u64;
+        self.encode_mix(padded_end)
+    }
+
+    fn encode_mix(&mut self, until: u64) -> Result<Vec<(u64, Sample)>, CapError> {
+        let mut packets = vec![];
+
+        while self.mix_start + FRAME_SIZE as u64 <= until {
+            let frame_len = FRAME_SIZE * CHANNELS;
+            if self.mix.len() < frame_len {
+                self.mix.resize(frame_len, 0.0);
+            }
+            let frame: Vec<f32> = self
+                .mix
+                .drain(..frame_len)
+                .map(|value| value.clamp(-1.0, 1.0))
+                .collect();
+
+            let size = self
+                .encoder
+                .encode_float(&frame, &mut self.output)
+                .map_err(|e| encoder_error("Failed to encode audio", e))?;
+
+            // The first packet starts wherever the first input did
+            let pts = *self.next_pts.get_or_insert(self.mix_start);
+            self.next_pts = Some(pts + FRAME_SIZE as u64);
+            packets.push((
+                pts,
+                Sample {
+                    data: self.output[..size].to_vec(),
+                    duration: FRAME_SIZE as u32,
+                    is_sync: true,
+                },
+            ));
+
+            self.mix_start += FRAME_SIZE as u64;
+        }
+
+        Ok(packets)
+    }
+}
+
+/// Converts raw samples to stereo frames, duplicating mono and dropping anything past
+/// the first two channels.
+fn to_stereo(data: &[u8], format: &AudioInputFormat) -> Result<Vec<[f32; CHANNELS]>, CapError> {
+    let samples: Vec<f32> = match format.sample_format.as_str() {
+        "s8" => data.iter().map(|s| *s as i8 as f32 / 128.0).collect(),
+        "u8" => data.iter().map(|s| (*s as f32 - 128.0) / 128.0).collect(),
+        "s16le" => data
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+            .collect(),
+        "u16le" => data
+            .chunks_exact(2)
+            .map(|s| (u16::from_le_bytes([s[0], s[1]]) as f32 - 32768.0) / 32768.0)
+            .collect(),
+        "s32le" => data
+            .chunks_exact(4)
+            .map(|s| i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        "u32le" => data
+            .chunks_exact(4)
+            .map(|s| {
+                (u32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f64 - 2_147_483_648.0) as f32
+                    / 2_147_483_648.0
+            })
+            .collect(),
+        "f32le" => data
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+            .collect(),
+        "f64le" => data
+            .chunks_exact(8)
+            .map(|s| f64::from_le_bytes(s.try_into().unwrap()) as f32)
+            .collect(),
+        other => {
+            return Err(CapError::new(
+                ErrorCode::Unsupported,
+                format!("Unsupported sample format {other}"),
+            ))
+        }
+    };
+
+    let channels = format.channels.max(1) as usize;
+    Ok(samples
+        .chunks_exact(channels)
+        .map(|frame| match frame {
+            [mono] => [*mono, *mono],
+            [left, right, ..] => [*left, *right],
+            [] => unreachable!(),
+        })
+        .collect())
+}
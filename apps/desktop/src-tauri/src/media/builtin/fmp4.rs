@@ -0,0 +1,472 @@
+//! Just enough of ISO BMFF to write CMAF init and media segments: one fragment per
+//! segment, with a track for H.264 video and an optional one for Opus audio.
+
+pub const VIDEO_TIMESCALE: u32 = 90_000;
+pub const AUDIO_TIMESCALE: u32 = 48_000;
+
+const VIDEO_TRACK_ID: u32 = 1;
+const AUDIO_TRACK_ID: u32 = 2;
+
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+pub struct VideoTrack {
+    pub width: u32,
+    pub height: u32,
+    pub sps: Vec<u8>,
+    pub pps: Vec<u8>,
+}
+
+pub struct AudioTrack {
+    pub channels: u16,
+    pub pre_skip: u16,
+}
+
+pub struct Sample {
+    pub data: Vec<u8>,
+    pub duration: u32,
+    pub is_sync: bool,
+}
+
+/// A track's samples for one fragment, starting at `decode_time` in its timescale.
+pub struct Run<'a> {
+    pub decode_time: u64,
+    pub samples: &'a [Sample],
+}
+
+pub fn init_segment(video: &VideoTrack, audio: Option<&AudioTrack>) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    write_box(&mut out, b"ftyp", |b| {
+        b.extend_from_slice(b"iso6");
+        put_u32(b, 0);
+        for brand in [b"iso6", b"cmfc", b"mp41"] {
+            b.extend_from_slice(brand);
+        }
+    });
+
+    write_box(&mut out, b"moov", |b| {
+        write_full_box(b, b"mvhd", 0, 0, |b| {
+            put_u32(b, 0); // creation time
+            put_u32(b, 0); // modification time
+            put_u32(b, 1000);
+            put_u32(b, 0); // duration, unknown up front
+            put_u32(b, 0x0001_0000); // rate
+            put_u16(b, 0x0100); // volume
+            b.extend_from_slice(&[0; 10]);
+            UNITY_MATRIX.iter().for_each(|value| put_u32(b, *value));
+            b.extend_from_slice(&[0; 24]);
+            put_u32(b, if audio.is_some() { 3 } else { 2 }); // next track ID
+        });
+
+        write_track(
+            b,
+            VIDEO_TRACK_ID,
+            VIDEO_TIMESCALE,
+            Some((video.width, video.height)),
+            |b| write_video_sample_entry(b, video),
+        );
+        if let Some(audio) = audio {
+            write_track(b, AUDIO_TRACK_ID, AUDIO_TIMESCALE, None, |b| {
+                write_audio_sample_entry(b, audio)
+            });
+        }
+
+        write_box(b, b"mvex", |b| {
+            for track_id in [Some(VIDEO_TRACK_ID), audio.map(|_| AUDIO_TRACK_ID)]
+                .into_iter()
+                .flatten()
+            {
+                write_full_box(b, b"trex", 0, 0, |b| {
+                    put_u32(b, track_id);
+                    put_u32(b, 1); // sample description index
+                    put_u32(b, 0); // default duration
+                    put_u32(b, 0); // default size
+                    put_u32(b, 0); // default flags
+                });
+            }
+        });
+    });
+
+    out
+}
+
+/// A `moof` + `mdat` pair holding a single fragment.
+pub fn media_segment(sequence_number: u32, video: Run, audio: Option<Run>) -> Vec<u8> {
+    let runs = [
+        Some((VIDEO_TRACK_ID, video)),
+        audio.map(|run| (AUDIO_TRACK_ID, run)),
+    ];
+    let runs: Vec<_> = runs.into_iter().flatten().collect();
+
+    let mut out = Vec::new();
+    let mut data_offset_positions = Vec::new();
+
+    write_box(&mut out, b"moof", |b| {
+        write_full_box(b, b"mfhd", 0, 0, |b| put_u32(b, sequence_number));
+
+        for (track_id, run) in &runs {
+            write_box(b, b"traf", |b| {
+                // Data offsets are relative to the start of the `moof` box
+                write_full_box(b, b"tfhd", 0, 0x02_0000, |b| put_u32(b, *track_id));
+                write_full_box(b, b"tfdt", 1, 0, |b| put_u64(b, run.decode_time));
+                // Data offset, and a duration, size and flags per sample
+                write_full_box(b, b"trun", 0, 0x0701, |b| {
+                    put_u32(b, run.samples.len() as u32);
+                    data_offset_positions.push(b.len());
+                    put_u32(b, 0);
+                    for sample in run.samples {
+                        put_u32(b, sample.duration);
+                        put_u32(b, sample.data.len() as u32);
+                        put_u32(
+                            b,
+                            match sample.is_sync {
+                                true => SYNC_SAMPLE_FLAGS,
+                                false => NON_SYNC_SAMPLE_FLAGS,
+                            },
+                        );
+                    }
+                });
+            });
+        }
+    });
+
+    // `moof` comes first, so the samples start right after it and `mdat`'s header
+    let mut data_offset = out.len() + 8;
+    for ((_, run), position) in runs.iter().zip(data_offset_positions) {
+        out[position..position + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        data_offset += run.samples.iter().map(|s| s.data.len()).sum::<usize>();
+    }
+
+    write_box(&mut out, b"mdat", |b| {
+        for (_, run) in &runs {
+            for sample in run.samples {
+                b.extend_from_slice(&sample.data);
+            }
+        }
+    });
+
+    out
+}
+
+fn write_track(
+    out: &mut Vec<u8>,
+    track_id: u32,
+    timescale: u32,
+    size: Option<(u32, u32)>,
+    sample_entry: impl FnOnce(&mut Vec<u8>),
+) {
+    let is_video = size.is_some();
+    let (width, height) = size.unwrap_or_default();
+
+    write_box(out, b"trak", |b| {
+        // Enabled, in movie and in preview
+        write_full_box(b, b"tkhd", 0, 0x7, |b| {
+            put_u32(b, 0); // creation time
+            put_u32(b, 0); // modification time
+            put_u32(b, track_id);
+            put_u32(b, 0);
+            put_u32(b, 0); // duration
+            b.extend_from_slice(&[0; 8]);
+            put_u16(b, 0); // layer
+            put_u16(b, 0); // alternate group
+            put_u16(b, if is_video { 0 } else { 0x0100 }); // volume
+            put_u16(b, 0);
+            UNITY_MATRIX.iter().for_each(|value| put_u32(b, *value));
+            put_u32(b, width << 16);
+            put_u32(b, height << 16);
+        });
+
+        write_box(b, b"mdia", |b| {
+            write_full_box(b, b"mdhd", 0, 0, |b| {
+                put_u32(b, 0); // creation time
+                put_u32(b, 0); // modification time
+                put_u32(b, timescale);
+                put_u32(b, 0); // duration
+                put_u16(b, 0x55c4); // "und"
+                put_u16(b, 0);
+            });
+
+            write_full_box(b, b"hdlr", 0, 0, |b| {
+                put_u32(b, 0);
+                b.extend_from_slice(if is_video { b"vide" } else { b"soun" });
+                b.extend_from_slice(&[0; 12]);
+                b.extend_from_slice(if is_video { b"Video\0" } else { b"Audio\0" });
+            });
+
+            write_box(b, b"minf", |b| {
+                if is_video {
+                    write_full_box(b, b"vmhd", 0, 1, |b| b.extend_from_slice(&[0; 8]));
+                } else {
+                    write_full_box(b, b"smhd", 0, 0, |b| b.extend_from_slice(&[0; 4]));
+                }
+
+                write_box(b, b"dinf", |b| {
+                    write_full_box(b, b"dref", 0, 0, |b| {
+                        put_u32(b, 1);
+                        // Media data is in the same file
+                        write_full_box(b, b"url ", 0, 1, |_| {});
+                    });
+                });
+
+                write_box(b, b"stbl", |b| {
+                    write_full_box(b, b"stsd", 0, 0, |b| {
+                        put_u32(b, 1);
+                        sample_entry(b);
+                    });
+                    // Samples are all described by the fragments
+                    write_full_box(b, b"stts", 0, 0, |b| put_u32(b, 0));
+                    write_full_box(b, b"stsc", 0, 0, |b| put_u32(b, 0));
+                    write_full_box(b, b"stsz", 0, 0, |b| put_u64(b, 0));
+                    write_full_box(b, b"stco", 0, 0, |b| put_u32(b, 0));
+                });
+            });
+        });
+    });
+}
+
+fn write_video_sample_entry(out: &mut Vec<u8>, video: &VideoTrack) {
+    write_box(out, b"avc1", |b| {
+        b.extend_from_slice(&[0; 6]);
+        put_u16(b, 1); // data reference index
+        b.extend_from_slice(&[0; 16]);
+        put_u16(b, video.width as u16);
+        put_u16(b, video.height as u16);
+        put_u32(b, 0x0048_0000); // 72 dpi
+        put_u32(b, 0x0048_0000);
+        put_u32(b, 0);
+        put_u16(b, 1); // frame count
+        b.extend_from_slice(&[0; 32]); // compressor name
+        put_u16(b, 0x0018); // depth
+        put_u16(b, 0xffff);
+
+        write_box(b, b"avcC", |b| {
+            b.push(1);
+            // Profile, compatibility and level, as in the SPS
+            b.extend_from_slice(&video.sps[1..4]);
+            b.push(0xff); // 4 byte NAL unit lengths
+            b.push(0xe1); // a single SPS
+            put_u16(b, video.sps.len() as u16);
+            b.extend_from_slice(&video.sps);
+            b.push(1);
+            put_u16(b, video.pps.len() as u16);
+            b.extend_from_slice(&video.pps);
+        });
+    });
+}
+
+fn write_audio_sample_entry(out: &mut Vec<u8>, audio: &AudioTrack) {
+    write_box(out, b"Opus", |b| {
+        b.extend_from_slice(&[0; 6]);
+        put_u16(b, 1); // data reference index
+        b.extend_from_slice(&[0; 8]);
+        put_u16(b, audio.channels);
+        put_u16(b, 16); // sample size
+        put_u32(b, 0);
+        put_u32(b, AUDIO_TIMESCALE << 16);
+
+        write_box(b, b"dOps", |b| {
+            b.push(0);
+            b.push(audio.channels as u8);
+            put_u16(b, audio.pre_skip);
+            put_u32(b, AUDIO_TIMESCALE);
+            put_u16(b, 0); // output gain
+            b.push(0); // mono or stereo, no mapping table
+        });
+    });
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    put_u32(out, 0);
+    out.extend_from_slice(kind);
+    content(out);
+
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |b| {
+        put_u32(b, ((version as u32) << 24) | flags);
+        content(b);
+    });
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The type and content of each box, in order.
+    fn boxes(data: &[u8]) -> Vec<(&str, &[u8])> {
+        let mut boxes = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind = std::str::from_utf8(&rest[4..8]).unwrap();
+            boxes.push((kind, &rest[8..size]));
+            rest = &rest[size..];
+        }
+        boxes
+    }
+
+    fn kinds<'a>(boxes: &[(&'a str, &[u8])]) -> Vec<&'a str> {
+        boxes.iter().map(|(kind, _)| *kind).collect()
+    }
+
+    fn child<'a>(data: &'a [u8], kind: &str) -> &'a [u8] {
+        boxes(data)
+            .into_iter()
+            .find(|(child, _)| *child == kind)
+            .map(|(_, content)| content)
+            .unwrap()
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn video_track() -> VideoTrack {
+        VideoTrack {
+            width: 1920,
+            height: 1080,
+            sps: vec![0x67, 0x42, 0xc0, 0x28, 0xda],
+            pps: vec![0x68, 0xce, 0x3c, 0x80],
+        }
+    }
+
+    fn sample(data: &[u8], is_sync: bool) -> Sample {
+        Sample {
+            data: data.to_vec(),
+            duration: 3000,
+            is_sync,
+        }
+    }
+
+    #[test]
+    fn lays_out_init_segment() {
+        let audio = AudioTrack {
+            channels: 2,
+            pre_skip: 312,
+        };
+        let segment = init_segment(&video_track(), Some(&audio));
+
+        let top = boxes(&segment);
+        assert_eq!(kinds(&top), ["ftyp", "moov"]);
+        assert_eq!(&top[0].1[..4], b"iso6");
+
+        let moov = boxes(top[1].1);
+        assert_eq!(kinds(&moov), ["mvhd", "trak", "trak", "mvex"]);
+
+        let mvex = boxes(moov[3].1);
+        assert_eq!(kinds(&mvex), ["trex", "trex"]);
+        // Version and flags, then the track ID
+        assert_eq!(u32_at(mvex[0].1, 4), VIDEO_TRACK_ID);
+        assert_eq!(u32_at(mvex[1].1, 4), AUDIO_TRACK_ID);
+
+        let stbl = child(child(child(moov[1].1, "mdia"), "minf"), "stbl");
+        assert_eq!(
+            kinds(&boxes(stbl)),
+            ["stsd", "stts", "stsc", "stsz", "stco"]
+        );
+        // Version and flags and the entry count come before the sample entry
+        let avc1 = child(&child(stbl, "stsd")[8..], "avc1");
+        let avcc = child(&avc1[78..], "avcC");
+        assert_eq!(&avcc[1..4], &video_track().sps[1..4]);
+        assert_eq!(&avcc[8..13], &video_track().sps[..]);
+        assert_eq!(&avcc[16..], &video_track().pps[..]);
+    }
+
+    #[test]
+    fn leaves_out_missing_audio_track() {
+        let segment = init_segment(&video_track(), None);
+
+        let moov = boxes(child(&segment, "moov"));
+        assert_eq!(kinds(&moov), ["mvhd", "trak", "mvex"]);
+        assert_eq!(kinds(&boxes(moov[2].1)), ["trex"]);
+    }
+
+    #[test]
+    fn points_runs_at_their_samples() {
+        let video = [sample(b"key", true), sample(b"delta", false)];
+        let audio = [sample(b"opus", true)];
+        let segment = media_segment(
+            7,
+            Run {
+                decode_time: 90_000,
+                samples: &video,
+            },
+            Some(Run {
+                decode_time: 48_000,
+                samples: &audio,
+            }),
+        );
+
+        let top = boxes(&segment);
+        assert_eq!(kinds(&top), ["moof", "mdat"]);
+        assert_eq!(top[1].1, b"keydeltaopus");
+
+        let moof = boxes(top[0].1);
+        assert_eq!(kinds(&moof), ["mfhd", "traf", "traf"]);
+        assert_eq!(u32_at(moof[0].1, 4), 7);
+
+        // The mdat's content starts after the moof and its own header
+        let mdat_start = top[0].1.len() + 16;
+        let expected = [
+            (
+                VIDEO_TRACK_ID,
+                90_000,
+                mdat_start,
+                vec![(3, true), (5, false)],
+            ),
+            (AUDIO_TRACK_ID, 48_000, mdat_start + 8, vec![(4, true)]),
+        ];
+        for ((_, traf), (track_id, decode_time, data_offset, samples)) in
+            moof[1..].iter().zip(expected)
+        {
+            let traf = boxes(traf);
+            assert_eq!(kinds(&traf), ["tfhd", "tfdt", "trun"]);
+            assert_eq!(u32_at(traf[0].1, 4), track_id);
+            assert_eq!(
+                u64::from_be_bytes(traf[1].1[4..12].try_into().unwrap()),
+                decode_time
+            );
+
+            let trun = traf[2].1;
+            assert_eq!(u32_at(trun, 4) as usize, samples.len());
+            assert_eq!(u32_at(trun, 8) as usize, data_offset);
+            for (index, (size, is_sync)) in samples.into_iter().enumerate() {
+                let entry = 12 + index * 12;
+                assert_eq!(u32_at(trun, entry), 3000);
+                assert_eq!(u32_at(trun, entry + 4), size);
+                assert_eq!(
+                    u32_at(trun, entry + 8),
+                    match is_sync {
+                        true => SYNC_SAMPLE_FLAGS,
+                        false => NON_SYNC_SAMPLE_FLAGS,
+                    }
+                );
+            }
+        }
+    }
+}
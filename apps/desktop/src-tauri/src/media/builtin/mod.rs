@@ -0,0 +1,211 @@
+//! The encoder used when FFmpeg couldn't be installed: H.264 through openh264 and Opus,
+//! muxed into fMP4 HLS segments by hand. It's slower than FFmpeg and always mixes the
+//! audio inputs together, but the recording ends up in the same place and shape.
+
+use std::{fmt::Write, path::PathBuf, sync::mpsc::Receiver};
+
+use super::encoder::{EncoderConfig, EncoderMessage, EncoderSession};
+use super::sync::Timeline;
+use super::{hls, HlsSegmentType};
+use crate::error::{CapError, ErrorCode};
+
+mod audio;
+mod fmp4;
+mod video;
+
+use audio::AudioEncoder;
+use fmp4::{Run, Sample, VIDEO_TIMESCALE};
+use video::VideoEncoder;
+
+pub struct Session {
+    recording_dir: PathBuf,
+    fps: u32,
+    video: VideoEncoder,
+    audio: Option<AudioEncoder>,
+    timeline: Timeline,
+    segment: Segment,
+    /// Names and durations of the segments written so far.
+    segments: Vec<(String, f64)>,
+}
+
+/// Samples waiting to be written out with the next keyframe.
+#[derive(Default)]
+struct Segment {
+    video_start: u64,
+    video: Vec<Sample>,
+    audio_start: u64,
+    audio: Vec<Sample>,
+}
+
+impl EncoderSession for Session {
+    fn open(config: EncoderConfig) -> Result<Self, CapError> {
+        let video = VideoEncoder::new(&config)?;
+        let audio = match config.audio_inputs.is_empty() {
+            true => None,
+            false => Some(AudioEncoder::new(&config)?),
+        };
+
+        Ok(Self {
+            recording_dir: config.recording_dir,
+            fps: config.quality.fps,
+            video,
+            audio,
+            timeline: Timeline::default(),
+            segment: Segment::default(),
+            segments: vec![],
+        })
+    }
+
+    fn run(mut self, receiver: Receiver<EncoderMessage>) -> Result<(), CapError> {
+        while let Ok(message) = receiver.recv() {
+            match message {
+                EncoderMessage::Video(frame) => {
+                    let Some(position) = self.timeline.video_position(frame.captured_at) else {
+                        continue;
+                    };
+                    if let Some((pts, sample)) = self.video.encode(&frame.data, position)? {
+                        self.push_video(pts, sample)?;
+                    }
+                }
+                EncoderMessage::Audio(input, samples) => {
+                    let Some(audio) = &mut self.audio else {
+                        continue;
+                    };
+                    let Some(position) = self.timeline.position(samples.captured_at) else {
+                        continue;
+                    };
+                    for (pts, sample) in audio.encode(input, &samples.data, position)? {
+                        self.push_audio(pts, sample);
+                    }
+                }
+                EncoderMessage::Pause(instant) => self.timeline.pause(instant),
+                EncoderMessage::Resume(instant) => self.timeline.resume(instant),
+            }
+        }
+
+        if let Some((pts, sample)) = self.video.finish(self.fps) {
+            self.push_video(pts, sample)?;
+        }
+        if let Some(audio) = &mut self.audio {
+            for (pts, sample) in audio.finish()? {
+                self.push_audio(pts, sample);
+            }
+        }
+        self.write_segment()?;
+
+        self.write_playlist(true)
+    }
+}
+
+impl Session {
+    /// Every keyframe starts a new segment.
+    fn push_video(&mut self, pts: u64, sample: Sample) -> Result<(), CapError> {
+        if sample.is_sync && !self.segment.video.is_empty() {
+            self.write_segment()?;
+        }
+
+        if self.segment.video.is_empty() {
+            self.segment.video_start = pts;
+        }
+        self.segment.video.push(sample);
+
+        Ok(())
+    }
+
+    fn push_audio(&mut self, pts: u64, sample: Sample) {
+        if self.segment.audio.is_empty() {
+            self.segment.audio_start = pts;
+        }
+        self.segment.audio.push(sample);
+    }
+
+    fn write_segment(&mut self) -> Result<(), CapError> {
+        let segment = std::mem::take(&mut self.segment);
+        if segment.video.is_empty() {
+            return Ok(());
+        }
+
+        // The parameter sets only exist once the first keyframe has been encoded
+        if self.segments.is_empty() {
+            let video_track = self.video.track().ok_or_else(|| {
+                CapError::new(
+                    ErrorCode::FfmpegCrashed,
+                    "The H.264 encoder didn't produce any parameter sets",
+                )
+            })?;
+            let audio_track = self.audio.as_ref().map(|audio| audio.track()).transpose()?;
+
+            self.write_file(
+                &hls::init_segment_name(None),
+                &fmp4::init_segment(&video_track, audio_track.as_ref()),
+            )?;
+        }
+
+        let index = self.segments.len();
+        let data = fmp4::media_segment(
+            index as u32 + 1,
+            Run {
+                decode_time: segment.video_start,
+                samples: &segment.video,
+            },
+            (!segment.audio.is_empty()).then_some(Run {
+                decode_time: segment.audio_start,
+                samples: &segment.audio,
+            }),
+        );
+
+        let name = hls::segment_name(None, HlsSegmentType::Fmp4, index);
+        self.write_file(&name, &data)?;
+
+        let duration =
+            segment.video.iter().map(|s| s.duration as f64).sum::<f64>() / VIDEO_TIMESCALE as f64;
+        self.segments.push((name, duration));
+
+        self.write_playlist(false)
+    }
+
+    /// Rewritten after every segment, and marked as complete once the recording is.
+    fn write_playlist(&self, is_complete: bool) -> Result<(), CapError> {
+        let target_duration = self
+            .segments
+            .iter()
+            .map(|(_, duration)| duration.ceil() as u32)
+            .max()
+            .unwrap_or(1);
+
+        let mut playlist = String::new();
+        writeln!(playlist, "#EXTM3U").ok();
+        writeln!(playlist, "#EXT-X-VERSION:7").ok();
+        writeln!(playlist, "#EXT-X-TARGETDURATION:{target_duration}").ok();
+        writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:0").ok();
+        if is_complete {
+            writeln!(playlist, "#EXT-X-PLAYLIST-TYPE:VOD").ok();
+        }
+        writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS").ok();
+        writeln!(
+            playlist,
+            "#EXT-X-MAP:URI=\"{}\"",
+            hls::init_segment_name(None)
+        )
+        .ok();
+        for (name, duration) in &self.segments {
+            writeln!(playlist, "#EXTINF:{duration:.6},\n{name}").ok();
+        }
+        if is_complete {
+            writeln!(playlist, "#EXT-X-ENDLIST").ok();
+        }
+
+        self.write_file(&hls::playlist_name(None), playlist.as_bytes())
+    }
+
+    /// Writes under a temporary name first, like FFmpeg's `temp_file` flag, so the
+    /// upload loop never picks up a half-written file.
+    fn write_file(&self, name: &str, data: &[u8]) -> Result<(), CapError> {
+        let path = self.recording_dir.join(name);
+        let temp_path = self.recording_dir.join(format!("{name}.tmp"));
+
+        std::fs::write(&temp_path, data)
+            .and_then(|_| std::fs::rename(&temp_path, &path))
+            .map_err(|e| CapError::io(format!("Failed to write {name}"), &e))
+    }
+}
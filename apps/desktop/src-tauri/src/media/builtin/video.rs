@@ -0,0 +1,215 @@
+use openh264::{
+    encoder::{
+        BitRate, Encoder, EncoderConfig as H264Config, FrameRate, FrameType, RateControlMode,
+    },
+    formats::YUVSlices,
+    OpenH264API,
+};
+use std::time::Duration;
+
+use super::fmp4::{Sample, VideoTrack, VIDEO_TIMESCALE};
+use crate::error::CapError;
+use crate::media::convert::{FrameConverter, PixelFormat};
+use crate::media::encoder::{encoder_error, EncoderConfig};
+use crate::media::quality::RateControl;
+
+pub struct VideoEncoder {
+    encoder: Encoder,
+    converter: FrameConverter,
+    /// Reused for every frame, as it's the size of the whole I420 frame.
+    yuv: Vec<u8>,
+    width: u32,
+    height: u32,
+    keyframe_interval: u64,
+    next_keyframe: u64,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    /// Held back until the next frame arrives, as that's what decides its duration.
+    pending: Option<(u64, Sample)>,
+}
+
+impl VideoEncoder {
+    pub fn new(config: &EncoderConfig) -> Result<Self, CapError> {
+        let quality = &config.quality;
+
+        // openh264 has no constant quality mode, so CRF is turned into a bitrate the
+        // way x264 roughly ends up spending it on screen content
+        let bitrate_bps = match quality.rate_control {
+            RateControl::Bitrate { kbps } => kbps * 1000,
+            RateControl::Crf { value } => {
+                let pixels_per_second = config.output_width * config.output_height * quality.fps;
+                let bits_per_pixel = 0.1 * 2f64.powf((23.0 - value as f64) / 6.0);
+                (pixels_per_second as f64 * bits_per_pixel) as u32
+            }
+        };
+
+        let h264_config = H264Config::new()
+            .bitrate(BitRate::from_bps(bitrate_bps))
+            .max_frame_rate(FrameRate::from_hz(quality.fps as f32))
+            .rate_control_mode(RateControlMode::Bitrate)
+            .skip_frames(false);
+        let encoder = Encoder::with_api_config(OpenH264API::from_source(), h264_config)
+            .map_err(|e| encoder_error("Failed to create H.264 encoder", e))?;
+
+        Ok(Self {
+            encoder,
+            converter: FrameConverter::new(
+                PixelFormat::I420,
+                config.frame_width,
+                config.frame_height,
+                config.output_width,
+                config.output_height,
+            ),
+            yuv: vec![],
+            width: config.output_width,
+            height: config.output_height,
+            keyframe_interval: quality.keyframe_interval_secs as u64 * VIDEO_TIMESCALE as u64,
+            next_keyframe: 0,
+            sps: None,
+            pps: None,
+            pending: None,
+        })
+    }
+
+    /// Available once the first keyframe has been encoded.
+    pub fn track(&self) -> Option<VideoTrack> {
+        Some(VideoTrack {
+            width: self.width,
+            height: self.height,
+            sps: self.sps.clone()?,
+            pps: self.pps.clone()?,
+        })
+    }
+
+    /// Encodes a BGRA frame, returning the previous one once its duration is known.
+    pub fn encode(
+        &mut self,
+        bgra: &[u8],
+        position: Duration,
+    ) -> Result<Option<(u64, Sample)>, CapError> {
+        let pts = position.as_micros() as u64 * VIDEO_TIMESCALE as u64 / 1_000_000;
+        if matches!(&self.pending, Some((last_pts, _)) if pts <= *last_pts) {
+            return Ok(None);
+        }
+
+        // Segments are cut at keyframes, so they have to come at the keyframe interval
+        // no matter what the encoder would pick
+        if pts >= self.next_keyframe {
+            self.encoder.force_intra_frame();
+            self.next_keyframe = pts - pts % self.keyframe_interval + self.keyframe_interval;
+        }
+
+        self.converter.convert(bgra, &mut self.yuv);
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (y, chroma) = self.yuv.split_at(width * height);
+        let (u, v) = chroma.split_at(chroma.len() / 2);
+        let yuv = YUVSlices::new((y, u, v), (width, height), (width, width / 2, width / 2));
+        let bitstream = self
+            .encoder
+            .encode(&yuv)
+            .map_err(|e| encoder_error("Failed to encode video", e))?;
+
+        let is_sync = matches!(bitstream.frame_type(), FrameType::IDR | FrameType::I);
+        let annex_b = bitstream.to_vec();
+        if annex_b.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = Vec::with_capacity(annex_b.len());
+        for nal in split_annex_b(&annex_b) {
+            match nal[0] & 0x1f {
+                // Parameter sets go in the init segment instead
+                7 => self.sps = Some(nal.to_vec()),
+                8 => self.pps = Some(nal.to_vec()),
+                _ => {
+                    data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    data.extend_from_slice(nal);
+                }
+            }
+        }
+
+        let sample = Sample {
+            data,
+            duration: 0,
+            is_sync,
+        };
+        Ok(self
+            .pending
+            .replace((pts, sample))
+            .map(|(last_pts, mut last)| {
+                last.duration = (pts - last_pts) as u32;
+                (last_pts, last)
+            }))
+    }
+
+    /// The last frame, shown for a frame's worth of time.
+    pub fn finish(&mut self, fps: u32) -> Option<(u64, Sample)> {
+        self.pending.take().map(|(pts, mut sample)| {
+            sample.duration = VIDEO_TIMESCALE / fps;
+            (pts, sample)
+        })
+    }
+}
+
+/// Splits an Annex B byte stream on its start codes.
+fn split_annex_b(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|start| {
+            // A 4 byte start code leaves a zero behind
+            let end = start - 3;
+            if end > 0 && data[end - 1] == 0 {
+                end - 1
+            } else {
+                end
+            }
+        })
+        .chain([data.len()])
+        .collect();
+
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(move |(start, end)| &data[start..end])
+        .filter(|nal| !nal.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_both_start_code_lengths() {
+        let data = [
+            0, 0, 0, 1, 0x67, 1, 2, // SPS after a 4 byte start code
+            0, 0, 1, 0x68, 3, // PPS after a 3 byte one
+            0, 0, 0, 1, 0x65, 4, 0, 5, // a slice with a zero in it
+        ];
+
+        let nals: Vec<&[u8]> = split_annex_b(&data).collect();
+        assert_eq!(
+            nals,
+            [&[0x67, 1, 2][..], &[0x68, 3][..], &[0x65, 4, 0, 5][..]]
+        );
+    }
+
+    #[test]
+    fn skips_empty_units() {
+        let data = [0, 0, 1, 0, 0, 1, 0x41, 7];
+
+        let nals: Vec<&[u8]> = split_annex_b(&data).collect();
+        assert_eq!(nals, [&[0x41, 7][..]]);
+    }
+}
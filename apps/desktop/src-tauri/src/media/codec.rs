@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, process::Command, sync::OnceLock};
+
+use super::convert::PixelFormat;
+use super::quality::{EncoderPreset, RateControl, RecordingQuality};
+use crate::error::{CapError, ErrorCode};
+use crate::utils::ffmpeg_path_as_str;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    Hevc,
+    Vp9,
+    Av1,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum HlsSegmentType {
+    MpegTs,
+    Fmp4,
+}
+
+impl HlsSegmentType {
+    pub fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            HlsSegmentType::MpegTs => "mpegts",
+            HlsSegmentType::Fmp4 => "fmp4",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HlsSegmentType::MpegTs => "ts",
+            HlsSegmentType::Fmp4 => "m4s",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, specta::Type)]
+pub struct VideoCodecInfo {
+    pub codec: VideoCodec,
+    pub encoder: String,
+    pub segment_types: Vec<HlsSegmentType>,
+}
+
+impl VideoCodec {
+    const ALL: [VideoCodec; 4] = [
+        VideoCodec::H264,
+        VideoCodec::Hevc,
+        VideoCodec::Vp9,
+        VideoCodec::Av1,
+    ];
+
+    /// FFmpeg encoders able to produce this codec, in order of preference.
+    fn encoder_candidates(&self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &["libx264"],
+            VideoCodec::Hevc => &["libx265"],
+            VideoCodec::Vp9 => &["libvpx-vp9"],
+            VideoCodec::Av1 => &["libsvtav1", "librav1e"],
+        }
+    }
+
+    /// HLS only allows H.264 in MPEG-TS segments; everything else has to go in fMP4.
+    pub fn segment_types(&self) -> &'static [HlsSegmentType] {
+        match self {
+            VideoCodec::H264 => &[HlsSegmentType::MpegTs, HlsSegmentType::Fmp4],
+            VideoCodec::Hevc | VideoCodec::Vp9 | VideoCodec::Av1 => &[HlsSegmentType::Fmp4],
+        }
+    }
+
+    pub fn default_segment_type(&self) -> HlsSegmentType {
+        self.segment_types()[0]
+    }
+
+    /// The first encoder for this codec that the installed FFmpeg build was compiled with.
+    pub fn encoder(&self) -> Option<&'static str> {
+        let available = available_encoders();
+
+        self.encoder_candidates()
+            .iter()
+            .find(|encoder| available.contains(**encoder))
+            .copied()
+    }
+
+    /// What frames are converted to before they're piped to FFmpeg. x264 works on NV12
+    /// internally, while the other encoders take planar I420.
+    pub fn input_format(&self) -> PixelFormat {
+        match self.encoder() {
+            Some("libx264") => PixelFormat::Nv12,
+            _ => PixelFormat::I420,
+        }
+    }
+
+    pub fn encoder_args(&self, quality: &RecordingQuality) -> Result<Vec<String>, CapError> {
+        let encoder = self.encoder().ok_or_else(|| {
+            CapError::new(
+                ErrorCode::Unsupported,
+                format!("The installed FFmpeg can't encode {self:?}"),
+            )
+        })?;
+
+        let mut args = vec!["-codec:v".to_string(), encoder.to_string()];
+
+        match encoder {
+            "libx264" => {
+                args.extend(
+                    ["-preset", quality.preset.as_str(), "-tune", "zerolatency"].map(String::from),
+                );
+                args.extend(quality.video_rate_args());
+            }
+            "libx265" => {
+                args.extend(
+                    ["-preset", quality.preset.as_str(), "-tag:v", "hvc1"].map(String::from),
+                );
+                args.extend(["-x265-params", "log-level=error"].map(String::from));
+                args.extend(quality.video_rate_args());
+            }
+            "libvpx-vp9" => {
+                let cpu_used = match quality.preset {
+                    EncoderPreset::Ultrafast => 8,
+                    EncoderPreset::Superfast => 7,
+                    EncoderPreset::Veryfast => 6,
+                    EncoderPreset::Faster => 5,
+                    EncoderPreset::Fast | EncoderPreset::Medium => 4,
+                };
+                args.extend(["-deadline", "realtime", "-row-mt", "1"].map(String::from));
+                args.extend(["-cpu-used".to_string(), cpu_used.to_string()]);
+                args.extend(quality.video_rate_args());
+                if let RateControl::Crf { .. } = quality.rate_control {
+                    // Constant quality mode in libvpx requires the bitrate to be unbounded
+                    args.extend(["-b:v", "0"].map(String::from));
+                }
+            }
+            "libsvtav1" => {
+                let preset = match quality.preset {
+                    EncoderPreset::Ultrafast => 12,
+                    EncoderPreset::Superfast => 11,
+                    EncoderPreset::Veryfast => 10,
+                    EncoderPreset::Faster => 9,
+                    EncoderPreset::Fast => 8,
+                    EncoderPreset::Medium => 6,
+                };
+                args.extend(["-preset".to_string(), preset.to_string()]);
+                args.extend(quality.video_rate_args());
+            }
+            "librav1e" => {
+                let speed = match quality.preset {
+                    EncoderPreset::Ultrafast => 10,
+                    EncoderPreset::Superfast => 9,
+                    EncoderPreset::Veryfast => 8,
+                    EncoderPreset::Faster => 7,
+                    EncoderPreset::Fast => 6,
+                    EncoderPreset::Medium => 5,
+                };
+                args.extend(["-speed".to_string(), speed.to_string()]);
+                match quality.rate_control {
+                    // rav1e's quantizer goes up to 255 instead of 51
+                    RateControl::Crf { value } => {
+                        args.extend(["-qp".to_string(), (value as u32 * 5).to_string()])
+                    }
+                    RateControl::Bitrate { .. } => args.extend(quality.video_rate_args()),
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(args)
+    }
+}
+
+pub fn available_codecs() -> Vec<VideoCodecInfo> {
+    VideoCodec::ALL
+        .into_iter()
+        .filter_map(|codec| {
+            codec.encoder().map(|encoder| VideoCodecInfo {
+                codec,
+                encoder: encoder.to_string(),
+                segment_types: codec.segment_types().to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// Video encoders listed by `ffmpeg -encoders`. Probed once, as the sidecar binary
+/// doesn't change while the app is running.
+fn available_encoders() -> &'static HashSet<String> {
+    static ENCODERS: OnceLock<HashSet<String>> = OnceLock::new();
+
+    ENCODERS.get_or_init(|| {
+        let output = ffmpeg_path_as_str().and_then(|ffmpeg| {
+            Command::new(ffmpeg)
+                .args(["-hide_banner", "-encoders"])
+                .output()
+                .map_err(|e| e.to_string())
+        });
+
+        match output {
+            Ok(output) => parse_encoders(&String::from_utf8_lossy(&output.stdout)),
+            Err(error) => {
+                tracing::error!("Failed to list FFmpeg encoders: {error}");
+                HashSet::new()
+            }
+        }
+    })
+}
+
+/// Encoder lines look like ` V....D libx264    libx264 H.264 / AVC ...`, and come
+/// after a legend that ends with a `------` separator.
+fn parse_encoders(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let flags = columns.next()?;
+            let name = columns.next()?;
+            flags.starts_with('V').then(|| name.to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,667 @@
+//! Converts captured BGRA frames to the 4:2:0 YUV encoders actually take, at the size
+//! they're encoded at, so FFmpeg gets 2.67 times less data and doesn't have to spend a
+//! core converting it. Colours are limited range BT.601, like FFmpeg's `scale` filter
+//! produces by default, and chroma is the average of each 2x2 block of pixels.
+
+/// Both are 4:2:0, with the chroma planes either separate or interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    I420,
+    Nv12,
+}
+
+impl PixelFormat {
+    pub fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            PixelFormat::I420 => "yuv420p",
+            PixelFormat::Nv12 => "nv12",
+        }
+    }
+
+    /// What Matroska identifies raw video in this format by.
+    pub fn fourcc(&self) -> &'static [u8; 4] {
+        match self {
+            PixelFormat::I420 => b"I420",
+            PixelFormat::Nv12 => b"NV12",
+        }
+    }
+
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        width * height + 2 * (width / 2) * (height / 2)
+    }
+}
+
+pub struct FrameConverter {
+    format: PixelFormat,
+    in_height: usize,
+    width: usize,
+    height: usize,
+    /// Source pixels and weights of every output column and row, when scaling.
+    columns: Vec<Tap>,
+    rows: Vec<Tap>,
+    /// The frame scaled to the output size.
+    scaled: Vec<u8>,
+    /// A row of each chroma plane, to interleave for NV12.
+    u_row: Vec<u8>,
+    v_row: Vec<u8>,
+}
+
+/// Bilinear interpolation between two source pixels, with the weight of the second in
+/// 1/256ths.
+#[derive(Clone, Copy)]
+struct Tap {
+    first: usize,
+    second: usize,
+    weight: u32,
+}
+
+impl FrameConverter {
+    /// The output size has to be even, which `RecordingQuality::output_size` makes sure of.
+    pub fn new(
+        format: PixelFormat,
+        in_width: u32,
+        in_height: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let (in_width, in_height) = (in_width as usize, in_height as usize);
+        let (width, height) = (width as usize & !1, height as usize & !1);
+        let needs_scaling = (in_width, in_height) != (width, height);
+
+        Self {
+            format,
+            in_height,
+            width,
+            height,
+            columns: match needs_scaling {
+                true => taps(in_width, width),
+                false => vec![],
+            },
+            rows: match needs_scaling {
+                true => taps(in_height, height),
+                false => vec![],
+            },
+            scaled: vec![],
+            u_row: vec![0; width / 2],
+            v_row: vec![0; width / 2],
+        }
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.format
+            .frame_size(self.width as u32, self.height as u32)
+    }
+
+    /// Converts a BGRA frame of the input size, whose rows may be padded.
+    pub fn convert(&mut self, bgra: &[u8], output: &mut Vec<u8>) {
+        output.clear();
+        output.resize(self.frame_size(), 0);
+
+        let Self {
+            format,
+            in_height,
+            width,
+            height,
+            columns,
+            rows,
+            scaled,
+            u_row,
+            v_row,
+        } = self;
+        let (width, height) = (*width, *height);
+        if width == 0 || height == 0 || *in_height == 0 {
+            return;
+        }
+
+        let stride = bgra.len() / *in_height;
+        let (source, stride) = match columns.is_empty() {
+            true => (bgra, stride),
+            false => {
+                scale(bgra, stride, columns, rows, scaled);
+                (&scaled[..], width * 4)
+            }
+        };
+
+        let (luma, chroma) = output.split_at_mut(width * height);
+        let chroma_width = width / 2;
+        let plane_size = chroma_width * (height / 2);
+
+        for row in 0..height / 2 {
+            let top = &source[row * 2 * stride..][..width * 4];
+            let bottom = &source[(row * 2 + 1) * stride..][..width * 4];
+            let (y_top, y_bottom) = luma[row * 2 * width..][..width * 2].split_at_mut(width);
+
+            match format {
+                PixelFormat::I420 => {
+                    let (u_plane, v_plane) = chroma.split_at_mut(plane_size);
+                    let u = &mut u_plane[row * chroma_width..][..chroma_width];
+                    let v = &mut v_plane[row * chroma_width..][..chroma_width];
+                    convert_rows(top, bottom, y_top, y_bottom, u, v);
+                }
+                PixelFormat::Nv12 => {
+                    convert_rows(top, bottom, y_top, y_bottom, u_row, v_row);
+                    // The chroma planes are one plane of twice the width
+                    let uv = &mut chroma[row * width..][..width];
+                    for (pair, (u, v)) in uv.chunks_exact_mut(2).zip(u_row.iter().zip(&*v_row)) {
+                        pair[0] = *u;
+                        pair[1] = *v;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Where each output pixel's centre falls between the input pixels.
+fn taps(input: usize, output: usize) -> Vec<Tap> {
+    (0..output)
+        .map(|index| {
+            let position = ((index as f64 + 0.5) * input as f64 / output as f64 - 0.5).max(0.0);
+            let first = (position as usize).min(input - 1);
+            Tap {
+                first,
+                second: (first + 1).min(input - 1),
+                weight: ((position - first as f64) * 256.0) as u32,
+            }
+        })
+        .collect()
+}
+
+fn scale(bgra: &[u8], stride: usize, columns: &[Tap], rows: &[Tap], output: &mut Vec<u8>) {
+    output.clear();
+    output.resize(columns.len() * rows.len() * 4, 0);
+
+    for (row, pixels) in rows.iter().zip(output.chunks_exact_mut(columns.len() * 4)) {
+        let top = &bgra[row.first * stride..];
+        let bottom = &bgra[row.second * stride..];
+
+        for (column, pixel) in columns.iter().zip(pixels.chunks_exact_mut(4)) {
+            for channel in 0..3 {
+                let lerp = |line: &[u8]| {
+                    let first = line[column.first * 4 + channel] as u32;
+                    let second = line[column.second * 4 + channel] as u32;
+                    first * (256 - column.weight) + second * column.weight
+                };
+                let value = lerp(top) * (256 - row.weight) + lerp(bottom) * row.weight;
+                pixel[channel] = ((value + 32768) >> 16) as u8;
+            }
+            pixel[3] = 255;
+        }
+    }
+}
+
+/// Converts a pair of rows, as that's what every row of chroma comes from.
+fn convert_rows(
+    top: &[u8],
+    bottom: &[u8],
+    y_top: &mut [u8],
+    y_bottom: &mut [u8],
+    u: &mut [u8],
+    v: &mut [u8],
+) {
+    let done = simd::convert_rows(top, bottom, y_top, y_bottom, u, v);
+
+    scalar::convert_rows(
+        &top[done * 4..],
+        &bottom[done * 4..],
+        &mut y_top[done..],
+        &mut y_bottom[done..],
+        &mut u[done / 2..],
+        &mut v[done / 2..],
+    );
+}
+
+/// The reference the SIMD versions have to match exactly, and what takes care of
+/// whatever's left of a row after them.
+mod scalar {
+    pub fn convert_rows(
+        top: &[u8],
+        bottom: &[u8],
+        y_top: &mut [u8],
+        y_bottom: &mut [u8],
+        u: &mut [u8],
+        v: &mut [u8],
+    ) {
+        for x in 0..u.len() {
+            for pixel in [x * 2, x * 2 + 1] {
+                y_top[pixel] = luma(&top[pixel * 4..]);
+                y_bottom[pixel] = luma(&bottom[pixel * 4..]);
+            }
+
+            // Rows are averaged (rounding up) before columns are summed, like the
+            // SIMD versions do
+            let average = |pixel: usize, channel: usize| {
+                let index = pixel * 4 + channel;
+                (top[index] as i32 + bottom[index] as i32 + 1) >> 1
+            };
+            let [b, g, r] = [0, 1, 2].map(|c| average(x * 2, c) + average(x * 2 + 1, c));
+
+            u[x] = chroma(112 * b - 74 * g - 38 * r);
+            v[x] = chroma(112 * r - 94 * g - 18 * b);
+        }
+    }
+
+    fn luma(pixel: &[u8]) -> u8 {
+        let [b, g, r] = [pixel[0], pixel[1], pixel[2]].map(|c| c as i32);
+        (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8
+    }
+
+    /// From the sum of two pixels' weighted channels.
+    fn chroma(sum: i32) -> u8 {
+        (((sum + 256) >> 9) + 128).clamp(0, 255) as u8
+    }
+}
+
+/// SSE2 is part of x86_64 itself, so it's always there.
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use std::arch::x86_64::*;
+
+    /// Converts 16 pixels at a time, returning how many were converted.
+    pub fn convert_rows(
+        top: &[u8],
+        bottom: &[u8],
+        y_top: &mut [u8],
+        y_bottom: &mut [u8],
+        u: &mut [u8],
+        v: &mut [u8],
+    ) -> usize {
+        let blocks = y_top.len().min(u.len() * 2) / 16;
+
+        // SAFETY: every block reads 64 bytes of both rows and writes 16 bytes of luma and
+        // 8 bytes of chroma, which the number of blocks keeps within the slices
+        unsafe {
+            for block in 0..blocks {
+                let top = load(&top[block * 64..block * 64 + 64]);
+                let bottom = load(&bottom[block * 64..block * 64 + 64]);
+
+                _mm_storeu_si128(y_top[block * 16..].as_mut_ptr() as *mut __m128i, luma(top));
+                _mm_storeu_si128(
+                    y_bottom[block * 16..].as_mut_ptr() as *mut __m128i,
+                    luma(bottom),
+                );
+
+                let average = [0, 1, 2, 3].map(|i| _mm_avg_epu8(top[i], bottom[i]));
+                let sums = [
+                    pair_sums(average[0], average[1]),
+                    pair_sums(average[2], average[3]),
+                ];
+
+                let u_values = chroma(sums, _mm_setr_epi16(112, -74, -38, 0, 112, -74, -38, 0));
+                let v_values = chroma(sums, _mm_setr_epi16(-18, -94, 112, 0, -18, -94, 112, 0));
+                _mm_storel_epi64(u[block * 8..].as_mut_ptr() as *mut __m128i, u_values);
+                _mm_storel_epi64(v[block * 8..].as_mut_ptr() as *mut __m128i, v_values);
+            }
+        }
+
+        blocks * 16
+    }
+
+    unsafe fn load(pixels: &[u8]) -> [__m128i; 4] {
+        [0, 1, 2, 3].map(|i| _mm_loadu_si128(pixels[i * 16..].as_ptr() as *const __m128i))
+    }
+
+    /// Adds up the pairs of 32 bit lanes of two vectors, as SSE2 has no horizontal add.
+    unsafe fn add_pairs(a: __m128i, b: __m128i) -> __m128i {
+        let (a, b) = (_mm_castsi128_ps(a), _mm_castsi128_ps(b));
+        let evens = _mm_castps_si128(_mm_shuffle_ps(a, b, 0b10_00_10_00));
+        let odds = _mm_castps_si128(_mm_shuffle_ps(a, b, 0b11_01_11_01));
+        _mm_add_epi32(evens, odds)
+    }
+
+    /// 16 BGRA pixels to 16 luma values.
+    unsafe fn luma(pixels: [__m128i; 4]) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let coefficients = _mm_setr_epi16(25, 129, 66, 0, 25, 129, 66, 0);
+
+        let values = pixels.map(|pixels| {
+            let low = _mm_madd_epi16(_mm_unpacklo_epi8(pixels, zero), coefficients);
+            let high = _mm_madd_epi16(_mm_unpackhi_epi8(pixels, zero), coefficients);
+            let sums = _mm_add_epi32(add_pairs(low, high), _mm_set1_epi32(128));
+            _mm_add_epi32(_mm_srai_epi32(sums, 8), _mm_set1_epi32(16))
+        });
+
+        _mm_packus_epi16(
+            _mm_packs_epi32(values[0], values[1]),
+            _mm_packs_epi32(values[2], values[3]),
+        )
+    }
+
+    /// The channels of each pair of neighbouring pixels added up, for 8 pixels.
+    unsafe fn pair_sums(a: __m128i, b: __m128i) -> [__m128i; 2] {
+        let zero = _mm_setzero_si128();
+        [a, b].map(|pixels| {
+            let low = _mm_unpacklo_epi8(pixels, zero);
+            let high = _mm_unpackhi_epi8(pixels, zero);
+            _mm_unpacklo_epi64(
+                _mm_add_epi16(low, _mm_srli_si128(low, 8)),
+                _mm_add_epi16(high, _mm_srli_si128(high, 8)),
+            )
+        })
+    }
+
+    /// 8 chroma values, in the low half, from the sums of 16 pixels.
+    unsafe fn chroma(sums: [[__m128i; 2]; 2], coefficients: __m128i) -> __m128i {
+        let values = sums.map(|[a, b]| {
+            let weighted = add_pairs(
+                _mm_madd_epi16(a, coefficients),
+                _mm_madd_epi16(b, coefficients),
+            );
+            let rounded = _mm_srai_epi32(_mm_add_epi32(weighted, _mm_set1_epi32(256)), 9);
+            _mm_add_epi32(rounded, _mm_set1_epi32(128))
+        });
+
+        let packed = _mm_packs_epi32(values[0], values[1]);
+        _mm_packus_epi16(packed, packed)
+    }
+}
+
+/// NEON is part of aarch64 itself, so it's always there.
+#[cfg(target_arch = "aarch64")]
+mod simd {
+    use std::arch::aarch64::*;
+
+    /// Converts 16 pixels at a time, returning how many were converted.
+    pub fn convert_rows(
+        top: &[u8],
+        bottom: &[u8],
+        y_top: &mut [u8],
+        y_bottom: &mut [u8],
+        u: &mut [u8],
+        v: &mut [u8],
+    ) -> usize {
+        let blocks = y_top.len().min(u.len() * 2) / 16;
+
+        // SAFETY: every block reads 64 bytes of both rows and writes 16 bytes of luma and
+        // 8 bytes of chroma, which the number of blocks keeps within the slices
+        unsafe {
+            for block in 0..blocks {
+                // Loaded as separate B, G, R and A vectors
+                let top = vld4q_u8(top[block * 64..block * 64 + 64].as_ptr());
+                let bottom = vld4q_u8(bottom[block * 64..block * 64 + 64].as_ptr());
+
+                vst1q_u8(y_top[block * 16..].as_mut_ptr(), luma(top));
+                vst1q_u8(y_bottom[block * 16..].as_mut_ptr(), luma(bottom));
+
+                let [b, g, r] = [
+                    vrhaddq_u8(top.0, bottom.0),
+                    vrhaddq_u8(top.1, bottom.1),
+                    vrhaddq_u8(top.2, bottom.2),
+                ]
+                .map(|average| vreinterpretq_s16_u16(vpaddlq_u8(average)));
+
+                vst1_u8(
+                    u[block * 8..].as_mut_ptr(),
+                    chroma([b, g, r], [112, -74, -38]),
+                );
+                vst1_u8(
+                    v[block * 8..].as_mut_ptr(),
+                    chroma([r, g, b], [112, -94, -18]),
+                );
+            }
+        }
+
+        blocks * 16
+    }
+
+    /// 16 BGRA pixels to 16 luma values.
+    unsafe fn luma(pixels: uint8x16x4_t) -> uint8x16_t {
+        let half = |b: uint8x8_t, g: uint8x8_t, r: uint8x8_t| {
+            let mut sum = vmull_u8(b, vdup_n_u8(25));
+            sum = vmlal_u8(sum, g, vdup_n_u8(129));
+            sum = vmlal_u8(sum, r, vdup_n_u8(66));
+            let shifted = vshrq_n_u16(vaddq_u16(sum, vdupq_n_u16(128)), 8);
+            vmovn_u16(vaddq_u16(shifted, vdupq_n_u16(16)))
+        };
+
+        vcombine_u8(
+            half(
+                vget_low_u8(pixels.0),
+                vget_low_u8(pixels.1),
+                vget_low_u8(pixels.2),
+            ),
+            half(
+                vget_high_u8(pixels.0),
+                vget_high_u8(pixels.1),
+                vget_high_u8(pixels.2),
+            ),
+        )
+    }
+
+    /// 8 chroma values from the sums of pairs of pixels, for each channel.
+    unsafe fn chroma(sums: [int16x8_t; 3], [a, b, c]: [i16; 3]) -> uint8x8_t {
+        let half = |x: int16x4_t, y: int16x4_t, z: int16x4_t| {
+            let mut sum = vmull_n_s16(x, a);
+            sum = vmlal_n_s16(sum, y, b);
+            sum = vmlal_n_s16(sum, z, c);
+            let rounded = vshrq_n_s32(vaddq_s32(sum, vdupq_n_s32(256)), 9);
+            vqmovn_s32(vaddq_s32(rounded, vdupq_n_s32(128)))
+        };
+
+        let low = half(
+            vget_low_s16(sums[0]),
+            vget_low_s16(sums[1]),
+            vget_low_s16(sums[2]),
+        );
+        let high = half(
+            vget_high_s16(sums[0]),
+            vget_high_s16(sums[1]),
+            vget_high_s16(sums[2]),
+        );
+        vqmovun_s16(vcombine_s16(low, high))
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod simd {
+    pub fn convert_rows(
+        _top: &[u8],
+        _bottom: &[u8],
+        _y_top: &mut [u8],
+        _y_bottom: &mut [u8],
+        _u: &mut [u8],
+        _v: &mut [u8],
+    ) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise, which is what SIMD code paths tend to get wrong.
+    fn noise(width: usize, height: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..width * height * 4)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    fn solid(width: usize, height: usize, [b, g, r]: [u8; 3]) -> Vec<u8> {
+        [b, g, r, 255].repeat(width * height)
+    }
+
+    /// Textbook BT.601 limited range conversion, in floating point.
+    fn reference(bgra: &[u8], width: usize, height: usize) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * width + x) * 4;
+            let [b, g, r] = [0, 1, 2].map(|c| bgra[offset + c] as f64 / 255.0);
+            (r, g, b)
+        };
+        let luma = |(r, g, b): (f64, f64, f64)| 0.299 * r + 0.587 * g + 0.114 * b;
+
+        let mut y_plane = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                y_plane.push(16.0 + 219.0 * luma(pixel(x, y)));
+            }
+        }
+
+        let (mut u_plane, mut v_plane) = (vec![], vec![]);
+        for y in (0..height).step_by(2) {
+            for x in (0..width).step_by(2) {
+                let block = [
+                    pixel(x, y),
+                    pixel(x + 1, y),
+                    pixel(x, y + 1),
+                    pixel(x + 1, y + 1),
+                ];
+                let (r, g, b) = block.iter().fold((0.0, 0.0, 0.0), |sum, p| {
+                    (sum.0 + p.0 / 4.0, sum.1 + p.1 / 4.0, sum.2 + p.2 / 4.0)
+                });
+                let y = luma((r, g, b));
+                u_plane.push(128.0 + 224.0 * (b - y) / 1.772);
+                v_plane.push(128.0 + 224.0 * (r - y) / 1.402);
+            }
+        }
+
+        (y_plane, u_plane, v_plane)
+    }
+
+    fn assert_close(actual: &[u8], expected: &[f64], tolerance: f64, plane: &str) {
+        assert_eq!(actual.len(), expected.len(), "{plane} plane size");
+        for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (*actual as f64 - expected).abs() <= tolerance,
+                "{plane}[{index}] is {actual}, expected {expected:.2}"
+            );
+        }
+    }
+
+    fn convert(format: PixelFormat, bgra: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let (width, height) = (width as u32, height as u32);
+        let mut output = vec![];
+        FrameConverter::new(format, width, height, width, height).convert(bgra, &mut output);
+        output
+    }
+
+    #[test]
+    fn matches_reference_conversion() {
+        // Not a multiple of 16, so the scalar code converts the end of every row
+        let (width, height) = (70, 6);
+        let bgra = noise(width, height, 1);
+
+        let output = convert(PixelFormat::I420, &bgra, width, height);
+        let (y_plane, u_plane, v_plane) = reference(&bgra, width, height);
+
+        let (luma, chroma) = output.split_at(width * height);
+        let (u, v) = chroma.split_at(u_plane.len());
+        assert_close(luma, &y_plane, 1.0, "Y");
+        assert_close(u, &u_plane, 1.5, "U");
+        assert_close(v, &v_plane, 1.5, "V");
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let (width, height) = (256, 4);
+        for seed in 0..8 {
+            let bgra = noise(width, height, seed);
+            let (top, bottom) = bgra.split_at(width * 4);
+
+            let mut simd_output = [
+                vec![0; width],
+                vec![0; width],
+                vec![0; width / 2],
+                vec![0; width / 2],
+            ];
+            let mut scalar_output = simd_output.clone();
+
+            let [y_top, y_bottom, u, v] = &mut simd_output;
+            let converted = simd::convert_rows(top, &bottom[..width * 4], y_top, y_bottom, u, v);
+            let [y_top, y_bottom, u, v] = &mut scalar_output;
+            scalar::convert_rows(top, &bottom[..width * 4], y_top, y_bottom, u, v);
+
+            #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+            assert_eq!(converted, width);
+            if converted == width {
+                assert_eq!(simd_output, scalar_output, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn converts_known_colours() {
+        let cases = [
+            ([0, 0, 0], [16, 128, 128]),
+            ([255, 255, 255], [235, 128, 128]),
+            ([0, 0, 255], [82, 90, 240]),
+            ([0, 255, 0], [144, 54, 34]),
+            ([255, 0, 0], [41, 240, 110]),
+        ];
+
+        for (bgr, [y, u, v]) in cases {
+            let output = convert(PixelFormat::I420, &solid(32, 2, bgr), 32, 2);
+            let (luma, chroma) = output.split_at(64);
+            assert!(luma.iter().all(|value| *value == y), "Y of {bgr:?}");
+            assert!(chroma[..16].iter().all(|value| *value == u), "U of {bgr:?}");
+            assert!(chroma[16..].iter().all(|value| *value == v), "V of {bgr:?}");
+        }
+    }
+
+    #[test]
+    fn nv12_interleaves_i420_chroma() {
+        let (width, height) = (48, 8);
+        let bgra = noise(width, height, 7);
+
+        let i420 = convert(PixelFormat::I420, &bgra, width, height);
+        let nv12 = convert(PixelFormat::Nv12, &bgra, width, height);
+
+        let luma_size = width * height;
+        let chroma_size = luma_size / 4;
+        assert_eq!(i420.len(), nv12.len());
+        assert_eq!(i420[..luma_size], nv12[..luma_size]);
+
+        let (u, v) = i420[luma_size..].split_at(chroma_size);
+        for (index, pair) in nv12[luma_size..].chunks_exact(2).enumerate() {
+            assert_eq!(pair, [u[index], v[index]]);
+        }
+    }
+
+    #[test]
+    fn handles_padded_rows() {
+        let (width, height) = (40, 4);
+        let bgra = noise(width, height, 3);
+        let mut padded = vec![];
+        for row in bgra.chunks_exact(width * 4) {
+            padded.extend_from_slice(row);
+            padded.extend_from_slice(&[0; 32]);
+        }
+
+        let mut output = vec![];
+        FrameConverter::new(PixelFormat::I420, width as u32, height as u32, 40, 4)
+            .convert(&padded, &mut output);
+        assert_eq!(output, convert(PixelFormat::I420, &bgra, width, height));
+    }
+
+    #[test]
+    fn scales_to_output_size() {
+        let mut converter = FrameConverter::new(PixelFormat::I420, 100, 60, 50, 30);
+        let mut output = vec![];
+
+        converter.convert(&solid(100, 60, [255, 0, 0]), &mut output);
+        assert_eq!(output.len(), 50 * 30 * 3 / 2);
+        let (luma, chroma) = output.split_at(50 * 30);
+        assert!(luma.iter().all(|value| *value == 41));
+        assert!(chroma[..25 * 15].iter().all(|value| *value == 240));
+        assert!(chroma[25 * 15..].iter().all(|value| *value == 110));
+
+        // Halving averages each pair of columns
+        let mut stripes = vec![];
+        for _ in 0..60 {
+            for x in 0..100 {
+                let value = if x % 2 == 0 { 0 } else { 200 };
+                stripes.extend_from_slice(&[value, value, value, 255]);
+            }
+        }
+        converter.convert(&stripes, &mut output);
+        let expected = ((66 + 129 + 25) * 100 + 128) / 256 + 16;
+        assert!(output[..50 * 30]
+            .iter()
+            .all(|value| *value == expected as u8));
+    }
+}
@@ -0,0 +1,161 @@
+//! Encoders that run in-process, on a thread of their own. Captured frames and samples
+//! are handed to them along with when they were captured, instead of FFmpeg pacing
+//! raw data it reads from named pipes.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread::JoinHandle,
+};
+
+use super::{AudioMixMode, Captured, Instant, RecordingQuality};
+use crate::error::{CapError, ErrorCode};
+
+/// Messages waiting on the encoder thread. Anything captured while it's behind stays
+/// in the capture queues instead, where their budgets and overflow policies apply.
+const CHANNEL_CAPACITY: usize = 4;
+
+#[derive(Clone)]
+pub struct EncoderConfig {
+    pub recording_dir: PathBuf,
+    pub quality: RecordingQuality,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub output_width: u32,
+    pub output_height: u32,
+    pub audio_inputs: Vec<AudioInputFormat>,
+    pub audio_mix: AudioMixMode,
+    pub separate_renditions: bool,
+}
+
+#[derive(Clone)]
+pub struct AudioInputFormat {
+    /// FFmpeg CLI name of the sample format, as used for the raw audio pipes.
+    pub sample_format: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub gain: f32,
+}
+
+pub enum EncoderMessage {
+    Video(Captured),
+    Audio(usize, Captured),
+    Pause(Instant),
+    Resume(Instant),
+}
+
+/// What runs on the encoder thread. Sessions are created on that thread too, as the
+/// libraries behind them don't necessarily allow moving their state across threads.
+pub trait EncoderSession: Sized {
+    fn open(config: EncoderConfig) -> Result<Self, CapError>;
+
+    /// Encodes everything received until every sender is dropped, then finalizes
+    /// the output.
+    fn run(self, receiver: Receiver<EncoderMessage>) -> Result<(), CapError>;
+}
+
+/// An encoder failing ends the recording just like FFmpeg exiting would.
+pub fn encoder_error(message: impl Into<String>, cause: impl fmt::Display) -> CapError {
+    CapError::new(ErrorCode::FfmpegCrashed, message).caused_by(cause)
+}
+
+#[derive(Clone)]
+pub struct EncoderSender(SyncSender<EncoderMessage>);
+
+impl EncoderSender {
+    /// Returns false once the encoder has shut down.
+    pub async fn send_video(&self, frame: Captured) -> bool {
+        self.send(EncoderMessage::Video(frame)).await
+    }
+
+    /// Returns false once the encoder has shut down.
+    pub async fn send_audio(&self, input: usize, samples: Captured) -> bool {
+        self.send(EncoderMessage::Audio(input, samples)).await
+    }
+
+    /// Waits for the encoder to make room when it's behind, off the runtime.
+    async fn send(&self, message: EncoderMessage) -> bool {
+        match self.0.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+            Err(TrySendError::Full(message)) => {
+                let sender = self.0.clone();
+                tokio::task::spawn_blocking(move || sender.send(message).is_ok())
+                    .await
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
+
+pub struct InProcessEncoder {
+    sender: EncoderSender,
+    thread: JoinHandle<Result<(), CapError>>,
+}
+
+impl InProcessEncoder {
+    /// Returns once the session has been opened and is ready to take data.
+    pub fn start<S: EncoderSession>(config: EncoderConfig) -> Result<Self, CapError> {
+        let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let (ready_sender, ready_receiver) = mpsc::sync_channel(1);
+
+        let thread = std::thread::spawn(move || {
+            let session = match S::open(config) {
+                Ok(session) => {
+                    ready_sender.send(Ok(())).ok();
+                    session
+                }
+                Err(error) => {
+                    ready_sender.send(Err(error.clone())).ok();
+                    return Err(error);
+                }
+            };
+
+            let result = session.run(receiver);
+            if let Err(error) = &result {
+                tracing::error!("Encoding failed: {error}");
+            }
+            result
+        });
+
+        ready_receiver
+            .recv()
+            .map_err(|_| CapError::from("Encoder thread exited unexpectedly"))??;
+
+        Ok(Self {
+            sender: EncoderSender(sender),
+            thread,
+        })
+    }
+
+    pub fn sender(&self) -> EncoderSender {
+        self.sender.clone()
+    }
+
+    /// Blocks for as long as the encoder takes to make room, at most a frame's worth
+    /// of encoding.
+    pub fn pause(&self) {
+        self.sender
+            .0
+            .send(EncoderMessage::Pause(Instant::now()))
+            .ok();
+    }
+
+    pub fn resume(&self) {
+        self.sender
+            .0
+            .send(EncoderMessage::Resume(Instant::now()))
+            .ok();
+    }
+
+    /// Flushes the encoders and finalizes the playlists. Blocks until every sender has
+    /// been dropped, so the tasks feeding the encoder must have finished first.
+    pub fn finish(self) -> Result<(), CapError> {
+        drop(self.sender);
+
+        self.thread
+            .join()
+            .map_err(|_| CapError::from("Encoder thread panicked"))?
+    }
+}
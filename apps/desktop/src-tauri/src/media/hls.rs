@@ -0,0 +1,72 @@
+//! Names of the files FFmpeg's HLS muxer writes into the recording directory.
+//!
+//! A recording either has a single rendition holding every stream (`None` below), or,
+//! when audio inputs are kept apart, a video rendition plus one alternate audio
+//! rendition (`EXT-X-MEDIA`) per input, tied together by the master playlist. Every
+//! rendition's files live in the same directory and carry its name, so the playlists
+//! can keep referencing them relatively once uploaded.
+
+use super::HlsSegmentType;
+
+pub const MASTER_PLAYLIST_NAME: &str = "master.m3u8";
+pub const VIDEO_RENDITION: &str = "video";
+
+/// Stands in for the rendition name in the paths handed to FFmpeg.
+pub const RENDITION_PLACEHOLDER: &str = "%v";
+
+pub fn audio_rendition(index: usize) -> String {
+    format!("audio_{index}")
+}
+
+pub fn is_audio_rendition(rendition: &str) -> bool {
+    rendition.starts_with("audio_")
+}
+
+pub fn playlist_name(rendition: Option<&str>) -> String {
+    match rendition {
+        Some(rendition) => format!("stream_{rendition}.m3u8"),
+        None => "stream.m3u8".to_string(),
+    }
+}
+
+pub fn segment_pattern(rendition: Option<&str>, segment_type: HlsSegmentType) -> String {
+    match rendition {
+        Some(rendition) => format!("segment_{rendition}_%03d.{}", segment_type.extension()),
+        None => format!("segment_%03d.{}", segment_type.extension()),
+    }
+}
+
+/// The name FFmpeg gives the segment at `index`, for encoders writing HLS themselves.
+pub fn segment_name(rendition: Option<&str>, segment_type: HlsSegmentType, index: usize) -> String {
+    segment_pattern(rendition, segment_type).replace("%03d", &format!("{index:03}"))
+}
+
+/// fMP4 (CMAF) playlists reference a single init segment holding the `moov` box
+/// that every `.m4s` part depends on.
+pub fn init_segment_name(rendition: Option<&str>) -> String {
+    match rendition {
+        Some(rendition) => format!("init_{rendition}.mp4"),
+        None => "init.mp4".to_string(),
+    }
+}
+
+pub fn is_init_segment(file_name: &str) -> bool {
+    file_name.starts_with("init") && file_name.ends_with(".mp4")
+}
+
+/// Works out which rendition a playlist, segment or init segment belongs to.
+pub fn rendition_of(file_name: &str) -> Option<&str> {
+    if let Some(name) = file_name.strip_prefix("stream_") {
+        return name.strip_suffix(".m3u8");
+    }
+    if let Some(name) = file_name.strip_prefix("init_") {
+        return name.strip_suffix(".mp4");
+    }
+
+    let (stem, _) = file_name.strip_prefix("segment_")?.rsplit_once('.')?;
+    let (rendition, number) = stem.rsplit_once('_')?;
+    number
+        .chars()
+        .all(|c| c.is_ascii_digit())
+        .then_some(rendition)
+}
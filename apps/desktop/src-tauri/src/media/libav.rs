@@ -0,0 +1,547 @@
+//! In-process encoding through libav, producing the same HLS output as the FFmpeg CLI.
+//! Encoder and muxer failures surface as actual errors rather than a process exiting.
+
+use ffmpeg_next::{
+    self as ffmpeg, codec, encoder, filter, format, frame, picture, software::scaling,
+    ChannelLayout, Dictionary, Packet, Rational,
+};
+use std::{sync::mpsc::Receiver, time::Duration};
+
+use super::encoder::{encoder_error, EncoderConfig, EncoderMessage, EncoderSession};
+use super::sync::Timeline;
+use super::{hls, HlsSegmentType, RecordingQuality};
+use crate::error::{CapError, ErrorCode};
+
+/// Timestamps handed to the video encoder are in microseconds.
+const VIDEO_TIME_BASE: Rational = Rational(1, 1_000_000);
+const AUDIO_SAMPLE_RATE: u32 = 48_000;
+
+pub struct Session {
+    output: format::context::Output,
+    video: VideoEncoder,
+    audio: Option<AudioEncoders>,
+    timeline: Timeline,
+}
+
+impl EncoderSession for Session {
+    fn open(config: EncoderConfig) -> Result<Self, CapError> {
+        ffmpeg::init().map_err(|e| encoder_error("Failed to initialize libav", e))?;
+
+        let quality = &config.quality;
+        let segment_type = quality.segment_type();
+        let rendition = config
+            .separate_renditions
+            .then_some(hls::RENDITION_PLACEHOLDER);
+
+        let playlist_path = config.recording_dir.join(hls::playlist_name(rendition));
+        let mut output = format::output_as(&playlist_path, "hls")
+            .map_err(|e| encoder_error("Failed to create HLS output", e))?;
+
+        let video = VideoEncoder::new(&mut output, &config)?;
+        let audio = match config.audio_inputs.is_empty() {
+            true => None,
+            false => Some(AudioEncoders::new(&mut output, &config)?),
+        };
+
+        let segment_pattern = config
+            .recording_dir
+            .join(hls::segment_pattern(rendition, segment_type));
+
+        let mut options = Dictionary::new();
+        options.set("hls_time", &quality.keyframe_interval_secs.to_string());
+        options.set("hls_playlist_type", "vod");
+        options.set("hls_flags", "independent_segments+temp_file");
+        options.set("master_pl_name", hls::MASTER_PLAYLIST_NAME);
+        options.set("hls_segment_type", segment_type.ffmpeg_name());
+        options.set("hls_segment_filename", &segment_pattern.to_string_lossy());
+        if segment_type == HlsSegmentType::Fmp4 {
+            options.set("hls_fmp4_init_filename", &hls::init_segment_name(rendition));
+        }
+        if let (true, Some(audio)) = (config.separate_renditions, &audio) {
+            options.set(
+                "var_stream_map",
+                &super::var_stream_map(audio.outputs.len()),
+            );
+        }
+
+        output
+            .write_header_with(options)
+            .map_err(|e| encoder_error("Failed to write HLS header", e))?;
+
+        Ok(Self {
+            output,
+            video,
+            audio,
+            timeline: Timeline::default(),
+        })
+    }
+
+    fn run(mut self, receiver: Receiver<EncoderMessage>) -> Result<(), CapError> {
+        while let Ok(message) = receiver.recv() {
+            match message {
+                EncoderMessage::Video(frame) => {
+                    if let Some(position) = self.timeline.video_position(frame.captured_at) {
+                        self.video.encode(&mut self.output, &frame.data, position)?;
+                    }
+                }
+                EncoderMessage::Audio(input, samples) => {
+                    let Some(audio) = &mut self.audio else {
+                        continue;
+                    };
+                    if let Some(position) = self.timeline.position(samples.captured_at) {
+                        audio.encode(&mut self.output, input, &samples.data, position)?;
+                    }
+                }
+                EncoderMessage::Pause(instant) => self.timeline.pause(instant),
+                EncoderMessage::Resume(instant) => self.timeline.resume(instant),
+            }
+        }
+
+        self.video.finish(&mut self.output)?;
+        if let Some(audio) = &mut self.audio {
+            audio.finish(&mut self.output)?;
+        }
+
+        self.output
+            .write_trailer()
+            .map_err(|e| encoder_error("Failed to finalize HLS output", e))
+    }
+}
+
+struct VideoEncoder {
+    encoder: encoder::video::Encoder,
+    scaler: scaling::Context,
+    stream_index: usize,
+    input: frame::Video,
+    output: frame::Video,
+    last_pts: Option<i64>,
+    next_keyframe_pts: i64,
+    keyframe_interval: i64,
+}
+
+impl VideoEncoder {
+    fn new(output: &mut format::context::Output, config: &EncoderConfig) -> Result<Self, CapError> {
+        let quality = &config.quality;
+        let (encoder_name, options) = encoder_options(quality)?;
+        let codec = encoder::find_by_name(&encoder_name)
+            .ok_or_else(|| unsupported(format!("libav was built without {encoder_name}")))?;
+        let global_header = output
+            .format()
+            .flags()
+            .contains(format::Flags::GLOBAL_HEADER);
+
+        let mut stream = output
+            .add_stream(codec)
+            .map_err(|e| encoder_error("Failed to add video stream", e))?;
+        let stream_index = stream.index();
+
+        let mut context = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .map_err(|e| encoder_error("Failed to create video encoder", e))?;
+        context.set_width(config.output_width);
+        context.set_height(config.output_height);
+        context.set_format(format::Pixel::YUV420P);
+        context.set_time_base(VIDEO_TIME_BASE);
+        context.set_frame_rate(Some(Rational(quality.fps as i32, 1)));
+        context.set_gop(quality.keyframe_interval_frames());
+        if global_header {
+            context.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = context
+            .open_as_with(codec, options)
+            .map_err(|e| encoder_error(format!("Failed to open {encoder_name}"), e))?;
+        stream.set_parameters(&encoder);
+        stream.set_time_base(VIDEO_TIME_BASE);
+
+        let scaler = scaling::Context::get(
+            format::Pixel::BGRA,
+            config.frame_width,
+            config.frame_height,
+            format::Pixel::YUV420P,
+            config.output_width,
+            config.output_height,
+            scaling::Flags::BILINEAR,
+        )
+        .map_err(|e| encoder_error("Failed to create scaler", e))?;
+
+        Ok(Self {
+            encoder,
+            scaler,
+            stream_index,
+            input: frame::Video::new(format::Pixel::BGRA, config.frame_width, config.frame_height),
+            output: frame::Video::empty(),
+            last_pts: None,
+            next_keyframe_pts: 0,
+            keyframe_interval: quality.keyframe_interval_secs as i64 * 1_000_000,
+        })
+    }
+
+    fn encode(
+        &mut self,
+        output: &mut format::context::Output,
+        data: &[u8],
+        position: Duration,
+    ) -> Result<(), CapError> {
+        let pts = position.as_micros() as i64;
+        // Frames that arrive out of order or share a timestamp can't be encoded
+        if self.last_pts.is_some_and(|last_pts| pts <= last_pts) {
+            return Ok(());
+        }
+        self.last_pts = Some(pts);
+
+        let height = self.input.height() as usize;
+        let stride = self.input.stride(0);
+        let row_size = self.input.width() as usize * 4;
+        let source_stride = data.len() / height.max(1);
+        let plane = self.input.data_mut(0);
+        for (y, row) in data.chunks_exact(source_stride).take(height).enumerate() {
+            plane[y * stride..y * stride + row_size].copy_from_slice(&row[..row_size]);
+        }
+
+        self.scaler
+            .run(&self.input, &mut self.output)
+            .map_err(|e| encoder_error("Failed to convert frame", e))?;
+        self.output.set_pts(Some(pts));
+
+        // Segments can only be cut on keyframes, so they're forced on the same schedule
+        // as the CLI's `-force_key_frames`
+        if pts >= self.next_keyframe_pts {
+            self.output.set_kind(picture::Type::I);
+            self.next_keyframe_pts = pts + self.keyframe_interval;
+        } else {
+            self.output.set_kind(picture::Type::None);
+        }
+
+        self.encoder
+            .send_frame(&self.output)
+            .map_err(|e| encoder_error("Failed to encode video frame", e))?;
+        self.write_packets(output)
+    }
+
+    fn finish(&mut self, output: &mut format::context::Output) -> Result<(), CapError> {
+        self.encoder
+            .send_eof()
+            .map_err(|e| encoder_error("Failed to flush video encoder", e))?;
+        self.write_packets(output)
+    }
+
+    fn write_packets(&mut self, output: &mut format::context::Output) -> Result<(), CapError> {
+        let stream_time_base = stream_time_base(output, self.stream_index)?;
+
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(self.stream_index);
+            packet.rescale_ts(VIDEO_TIME_BASE, stream_time_base);
+            packet
+                .write_interleaved(output)
+                .map_err(|e| encoder_error("Failed to write video packet", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct AudioInput {
+    source: String,
+    format: format::Sample,
+    layout: ChannelLayout,
+    sample_rate: u32,
+    bytes_per_frame: usize,
+    next_pts: Option<i64>,
+}
+
+struct AudioOutput {
+    sink: String,
+    encoder: encoder::audio::Encoder,
+    stream_index: usize,
+}
+
+/// Runs every input through the same filter graph as the CLI path, ending in one AAC
+/// encoder per audio track.
+struct AudioEncoders {
+    graph: filter::Graph,
+    inputs: Vec<AudioInput>,
+    outputs: Vec<AudioOutput>,
+}
+
+impl AudioEncoders {
+    fn new(output: &mut format::context::Output, config: &EncoderConfig) -> Result<Self, CapError> {
+        let mut graph = filter::Graph::new();
+        let abuffer =
+            filter::find("abuffer").ok_or_else(|| unsupported("libav has no abuffer filter"))?;
+        let abuffersink = filter::find("abuffersink")
+            .ok_or_else(|| unsupported("libav has no abuffersink filter"))?;
+
+        let mut inputs = Vec::new();
+        for (index, input) in config.audio_inputs.iter().enumerate() {
+            let format = sample_format(&input.sample_format)?;
+            let layout = ChannelLayout::default(input.channels as i32);
+            let source = format!("in{index}");
+
+            let args = format!(
+                "time_base=1/{rate}:sample_rate={rate}:sample_fmt={}:channel_layout=0x{:x}",
+                format.name(),
+                layout.bits(),
+                rate = input.sample_rate,
+            );
+            graph
+                .add(&abuffer, &source, &args)
+                .map_err(|e| encoder_error(format!("Failed to add audio input {index}"), e))?;
+
+            inputs.push(AudioInput {
+                source,
+                format,
+                layout,
+                sample_rate: input.sample_rate,
+                bytes_per_frame: format.bytes() * input.channels as usize,
+                next_pts: None,
+            });
+        }
+
+        let gains: Vec<f32> = config.audio_inputs.iter().map(|input| input.gain).collect();
+        let (spec, output_labels) =
+            super::audio_filter_graph(&gains, config.audio_mix, |index| format!("in{index}"));
+
+        let mut outputs = Vec::new();
+        for label in output_labels {
+            let sink = label.trim_matches(|c| c == '[' || c == ']').to_string();
+            let (encoder, stream_index) = open_aac_encoder(output, &config.quality)?;
+
+            let mut sink_context = graph
+                .add(&abuffersink, &sink, "")
+                .map_err(|e| encoder_error(format!("Failed to add audio output {sink}"), e))?;
+            sink_context.set_sample_format(encoder.format());
+            sink_context.set_channel_layout(encoder.channel_layout());
+            sink_context.set_sample_rate(encoder.rate());
+
+            outputs.push(AudioOutput {
+                sink,
+                encoder,
+                stream_index,
+            });
+        }
+
+        let mut parser = graph.output(&inputs[0].source, 0);
+        for input in &inputs[1..] {
+            parser = parser.and_then(|parser| parser.output(&input.source, 0));
+        }
+        for output in &outputs {
+            parser = parser.and_then(|parser| parser.input(&output.sink, 0));
+        }
+        parser
+            .and_then(|parser| parser.parse(&spec))
+            .map_err(|e| encoder_error("Failed to parse audio filter graph", e))?;
+        graph
+            .validate()
+            .map_err(|e| encoder_error("Invalid audio filter graph", e))?;
+
+        for output in &outputs {
+            let variable_frame_size = output.encoder.codec().is_some_and(|codec| {
+                codec
+                    .capabilities()
+                    .contains(codec::Capabilities::VARIABLE_FRAME_SIZE)
+            });
+            if !variable_frame_size {
+                if let Some(mut sink) = graph.get(&output.sink) {
+                    sink.sink().set_frame_size(output.encoder.frame_size());
+                }
+            }
+        }
+
+        Ok(Self {
+            graph,
+            inputs,
+            outputs,
+        })
+    }
+
+    fn encode(
+        &mut self,
+        output: &mut format::context::Output,
+        index: usize,
+        data: &[u8],
+        position: Duration,
+    ) -> Result<(), CapError> {
+        let input = &mut self.inputs[index];
+        let samples = data.len() / input.bytes_per_frame;
+        if samples == 0 {
+            return Ok(());
+        }
+
+        // Samples are timed by how many came before them, anchored to when the first
+        // chunk was captured. That chunk's timestamp marks its end, not its start.
+        let pts = *input.next_pts.get_or_insert_with(|| {
+            let end = (position.as_secs_f64() * input.sample_rate as f64) as i64;
+            (end - samples as i64).max(0)
+        });
+        input.next_pts = Some(pts + samples as i64);
+
+        let mut frame = frame::Audio::new(input.format, samples, input.layout);
+        frame.set_rate(input.sample_rate);
+        frame.set_pts(Some(pts));
+        let size = samples * input.bytes_per_frame;
+        frame.data_mut(0)[..size].copy_from_slice(&data[..size]);
+
+        let mut source = self
+            .graph
+            .get(&input.source)
+            .ok_or_else(|| CapError::from("Audio input is missing from the filter graph"))?;
+        source
+            .source()
+            .add(&frame)
+            .map_err(|e| encoder_error("Failed to filter audio", e))?;
+
+        self.write_packets(output)
+    }
+
+    fn finish(&mut self, output: &mut format::context::Output) -> Result<(), CapError> {
+        for input in &self.inputs {
+            if let Some(mut source) = self.graph.get(&input.source) {
+                source.source().flush().ok();
+            }
+        }
+        self.write_packets(output)?;
+
+        for audio_output in &mut self.outputs {
+            audio_output
+                .encoder
+                .send_eof()
+                .map_err(|e| encoder_error("Failed to flush audio encoder", e))?;
+        }
+        self.write_packets(output)
+    }
+
+    /// Pulls whatever the filter graph has ready through the encoders and into the muxer.
+    fn write_packets(&mut self, output: &mut format::context::Output) -> Result<(), CapError> {
+        let mut filtered = frame::Audio::empty();
+
+        for audio_output in &mut self.outputs {
+            let Some(mut sink) = self.graph.get(&audio_output.sink) else {
+                continue;
+            };
+            let sink_time_base = sink.sink().time_base();
+            let encoder_time_base = audio_output.encoder.time_base();
+            let stream_time_base = stream_time_base(output, audio_output.stream_index)?;
+
+            while sink.sink().frame(&mut filtered).is_ok() {
+                let pts = filtered
+                    .pts()
+                    .map(|pts| ffmpeg::Rescale::rescale(&pts, sink_time_base, encoder_time_base));
+                filtered.set_pts(pts);
+
+                audio_output
+                    .encoder
+                    .send_frame(&filtered)
+                    .map_err(|e| encoder_error("Failed to encode audio", e))?;
+
+                let mut packet = Packet::empty();
+                while audio_output.encoder.receive_packet(&mut packet).is_ok() {
+                    packet.set_stream(audio_output.stream_index);
+                    packet.rescale_ts(encoder_time_base, stream_time_base);
+                    packet
+                        .write_interleaved(output)
+                        .map_err(|e| encoder_error("Failed to write audio packet", e))?;
+                }
+            }
+
+            let mut packet = Packet::empty();
+            while audio_output.encoder.receive_packet(&mut packet).is_ok() {
+                packet.set_stream(audio_output.stream_index);
+                packet.rescale_ts(encoder_time_base, stream_time_base);
+                packet
+                    .write_interleaved(output)
+                    .map_err(|e| encoder_error("Failed to write audio packet", e))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn open_aac_encoder(
+    output: &mut format::context::Output,
+    quality: &RecordingQuality,
+) -> Result<(encoder::audio::Encoder, usize), CapError> {
+    let codec = encoder::find(codec::Id::AAC)
+        .ok_or_else(|| unsupported("libav was built without an AAC encoder"))?;
+    let global_header = output
+        .format()
+        .flags()
+        .contains(format::Flags::GLOBAL_HEADER);
+
+    let mut stream = output
+        .add_stream(codec)
+        .map_err(|e| encoder_error("Failed to add audio stream", e))?;
+    let stream_index = stream.index();
+
+    let mut context = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .audio()
+        .map_err(|e| encoder_error("Failed to create audio encoder", e))?;
+    context.set_rate(AUDIO_SAMPLE_RATE as i32);
+    context.set_channel_layout(ChannelLayout::STEREO);
+    context.set_format(format::Sample::F32(format::sample::Type::Planar));
+    context.set_bit_rate(quality.audio_bitrate_kbps as usize * 1000);
+    context.set_time_base(Rational(1, AUDIO_SAMPLE_RATE as i32));
+    if global_header {
+        context.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+
+    let encoder = context
+        .open_as(codec)
+        .map_err(|e| encoder_error("Failed to open AAC encoder", e))?;
+    stream.set_parameters(&encoder);
+    stream.set_time_base(Rational(1, AUDIO_SAMPLE_RATE as i32));
+
+    Ok((encoder, stream_index))
+}
+
+/// The muxer may pick its own time base for a stream when writing the header.
+fn stream_time_base(output: &format::context::Output, index: usize) -> Result<Rational, CapError> {
+    output
+        .stream(index)
+        .map(|stream| stream.time_base())
+        .ok_or_else(|| CapError::from(format!("Output stream {index} is missing")))
+}
+
+/// Translates the CLI arguments for the codec into encoder options, so both paths
+/// encode with the exact same settings.
+fn encoder_options(quality: &RecordingQuality) -> Result<(String, Dictionary<'static>), CapError> {
+    let args = quality.codec.encoder_args(quality)?;
+    let [_, encoder, options @ ..] = args.as_slice() else {
+        return Err("Missing encoder arguments".into());
+    };
+
+    let mut dictionary = Dictionary::new();
+    for pair in options.chunks_exact(2) {
+        let key = pair[0].trim_start_matches('-');
+        // Stream tags are up to the muxer, which can't be told about them here
+        if key.starts_with("tag:") {
+            continue;
+        }
+        dictionary.set(key.strip_suffix(":v").unwrap_or(key), &pair[1]);
+    }
+
+    Ok((encoder.clone(), dictionary))
+}
+
+/// Maps the CLI names of the raw PCM formats onto libav's. The CLI's are always little
+/// endian, which is what libav uses on every platform we ship.
+fn sample_format(name: &str) -> Result<format::Sample, CapError> {
+    use format::sample::Type::Packed;
+
+    match name {
+        "u8" => Ok(format::Sample::U8(Packed)),
+        "s16le" => Ok(format::Sample::I16(Packed)),
+        "s32le" => Ok(format::Sample::I32(Packed)),
+        "f32le" => Ok(format::Sample::F32(Packed)),
+        "f64le" => Ok(format::Sample::F64(Packed)),
+        _ => Err(unsupported(format!("libav can't take {name} samples"))),
+    }
+}
+
+fn unsupported(message: impl Into<String>) -> CapError {
+    CapError::new(ErrorCode::Unsupported, message)
+}
@@ -0,0 +1,148 @@
+//! Just enough Matroska to hand FFmpeg raw frames along with their timestamps,
+//! which the `rawvideo` input can't carry. Every frame goes in a cluster of its own,
+//! and the segment is left unsized, as it's written to a pipe.
+
+use std::time::Duration;
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const COLOUR_SPACE: u32 = 0x2E_B524;
+
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Timestamps are in microseconds.
+const NANOSECONDS_PER_TICK: u64 = 1000;
+const UNKNOWN_SIZE: u64 = 0x00FF_FFFF_FFFF_FFFF;
+
+/// The EBML header, and the start of a segment holding a single raw video track, in the
+/// pixel format with the given FourCC.
+pub fn header(width: u32, height: u32, fourcc: &[u8; 4]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    master(&mut out, EBML, |b| {
+        uint(b, EBML_VERSION, 1);
+        uint(b, EBML_READ_VERSION, 1);
+        uint(b, EBML_MAX_ID_LENGTH, 4);
+        uint(b, EBML_MAX_SIZE_LENGTH, 8);
+        binary(b, DOC_TYPE, b"matroska");
+        uint(b, DOC_TYPE_VERSION, 4);
+        uint(b, DOC_TYPE_READ_VERSION, 2);
+    });
+
+    write_id(&mut out, SEGMENT);
+    write_size(&mut out, UNKNOWN_SIZE);
+
+    master(&mut out, INFO, |b| {
+        uint(b, TIMESTAMP_SCALE, NANOSECONDS_PER_TICK);
+        binary(b, MUXING_APP, b"Cap");
+        binary(b, WRITING_APP, b"Cap");
+    });
+
+    master(&mut out, TRACKS, |b| {
+        master(b, TRACK_ENTRY, |b| {
+            uint(b, TRACK_NUMBER, 1);
+            uint(b, TRACK_UID, 1);
+            uint(b, TRACK_TYPE, 1);
+            uint(b, FLAG_LACING, 0);
+            binary(b, CODEC_ID, b"V_UNCOMPRESSED");
+            master(b, VIDEO, |b| {
+                uint(b, PIXEL_WIDTH, width as u64);
+                uint(b, PIXEL_HEIGHT, height as u64);
+                // FFmpeg picks the pixel format of raw video by its FourCC
+                binary(b, COLOUR_SPACE, fourcc);
+            });
+        });
+    });
+
+    out
+}
+
+/// The cluster holding a frame, without the frame data itself so it can be written
+/// straight from the captured buffer.
+pub fn frame_header(timestamp: Duration, frame_size: usize) -> Vec<u8> {
+    let mut block_header = Vec::with_capacity(4);
+    block_header.push(0x81); // track 1
+    block_header.extend_from_slice(&0i16.to_be_bytes()); // relative to the cluster
+    block_header.push(0x80); // keyframe
+
+    let mut timestamp_element = Vec::new();
+    uint(
+        &mut timestamp_element,
+        TIMESTAMP,
+        timestamp.as_nanos() as u64 / NANOSECONDS_PER_TICK,
+    );
+
+    let block_size = block_header.len() + frame_size;
+    let mut block_element_header = Vec::new();
+    write_id(&mut block_element_header, SIMPLE_BLOCK);
+    write_size(&mut block_element_header, block_size as u64);
+
+    let mut out = Vec::new();
+    write_id(&mut out, CLUSTER);
+    write_size(
+        &mut out,
+        (timestamp_element.len() + block_element_header.len() + block_size) as u64,
+    );
+    out.extend_from_slice(&timestamp_element);
+    out.extend_from_slice(&block_element_header);
+    out.extend_from_slice(&block_header);
+
+    out
+}
+
+fn master(out: &mut Vec<u8>, id: u32, content: impl FnOnce(&mut Vec<u8>)) {
+    let mut body = Vec::new();
+    content(&mut body);
+    binary(out, id, &body);
+}
+
+fn uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(7);
+    binary(out, id, &bytes[first..]);
+}
+
+fn binary(out: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(out, id);
+    write_size(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Element IDs already include their length marker.
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(3);
+    out.extend_from_slice(&bytes[first..]);
+}
+
+/// Sizes are always written as 8 byte variable length integers, which is allowed and
+/// saves working out how long they need to be.
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    out.push(0x01);
+    out.extend_from_slice(&size.to_be_bytes()[1..]);
+}
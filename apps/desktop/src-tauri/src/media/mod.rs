@@ -2,11 +2,12 @@ use std::{
     path::{Path, PathBuf},
     process::Stdio,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
     time::{Duration, Instant},
 };
+use tauri::AppHandle;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, ChildStdin, Command};
 use tokio::sync::Mutex;
@@ -15,14 +16,47 @@ use tracing::Level;
 
 use crate::{
     app::config,
+    error::{CapError, ErrorCode},
+    metadata::{AudioRendition, ChildProcess, RecordingMeta, SessionStats, StreamSync},
     recording::RecordingOptions,
     utils::{create_named_pipe, ffmpeg_path_as_str},
 };
 
 mod audio;
+mod builtin;
+mod codec;
+mod convert;
+mod encoder;
+pub mod hls;
+#[cfg(feature = "libav")]
+mod libav;
+mod matroska;
+mod pool;
+mod progress;
+mod quality;
+mod queue;
+mod status;
+mod sync;
+mod targets;
 mod video;
 
+pub use audio::{AudioInputOptions, AudioMixMode, AudioSource};
+pub use codec::{HlsSegmentType, VideoCodec};
+pub use progress::{EncoderProgress, EncodingAdaptation, ProgressParser};
+pub use quality::RecordingQuality;
+pub use queue::{OverflowPolicy, QueueDepth, QueueMetrics, QueueOverflow};
+pub use status::{RecordingStatus, UploadStatus, STATUS_INTERVAL};
+
 use audio::AudioCapturer;
+use codec::VideoCodecInfo;
+use convert::FrameConverter;
+use encoder::{AudioInputFormat, EncoderConfig, InProcessEncoder};
+use pool::Buffer;
+use progress::ProgressMonitor;
+use queue::QueueOptions;
+use status::StatusSources;
+use sync::{DriftCorrector, SharedTimeline, Timeline};
+use targets::{CaptureDisplay, CaptureWindow};
 use video::VideoCapturer;
 
 type SharedInstant = Arc<Mutex<Option<Instant>>>;
@@ -53,26 +87,62 @@ impl SharedFlag {
     }
 }
 
+/// The frame rate video is currently sent to the encoder at, which can go down while
+/// recording.
+#[derive(Default)]
+struct SharedFrameRate(Arc<AtomicU32>);
+
+impl SharedFrameRate {
+    fn get(&self) -> u32 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn set(&self, fps: u32) {
+        self.0.store(fps, Ordering::SeqCst);
+    }
+
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+/// A chunk of captured video or audio, along with when it was captured.
+pub struct Captured {
+    pub data: Buffer,
+    pub captured_at: Instant,
+}
+
 #[derive(Default)]
 pub struct MediaRecorder {
     pub options: Option<RecordingOptions>,
-    audio_capturer: Option<AudioCapturer>,
-    audio_enabled: bool,
+    /// Where status events go.
+    app: Option<AppHandle>,
+    audio_inputs: Vec<AudioCapturer>,
     // video_capturer: Option<VideoCapturer>,
     should_stop: SharedFlag,
+    is_paused: SharedFlag,
+    frame_rate: SharedFrameRate,
     ffmpeg_process: Option<Child>,
     // ffmpeg_stdin: Option<Arc<Mutex<Option<ChildStdin>>>>,
     ffmpeg_stdin: Option<ChildStdin>,
-    device_name: Option<String>,
     start_time: Option<Instant>,
     chunks_dir: PathBuf,
-    audio_pipe_task: Option<JoinHandle<()>>,
-    video_pipe_task: Option<JoinHandle<()>>,
+    audio_pipe_tasks: Vec<JoinHandle<Result<StreamSync, CapError>>>,
+    video_pipe_task: Option<JoinHandle<Result<Option<StreamSync>, CapError>>>,
+    /// Shared with the tasks feeding the encoder, to correct drift against.
+    timeline: SharedTimeline,
+    in_process_encoder: Option<InProcessEncoder>,
+    queues: Vec<QueueMetrics>,
+    /// Only there when recording through the FFmpeg CLI.
+    progress: Option<ProgressMonitor>,
 }
 
 impl MediaRecorder {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(app: AppHandle) -> Self {
+        Self {
+            app: Some(app),
+            ..Self::default()
+        }
     }
 
     #[tracing::instrument(skip(self))]
@@ -81,98 +151,285 @@ impl MediaRecorder {
         options: RecordingOptions,
         screenshot_dir: &Path,
         recording_dir: &Path,
-        custom_device: Option<&str>,
+        audio_inputs: &[AudioInputOptions],
+        audio_mix: AudioMixMode,
+        quality: RecordingQuality,
         max_screen_width: usize,
         max_screen_height: usize,
-    ) -> Result<(), String> {
+        ffmpeg_installed: bool,
+    ) -> Result<(), CapError> {
         if !scap::has_permission() {
             tracing::warn!("Screen capturing permission not granted. Requesting permission...");
             scap::request_permission();
-            return Err("App does not have screen capturing permission".into());
+            return Err(CapError::new(
+                ErrorCode::PermissionMissing,
+                "App does not have screen capturing permission",
+            ));
         }
 
         let options_clone = options.clone();
         self.options = Some(options);
 
-        // let adjusted_width = max_screen_width & !2;
-        // let adjusted_height = max_screen_height & !2;
-
         let ffmpeg_binary_path_str = ffmpeg_path_as_str().unwrap().to_owned();
 
-        let audio_start_time: SharedInstant = Arc::new(Mutex::new(None));
         let video_start_time: SharedInstant = Arc::new(Mutex::new(None));
 
-        self.audio_capturer = AudioCapturer::init(custom_device, self.should_stop.clone());
+        let mut audio_sources = Vec::new();
+        for input in audio_inputs {
+            let capturer = match &input.source {
+                AudioSource::Microphone { name } => AudioCapturer::init(
+                    (!name.is_empty()).then_some(name.as_str()),
+                    self.should_stop.clone(),
+                    self.is_paused.clone(),
+                )
+                .ok_or_else(|| {
+                    CapError::new(
+                        ErrorCode::DeviceGone,
+                        format!("Audio device {name} is not available"),
+                    )
+                }),
+                AudioSource::SystemAudio { monitor } => AudioCapturer::system_audio(
+                    monitor.as_deref(),
+                    self.should_stop.clone(),
+                    self.is_paused.clone(),
+                ),
+            };
+
+            match capturer {
+                Ok(mut capturer) => {
+                    capturer.gain = input.gain;
+                    self.audio_inputs.push(capturer);
+                    audio_sources.push(input.source.clone());
+                }
+                // Missing audio shouldn't prevent the screen from being recorded
+                Err(error) => tracing::error!("{error}"),
+            }
+        }
 
+        let capture_target =
+            targets::find_target(&options_clone.screen_index, options_clone.capture_mode)?;
+
+        let crop_area = options_clone
+            .crop_area
+            .as_ref()
+            .map(|region| targets::crop_area_for(&capture_target, region))
+            .transpose()?;
+
+        let display_id = match &capture_target {
+            scap::Target::Display(display) => Some(display.id),
+            _ => None,
+        };
+
+        self.frame_rate.set(quality.fps);
         let mut video_capturer = VideoCapturer::new(
+            Some(capture_target),
+            crop_area,
+            quality.fps,
+            quality.variable_frame_rate,
+            self.should_stop.clone(),
+            self.is_paused.clone(),
+            self.frame_rate.clone(),
+        );
+        let (output_width, output_height) = quality.output_size(
+            video_capturer.frame_width,
+            video_capturer.frame_height,
             max_screen_width,
             max_screen_height,
-            self.should_stop.clone(),
         );
-        let adjusted_width = video_capturer.frame_width;
-        let adjusted_height = video_capturer.frame_height;
 
-        if let Some(ref mut audio_capturer) = self.audio_capturer {
+        let queue_options = QueueOptions {
+            policy: options_clone.overflow_policy,
+            spill_dir: recording_dir.to_path_buf(),
+        };
+
+        let mut audio_start_times = Vec::new();
+        let mut started_sources = Vec::new();
+        let capturers = std::mem::take(&mut self.audio_inputs);
+        for (mut audio_capturer, source) in capturers.into_iter().zip(audio_sources) {
             audio_capturer.log_info();
 
-            match audio_capturer.start(audio_start_time.clone()) {
-                Ok(_) => {
-                    self.audio_enabled = true;
+            let start_time: SharedInstant = Arc::new(Mutex::new(None));
+            match audio_capturer.start(start_time.clone(), &queue_options) {
+                Ok(metrics) => {
+                    self.queues.push(metrics);
+                    audio_start_times.push(start_time);
+                    started_sources.push(source);
+                    self.audio_inputs.push(audio_capturer);
                 }
-                Err(error) => tracing::error!(error),
+                Err(error) => tracing::error!("{error}"),
             }
         }
 
-        video_capturer.start(video_start_time.clone(), screenshot_dir, options_clone);
+        // With separate tracks, every audio input gets an alternate rendition of its own
+        // instead of being muxed in with the video. The built-in encoder always mixes.
+        let separate_renditions = audio_mix == AudioMixMode::SeparateTracks
+            && !self.audio_inputs.is_empty()
+            && ffmpeg_installed;
+        let audio_renditions = if separate_renditions {
+            started_sources
+                .into_iter()
+                .zip(self.audio_inputs.iter())
+                .enumerate()
+                .map(|(index, (source, capturer))| AudioRendition {
+                    name: hls::audio_rendition(index),
+                    source,
+                    gain: capturer.gain,
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        RecordingMeta {
+            video_id: options_clone.video_id.clone(),
+            capture_mode: options_clone.capture_mode,
+            display_id,
+            crop_area: options_clone.crop_area,
+            width: output_width,
+            height: output_height,
+            video_codec: quality.codec,
+            segment_type: quality.segment_type(),
+            audio_renditions,
+            session_stats: None,
+        }
+        .save(recording_dir)?;
+
+        let encoder_config = EncoderConfig {
+            recording_dir: recording_dir.to_path_buf(),
+            quality: quality.clone(),
+            frame_width: video_capturer.frame_width,
+            frame_height: video_capturer.frame_height,
+            output_width,
+            output_height,
+            audio_inputs: self
+                .audio_inputs
+                .iter()
+                .map(|capturer| AudioInputFormat {
+                    sample_format: capturer.sample_format().to_string(),
+                    sample_rate: capturer.sample_rate(),
+                    channels: capturer.channels(),
+                    gain: capturer.gain,
+                })
+                .collect(),
+            audio_mix,
+            separate_renditions,
+        };
+
+        // The CLI stays around for FFmpeg builds (or inputs) libav can't deal with
+        #[cfg(feature = "libav")]
+        let in_process_encoder = InProcessEncoder::start::<libav::Session>(encoder_config.clone())
+            .map_err(|error| tracing::warn!("Couldn't encode through libav: {error}"))
+            .ok();
+        #[cfg(not(feature = "libav"))]
+        let in_process_encoder = None;
+
+        let in_process_encoder = match in_process_encoder {
+            Some(encoder) => Some(encoder),
+            // Without FFmpeg, the built-in encoder is all there is
+            None if !ffmpeg_installed => {
+                Some(InProcessEncoder::start::<builtin::Session>(encoder_config)?)
+            }
+            None => None,
+        };
+
+        // FFmpeg gets frames already converted to what it encodes, at the size it encodes
+        // them at. The in-process encoders convert frames themselves.
+        let input_format = quality.codec.input_format();
+        let converter = in_process_encoder.is_none().then(|| {
+            FrameConverter::new(
+                input_format,
+                video_capturer.frame_width,
+                video_capturer.frame_height,
+                output_width,
+                output_height,
+            )
+        });
+
+        self.timeline = Arc::new(std::sync::Mutex::new(Timeline::starting_at(Instant::now())));
+        let video_queue = video_capturer.start(
+            video_start_time.clone(),
+            screenshot_dir,
+            options_clone,
+            &queue_options,
+            converter,
+        );
+        self.queues.insert(0, video_queue);
+
+        if let Some(encoder) = in_process_encoder {
+            for (index, capturer) in self.audio_inputs.iter_mut().enumerate() {
+                let drift = capturer.drift_corrector(self.timeline.clone());
+                self.audio_pipe_tasks
+                    .push(tokio::spawn(capturer.encode_samples(
+                        index,
+                        encoder.sender(),
+                        drift,
+                    )));
+            }
+            self.video_pipe_task =
+                Some(tokio::spawn(video_capturer.encode_frames(encoder.sender())));
+
+            self.in_process_encoder = Some(encoder);
+            self.start_time = Some(Instant::now());
+            self.chunks_dir = recording_dir.to_path_buf();
+            self.report_status();
+
+            tracing::info!("Media recording successfully started with an in-process encoder");
+            return Ok(());
+        }
 
         tracing::info!("Starting audio recording and processing...");
-        let segment_pattern_path = recording_dir.join("segment_%03d.ts");
-        let playlist_path = recording_dir.join("stream.m3u8");
+        let segment_type = quality.segment_type();
+        let rendition = separate_renditions.then_some(hls::RENDITION_PLACEHOLDER);
+        let segment_pattern_path =
+            recording_dir.join(hls::segment_pattern(rendition, segment_type));
+        let playlist_path = recording_dir.join(hls::playlist_name(rendition));
 
         let video_pipe_path = recording_dir.join("video.pipe");
 
         std::fs::remove_file(&video_pipe_path).ok();
-        create_named_pipe(&video_pipe_path).map_err(|e| e.to_string())?;
+        create_named_pipe(&video_pipe_path).map_err(pipe_error)?;
 
-        let audio_pipe_path = recording_dir.join("audio.pipe");
+        let mut audio_pipe_paths = Vec::new();
+        for index in 0..self.audio_inputs.len() {
+            let audio_pipe_path = recording_dir.join(format!("audio_{index}.pipe"));
 
-        std::fs::remove_file(&audio_pipe_path).ok();
-        create_named_pipe(&audio_pipe_path).map_err(|e| e.to_string())?;
+            std::fs::remove_file(&audio_pipe_path).ok();
+            create_named_pipe(&audio_pipe_path).map_err(pipe_error)?;
+            audio_pipe_paths.push(audio_pipe_path);
+        }
 
-        let time_offset = if self.audio_enabled {
+        let time_offsets = if !self.audio_inputs.is_empty() {
             tracing::trace!("Adjusting FFmpeg commands based on start times...");
-            create_time_offset_args(&audio_start_time, &video_start_time).await
+            let start_times = [vec![video_start_time.clone()], audio_start_times].concat();
+            create_time_offset_args(&start_times).await
         } else {
-            None
+            vec![None]
         };
 
-        let size = format!("{}x{}", adjusted_width, adjusted_height);
-
         let mut ffmpeg_command = Command::new(ffmpeg_binary_path_str);
+        // Progress reports replace the stats FFmpeg otherwise keeps writing to stderr
+        ffmpeg_command.args(["-nostats", "-progress", "pipe:1"]);
 
         // Quiet ffmpeg output a bit
         let log_level = config::logging_level();
         if log_level == Level::DEBUG || log_level == Level::TRACE {
-            ffmpeg_command.args(["-nostats", "-hide_banner"]);
+            ffmpeg_command.args(["-hide_banner"]);
         }
-        if let Some((TimeOffsetTarget::Video, args)) = &time_offset {
+        if let Some(args) = &time_offsets[0] {
             ffmpeg_command.args(args);
         }
 
-        let fps = VideoCapturer::FPS.to_string();
         ffmpeg_command
-            // video in
-            .args(["-f", "rawvideo", "-pix_fmt", "bgra"])
-            .args(["-s", &size, "-r", &fps])
+            // video in, with timestamps
+            .args(["-f", "matroska"])
             .args(["-thread_queue_size", "4096", "-i"])
             .arg(&video_pipe_path);
 
-        if self.audio_enabled {
-            if let Some((TimeOffsetTarget::Audio, args)) = &time_offset {
+        for (index, capturer) in self.audio_inputs.iter().enumerate() {
+            if let Some(args) = &time_offsets[index + 1] {
                 ffmpeg_command.args(args);
             }
 
-            let capturer = self.audio_capturer.as_ref().unwrap();
             let sample_format = capturer.sample_format();
             let sample_rate_str = capturer.sample_rate().to_string();
             let channels_str = capturer.channels().to_string();
@@ -181,40 +438,65 @@ impl MediaRecorder {
                 // audio in
                 .args(["-f", sample_format, "-ar", &sample_rate_str])
                 .args(["-ac", &channels_str, "-thread_queue_size", "4096", "-i"])
-                .arg(&audio_pipe_path);
-            // out
-            // .args(["-f", "hls", "-async", "1"])
-            // .args(["-segment_time", "3", "-segment_time_delta", "0.01"])
-            // .args(["-reset_timestamps", "1", "-vn", "-segment_list"])
-            // .args([&audio_segment_list_filename, &audio_chunk_pattern]);
+                .arg(&audio_pipe_paths[index]);
         }
 
+        let keyframe_interval = quality.keyframe_interval_secs.to_string();
         ffmpeg_command
             .args(["-f", "hls"])
-            .args(["-hls_time", "3", "-hls_playlist_type", "vod"])
-            .args(["-hls_flags", "independent_segments"])
-            .args(["-master_pl_name", "master.m3u8"])
-            .args(["-hls_segment_type", "mpegts"])
+            .args(["-hls_time", &keyframe_interval, "-hls_playlist_type", "vod"])
+            // Segments are written under a temporary name, so the upload loop never
+            // sees half-written files
+            .args(["-hls_flags", "independent_segments+temp_file"])
+            .args(["-master_pl_name", hls::MASTER_PLAYLIST_NAME])
+            .args(["-hls_segment_type", segment_type.ffmpeg_name()])
             .arg("-hls_segment_filename")
-            .arg(&segment_pattern_path)
+            .arg(&segment_pattern_path);
+
+        if segment_type == HlsSegmentType::Fmp4 {
+            ffmpeg_command.args([
+                "-hls_fmp4_init_filename",
+                &hls::init_segment_name(rendition),
+            ]);
+        }
+
+        ffmpeg_command
             // video
-            .args(["-codec:v", "libx264", "-preset", "ultrafast"])
-            .args(["-pix_fmt", "yuv420p", "-tune", "zerolatency"])
-            .args(["-vsync", "1", "-force_key_frames", "expr:gte(t,n_forced*3)"])
-            .args(["-movflags", "frag_keyframe+empty_moov"])
+            .args(quality.codec.encoder_args(&quality)?)
+            .args(["-pix_fmt", input_format.ffmpeg_name()])
+            .args(["-g", &quality.keyframe_interval_frames().to_string()])
             .args([
-                "-vf",
-                &format!("fps={fps},scale=in_range=full:out_range=limited"),
-            ]);
+                "-force_key_frames",
+                &format!("expr:gte(t,n_forced*{keyframe_interval})"),
+            ])
+            .args(["-movflags", "frag_keyframe+empty_moov"]);
+
+        // Frames keep the timestamps they came with, and last until the next one. With a
+        // constant frame rate, that's still the rate they were written at, so a lowered
+        // one carries through to the output rather than FFmpeg duplicating frames back up
+        ffmpeg_command.args(["-vsync", "vfr"]);
+
+        if !self.audio_inputs.is_empty() {
+            let gains: Vec<f32> = self.audio_inputs.iter().map(|input| input.gain).collect();
+            let (filter_graph, audio_outputs) =
+                audio_filter_graph(&gains, audio_mix, |index| format!("{}:a", index + 1));
+            let audio_output_count = audio_outputs.len();
+
+            ffmpeg_command
+                .args(["-filter_complex", &filter_graph])
+                .args(["-map", "0:v"]);
+            for output in audio_outputs {
+                ffmpeg_command.args(["-map", &output]);
+            }
 
-        if self.audio_enabled {
             ffmpeg_command
                 // audio
-                .args(["-codec:a", "aac", "-b:a", "128k", "-async", "1"])
-                .args([
-                    "-af",
-                    "aresample=async=1:min_hard_comp=0.100000:first_pts=0",
-                ]);
+                .args(["-codec:a", "aac", "-async", "1"])
+                .args(["-b:a", &format!("{}k", quality.audio_bitrate_kbps)]);
+
+            if separate_renditions {
+                ffmpeg_command.args(["-var_stream_map", &var_stream_map(audio_output_count)]);
+            }
         } else {
             ffmpeg_command.args(["-an"]);
         }
@@ -223,62 +505,191 @@ impl MediaRecorder {
 
         tracing::trace!("Starting FFmpeg process...");
 
-        let (ffmpeg_child, ffmpeg_stdin) = self
+        let (mut ffmpeg_child, ffmpeg_stdin) = self
             .start_ffmpeg_process(ffmpeg_command)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| {
+                CapError::new(ErrorCode::FfmpegCrashed, "Failed to start FFmpeg").caused_by(e)
+            })?;
         tracing::trace!("Ffmpeg process started");
 
-        if self.audio_enabled {
-            let capturer = self.audio_capturer.as_mut().unwrap();
-            self.audio_pipe_task = Some(tokio::spawn(capturer.collect_samples(audio_pipe_path)));
+        if let Some(stdout) = ffmpeg_child.stdout.take() {
+            self.progress = Some(ProgressMonitor::start(stdout, self.frame_rate.clone()));
         }
 
-        self.video_pipe_task = Some(tokio::spawn(video_capturer.collect_frames(video_pipe_path)));
+        for (capturer, audio_pipe_path) in self.audio_inputs.iter_mut().zip(audio_pipe_paths) {
+            let drift = capturer.drift_corrector(self.timeline.clone());
+            self.audio_pipe_tasks.push(tokio::spawn(
+                capturer.collect_samples(audio_pipe_path, drift),
+            ));
+        }
+
+        self.video_pipe_task = Some(if quality.variable_frame_rate {
+            tokio::spawn(video_capturer.collect_timed_frames(
+                video_pipe_path,
+                self.timeline.clone(),
+                input_format,
+                (output_width, output_height),
+            ))
+        } else {
+            let frame_size = input_format.frame_size(output_width, output_height);
+            let drift = DriftCorrector::new(
+                "video",
+                quality.fps as f64,
+                frame_size,
+                self.timeline.clone(),
+            );
+            tokio::spawn(video_capturer.collect_frames(
+                video_pipe_path,
+                drift,
+                input_format,
+                (output_width, output_height),
+            ))
+        });
 
         self.start_time = Some(Instant::now());
         self.chunks_dir = recording_dir.to_path_buf();
         self.ffmpeg_process = Some(ffmpeg_child);
         self.ffmpeg_stdin = Some(ffmpeg_stdin);
-        self.device_name = self.audio_capturer.as_ref().map(|c| c.device_name.clone());
+        self.report_status();
 
         tracing::info!("Media recording successfully started");
 
         Ok(())
     }
 
+    /// Stops handing captured frames and samples to FFmpeg without tearing down the
+    /// process. Both inputs are raw streams whose timestamps are derived from the
+    /// amount of data received, so whatever is dropped while paused simply doesn't
+    /// exist in the output: no frozen frames or silence where the pause happened.
+    #[tracing::instrument(skip(self))]
+    pub fn pause_media_recording(&mut self) -> Result<(), CapError> {
+        if self.start_time.is_none() {
+            return Err(CapError::new(
+                ErrorCode::IllegalTransition,
+                "Media recording has not been started.",
+            ));
+        }
+        if self.is_paused.get() {
+            return Err(CapError::new(
+                ErrorCode::IllegalTransition,
+                "Recording is already paused.",
+            ));
+        }
+
+        self.is_paused.set(true);
+        if let Ok(mut timeline) = self.timeline.lock() {
+            timeline.pause(Instant::now());
+        }
+        if let Some(encoder) = &self.in_process_encoder {
+            encoder.pause();
+        }
+        tracing::info!("Media recording paused");
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn resume_media_recording(&mut self) -> Result<(), CapError> {
+        if self.start_time.is_none() {
+            return Err(CapError::new(
+                ErrorCode::IllegalTransition,
+                "Media recording has not been started.",
+            ));
+        }
+        if !self.is_paused.get() {
+            return Err(CapError::new(
+                ErrorCode::IllegalTransition,
+                "Recording is not paused.",
+            ));
+        }
+
+        self.is_paused.set(false);
+        if let Ok(mut timeline) = self.timeline.lock() {
+            timeline.resume(Instant::now());
+        }
+        if let Some(encoder) = &self.in_process_encoder {
+            encoder.resume();
+        }
+        tracing::info!("Media recording resumed");
+
+        Ok(())
+    }
+
     /// The order of operations in this function is important!! Letting the tasks
     /// that pipe collected audio/video into FFmpeg gracefully shut down first allows
     /// us to close the ffmpeg process (and kill the cpal stream) with impunity.
     #[tracing::instrument(skip(self))]
-    pub async fn stop_media_recording(&mut self) -> Result<(), String> {
+    pub async fn stop_media_recording(&mut self) -> Result<(), CapError> {
+        // The pipe tasks only notice `should_stop` once data flows through them again
+        self.is_paused.set(false);
         self.should_stop.set(true);
 
-        if self.audio_enabled {
-            if let Some(ref mut audio_task) = self.audio_pipe_task {
-                audio_task.await.map_err(|error| error.to_string())?;
-                tracing::info!("Audio recording stopped");
+        // Whatever went wrong along the way doesn't stop the rest from shutting down, so
+        // what was recorded until then is kept
+        let mut failure = None;
+
+        let mut sync = vec![];
+        for audio_task in self.audio_pipe_tasks.drain(..) {
+            match audio_task.await.map_err(|error| error.to_string())? {
+                Ok(audio_sync) => sync.push(audio_sync),
+                Err(error) => {
+                    failure.get_or_insert(error);
+                }
             }
+        }
 
-            if let Some(ref mut audio_capturer) = self.audio_capturer {
-                audio_capturer.stop()?;
+        for audio_capturer in self.audio_inputs.iter_mut() {
+            match audio_capturer.stop() {
+                Ok(()) => {
+                    tracing::info!("Audio recording of {} stopped", audio_capturer.device_name)
+                }
+                Err(error) => {
+                    failure.get_or_insert(error);
+                }
             }
         }
 
         if let Some(ref mut video_task) = self.video_pipe_task {
-            video_task.await.map_err(|error| error.to_string())?;
+            match video_task.await.map_err(|error| error.to_string())? {
+                Ok(video_sync) => {
+                    sync.splice(0..0, video_sync);
+                }
+                Err(error) => {
+                    failure.get_or_insert(error);
+                }
+            }
             tracing::info!("Video capturing stopped");
         }
 
+        if let Some(encoder) = self.in_process_encoder.take() {
+            tracing::info!("Writing remaining segments to disk...");
+            tokio::task::spawn_blocking(move || encoder.finish())
+                .await
+                .map_err(|error| error.to_string())??;
+            tracing::info!("Successfully written all segments to disk");
+        }
+
         if let Some(ref mut stdin) = self.ffmpeg_stdin {
             tracing::info!("Shutting down recording");
-            stdin.shutdown().await.map_err(|e| e.to_string())?;
+            if let Err(error) = stdin.shutdown().await {
+                failure.get_or_insert(CapError::io("Failed to shut down FFmpeg", &error));
+            }
         }
 
         if let Some(mut process) = self.ffmpeg_process.take() {
             tracing::info!("Writing remaining segments to disk...");
             loop {
                 match process.try_wait() {
+                    Ok(Some(status)) if !status.success() => {
+                        tracing::error!("FFmpeg exited with {status}");
+                        // FFmpeg going away is what broke the pipes to it, if they did
+                        failure = Some(CapError::new(
+                            ErrorCode::FfmpegCrashed,
+                            format!("FFmpeg exited with {status}"),
+                        ));
+                        break;
+                    }
                     Ok(Some(_)) => {
                         tracing::info!("Successfully written all segments to disk");
                         break;
@@ -288,16 +699,124 @@ impl MediaRecorder {
                     }
                     Err(error) => {
                         tracing::error!("Couldn't check on FFmpeg process");
-                        return Err(error.to_string());
+                        return Err(CapError::io("Couldn't check on FFmpeg", &error));
                     }
                 }
             }
         }
 
+        for stream in &sync {
+            tracing::info!(
+                "{} ended {:.1}ms off its capture timestamps (at most {:.1}ms), {} inserted, {} removed",
+                stream.stream,
+                stream.offset_ms,
+                stream.max_offset_ms,
+                stream.inserted,
+                stream.removed
+            );
+        }
+        let queues = self.queue_depths();
+        for queue in &queues {
+            tracing::info!(
+                "{} queue peaked at {} bytes of its {} byte budget, {} buffers dropped",
+                queue.queue,
+                queue.peak_bytes,
+                queue.budget_bytes,
+                queue.dropped_buffers
+            );
+        }
+        let overflow = self.overflow().map(|overflow| overflow.to_string());
+        let adaptations = self
+            .progress
+            .take()
+            .map(|progress| progress.adaptations())
+            .unwrap_or_default();
+        self.save_session_stats(SessionStats {
+            sync,
+            queues,
+            overflow,
+            adaptations,
+        })?;
+
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+
         tracing::info!("All recording stopped.");
         Ok(())
     }
 
+    /// How much captured data is waiting to be encoded, video first.
+    pub fn queue_depths(&self) -> Vec<QueueDepth> {
+        self.queues.iter().map(|queue| queue.depth()).collect()
+    }
+
+    pub fn queue_metrics(&self) -> Vec<QueueMetrics> {
+        self.queues.clone()
+    }
+
+    /// The FFmpeg CLI, when recording through it, and whatever audio inputs run in a
+    /// process of their own.
+    pub fn child_processes(&self) -> Vec<ChildProcess> {
+        let ffmpeg = self
+            .ffmpeg_process
+            .as_ref()
+            .and_then(|process| process.id())
+            .map(|pid| ChildProcess {
+                pid,
+                name: "ffmpeg".to_string(),
+            });
+
+        ffmpeg
+            .into_iter()
+            .chain(
+                self.audio_inputs
+                    .iter()
+                    .filter_map(|input| input.child_process()),
+            )
+            .collect()
+    }
+
+    /// The last progress report of FFmpeg, when recording through it.
+    pub fn encoder_progress(&self) -> Option<EncoderProgress> {
+        self.progress.as_ref()?.latest()
+    }
+
+    /// Set once a queue has stopped the recording, with the `abort` overflow policy.
+    pub fn overflow(&self) -> Option<QueueOverflow> {
+        self.queues.iter().find_map(|queue| queue.overflow())
+    }
+
+    /// Keeps reporting on the recording until it's stopped.
+    fn report_status(&self) {
+        let (Some(app), Some(options)) = (&self.app, &self.options) else {
+            return;
+        };
+        let sources = StatusSources {
+            video_id: options.video_id.clone(),
+            recording_dir: self.chunks_dir.clone(),
+            timeline: self.timeline.clone(),
+            video_queue: self.queues.first().cloned(),
+            progress: self.progress.clone(),
+            levels: self
+                .audio_inputs
+                .iter()
+                .map(|input| input.level())
+                .collect(),
+        };
+        tokio::spawn(status::report(
+            app.clone(),
+            sources,
+            self.should_stop.clone(),
+        ));
+    }
+
+    fn save_session_stats(&self, stats: SessionStats) -> Result<(), CapError> {
+        let mut meta = RecordingMeta::load(&self.chunks_dir)?;
+        meta.session_stats = Some(stats);
+        meta.save(&self.chunks_dir)
+    }
+
     async fn start_ffmpeg_process(
         &self,
         cmd: Command,
@@ -325,17 +844,45 @@ pub fn enumerate_audio_devices() -> Vec<String> {
     devices.keys().cloned().collect()
 }
 
+#[tauri::command]
+#[specta::specta]
+#[tracing::instrument]
+pub fn enumerate_video_codecs() -> Vec<VideoCodecInfo> {
+    codec::available_codecs()
+}
+
+#[tauri::command]
+#[specta::specta]
 #[tracing::instrument]
+pub fn enumerate_displays() -> Vec<CaptureDisplay> {
+    targets::get_displays()
+}
+
+#[tauri::command]
+#[specta::specta]
+#[tracing::instrument]
+pub fn enumerate_windows() -> Vec<CaptureWindow> {
+    targets::get_windows()
+}
+
+#[tracing::instrument]
+fn pipe_error(error: Box<dyn std::error::Error>) -> CapError {
+    CapError::new(ErrorCode::Io, "Failed to create a pipe to FFmpeg").caused_by(error)
+}
+
 async fn start_recording_process(
     mut cmd: Command,
 ) -> Result<tokio::process::Child, std::io::Error> {
-    let mut process = cmd.stdin(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let mut process = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
 
     if let Some(process_stderr) = process.stderr.take() {
         tokio::spawn(async move {
             let mut process_reader = BufReader::new(process_stderr).lines();
             while let Ok(Some(line)) = process_reader.next_line().await {
-                // TODO: Replace with ingesting the output of ffmpeg's -process flag and reserve this for actual errors?
                 tracing::info!("FFmpeg process: {}", line);
             }
         });
@@ -344,69 +891,115 @@ async fn start_recording_process(
     Ok(process)
 }
 
+/// Every audio input goes through its own resampler (to fill gaps in its timestamps)
+/// and volume filter, and then either gets mixed down into a single track or mapped to
+/// an output stream of its own. `input_label` names the graph input for each of them.
+fn audio_filter_graph(
+    gains: &[f32],
+    mix: AudioMixMode,
+    input_label: impl Fn(usize) -> String,
+) -> (String, Vec<String>) {
+    let mut filters: Vec<String> = gains
+        .iter()
+        .enumerate()
+        .map(|(index, gain)| {
+            format!(
+                "[{}]aresample=async=1:min_hard_comp=0.100000:first_pts=0,volume={gain:.3}[a{index}]",
+                input_label(index),
+            )
+        })
+        .collect();
+
+    let outputs = match mix {
+        AudioMixMode::Mixed if gains.len() > 1 => {
+            let labels: String = (0..gains.len())
+                .map(|index| format!("[a{index}]"))
+                .collect();
+            filters.push(format!(
+                "{labels}amix=inputs={}:duration=longest:normalize=0[aout]",
+                gains.len()
+            ));
+            vec!["[aout]".to_string()]
+        }
+        _ => (0..gains.len())
+            .map(|index| format!("[a{index}]"))
+            .collect(),
+    };
+
+    (filters.join(";"), outputs)
+}
+
+/// Splits the output into a video rendition and one alternate audio rendition per audio
+/// stream, all in the same group so the master playlist lists them as `EXT-X-MEDIA`.
+fn var_stream_map(audio_streams: usize) -> String {
+    let mut variants = vec![format!("v:0,agroup:audio,name:{}", hls::VIDEO_RENDITION)];
+    variants.extend((0..audio_streams).map(|index| {
+        let default = if index == 0 { ",default:yes" } else { "" };
+        format!(
+            "a:{index},agroup:audio,name:{}{default}",
+            hls::audio_rendition(index)
+        )
+    }));
+
+    variants.join(" ")
+}
+
+/// Waits for every input to deliver its first data. Some sources (like WASAPI loopback
+/// while nothing is playing) can stay quiet for a while, so they're given up on after
+/// a few seconds and treated as having started then.
 #[tracing::instrument]
-async fn wait_for_start_times(
-    audio_start_time: &Mutex<Option<Instant>>,
-    video_start_time: &Mutex<Option<Instant>>,
-) -> (Instant, Instant) {
+async fn wait_for_start_times(start_times: &[SharedInstant]) -> Vec<Instant> {
+    let deadline = Instant::now() + Duration::from_secs(3);
+
     loop {
-        let audio_start_locked = audio_start_time.lock().await;
-        let video_start_locked = video_start_time.lock().await;
+        let mut instants = Vec::with_capacity(start_times.len());
+        for start_time in start_times {
+            match *start_time.lock().await {
+                Some(instant) => instants.push(instant),
+                None if Instant::now() >= deadline => {
+                    tracing::warn!("An input didn't start in time");
+                    instants.push(Instant::now());
+                }
+                None => break,
+            }
+        }
 
-        if audio_start_locked.is_some() && video_start_locked.is_some() {
-            let audio_start = *audio_start_locked.as_ref().unwrap();
-            let video_start = *video_start_locked.as_ref().unwrap();
-            return (audio_start, video_start);
+        if instants.len() == start_times.len() {
+            return instants;
         }
-        drop(audio_start_locked);
-        drop(video_start_locked);
+
         tokio::time::sleep(Duration::from_millis(50)).await;
     }
 }
 
-pub enum TimeOffsetTarget {
-    Audio,
-    Video,
-}
-
+/// Returns the `-itsoffset` arguments for each input, in the order of `start_times`.
+/// Every input is offset by how much earlier it started than the input that started last.
 #[tracing::instrument]
-async fn create_time_offset_args(
-    audio_start_time: &Mutex<Option<Instant>>,
-    video_start_time: &Mutex<Option<Instant>>,
-) -> Option<(TimeOffsetTarget, Vec<String>)> {
-    let (audio_start, video_start) = wait_for_start_times(audio_start_time, video_start_time).await;
-    let duration_difference = if audio_start > video_start {
-        audio_start.duration_since(video_start)
-    } else {
-        video_start.duration_since(audio_start)
+async fn create_time_offset_args(start_times: &[SharedInstant]) -> Vec<Option<Vec<String>>> {
+    let instants = wait_for_start_times(start_times).await;
+    let Some(latest_start) = instants.iter().max().copied() else {
+        return vec![];
     };
 
-    tracing::debug!("Duration difference: {:?}", duration_difference);
-    tracing::debug!("Audio start: {:?}", audio_start);
-    tracing::debug!("Video start: {:?}", video_start);
-
-    // Convert the duration difference to a float representing seconds
-    let offset_seconds =
-        (duration_difference.as_secs() as f64) + (duration_difference.subsec_nanos() as f64) * 1e-9;
-
-    // Depending on which started first, adjust the relevant FFmpeg command
-    if audio_start > video_start {
-        // Offset the video start time
-        tracing::info!("Applying -itsoffset {:.3} to video", offset_seconds);
-
-        Some((
-            TimeOffsetTarget::Video,
-            vec!["-itsoffset".to_string(), format!("{:.3}", offset_seconds)],
-        ))
-    } else if video_start > audio_start {
-        // Offset the audio start time
-        tracing::info!("Applying -itsoffset {:.3} to audio", offset_seconds);
-
-        Some((
-            TimeOffsetTarget::Audio,
-            vec!["-itsoffset".to_string(), format!("{:.3}", offset_seconds)],
-        ))
-    } else {
-        None
-    }
+    instants
+        .into_iter()
+        .enumerate()
+        .map(|(index, start)| {
+            let duration_difference = latest_start.duration_since(start);
+            if duration_difference.is_zero() {
+                return None;
+            }
+
+            tracing::debug!("Input {index} start: {:?}", start);
+
+            // Convert the duration difference to a float representing seconds
+            let offset_seconds = duration_difference.as_secs_f64();
+            tracing::info!("Applying -itsoffset {:.3} to input {index}", offset_seconds);
+
+            Some(vec![
+                "-itsoffset".to_string(),
+                format!("{:.3}", offset_seconds),
+            ])
+        })
+        .collect()
 }
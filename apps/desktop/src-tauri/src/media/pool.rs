@@ -0,0 +1,89 @@
+//! Buffers for captured frames and samples that go back to a pool once they've been
+//! written to the encoder, so capturing doesn't keep allocating (and page faulting in)
+//! tens of megabytes a second on large displays.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex, Weak},
+};
+
+/// Holds on to at most a fixed number of returned buffers. Anything handed out on top of
+/// that, when the encoder falls behind, is freed once it comes back.
+#[derive(Clone)]
+pub struct BufferPool(Arc<Pool>);
+
+struct Pool {
+    free: Mutex<Vec<Vec<u8>>>,
+    buffers: usize,
+}
+
+impl BufferPool {
+    pub fn new(buffers: usize) -> Self {
+        Self(Arc::new(Pool {
+            free: Mutex::new(Vec::with_capacity(buffers)),
+            buffers,
+        }))
+    }
+
+    /// An empty buffer, with the capacity of a returned one whenever there is one.
+    pub fn take(&self) -> Vec<u8> {
+        let mut free = self.0.free.lock().unwrap_or_else(|e| e.into_inner());
+        free.pop().unwrap_or_default()
+    }
+
+    /// Hands a buffer out, to be returned once every clone of it is dropped.
+    pub fn wrap(&self, data: Vec<u8>) -> Buffer {
+        Buffer(Arc::new(Inner {
+            data,
+            pool: Some(Arc::downgrade(&self.0)),
+        }))
+    }
+
+    pub fn copy_from(&self, data: &[u8]) -> Buffer {
+        let mut buffer = self.take();
+        buffer.extend_from_slice(data);
+        self.wrap(buffer)
+    }
+}
+
+impl Pool {
+    fn release(&self, mut data: Vec<u8>) {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.buffers {
+            data.clear();
+            free.push(data);
+        }
+    }
+}
+
+/// Captured data, cheap to clone.
+#[derive(Clone)]
+pub struct Buffer(Arc<Inner>);
+
+struct Inner {
+    data: Vec<u8>,
+    pool: Option<Weak<Pool>>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.as_ref().and_then(Weak::upgrade) {
+            pool.release(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0.data
+    }
+}
+
+/// For data that isn't worth pooling.
+impl From<Vec<u8>> for Buffer {
+    fn from(data: Vec<u8>) -> Self {
+        Self(Arc::new(Inner { data, pool: None }))
+    }
+}
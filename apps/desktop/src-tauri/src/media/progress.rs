@@ -0,0 +1,388 @@
+//! Follows how FFmpeg keeps up through the reports `-progress` writes, and thins out the
+//! frames handed to it when it falls behind real time, instead of letting the queues in
+//! front of it grow until they overflow.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::watch;
+
+use super::{Instant, SharedFrameRate};
+
+/// Reports are written about twice a second.
+const SLOW_REPORTS: u32 = 6;
+/// Reports left alone after an adaptation, for it to take effect.
+const SETTLE_REPORTS: u32 = 10;
+/// Encoding slower than this counts as falling behind, as speed jitters around 1.
+const SLOW_SPEED: f64 = 0.95;
+/// FFmpeg is slow to get going, which says nothing about how it'll keep up.
+const WARM_UP_SECS: f64 = 5.0;
+/// Frame rates aren't lowered any further than this.
+const MIN_FPS: u32 = 10;
+
+/// What FFmpeg last reported about its progress.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EncoderProgress {
+    pub frame: u64,
+    pub fps: f64,
+    /// How fast encoding goes compared to real time, since the start.
+    pub speed: Option<f64>,
+    pub bitrate_kbps: Option<f64>,
+    pub out_time_us: u64,
+    /// Frames FFmpeg duplicated or dropped to hold the output frame rate.
+    pub dup_frames: u64,
+    pub drop_frames: u64,
+}
+
+/// A change made to keep encoding in real time, kept in the session stats.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodingAdaptation {
+    /// Where in the recording it happened.
+    pub at_secs: f64,
+    /// How fast encoding went over the reports that led to it.
+    pub speed: f64,
+    pub from_fps: u32,
+    pub to_fps: u32,
+}
+
+/// Reads FFmpeg's progress reports while it runs.
+#[derive(Clone)]
+pub struct ProgressMonitor {
+    progress: watch::Receiver<Option<EncoderProgress>>,
+    adaptations: Arc<Mutex<Vec<EncodingAdaptation>>>,
+}
+
+impl ProgressMonitor {
+    /// Follows the `key=value` lines of `-progress`, lowering `frame_rate` whenever
+    /// encoding falls behind.
+    pub fn start(
+        output: impl AsyncRead + Unpin + Send + 'static,
+        frame_rate: SharedFrameRate,
+    ) -> Self {
+        let (sender, progress) = watch::channel(None);
+        let adaptations = Arc::new(Mutex::new(Vec::new()));
+
+        let monitor = Self {
+            progress,
+            adaptations: adaptations.clone(),
+        };
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(output).lines();
+            let mut parser = ProgressParser::default();
+            let mut adapter = Adapter::new(frame_rate);
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Some(progress) = parser.push(&line) else {
+                    continue;
+                };
+
+                if let Some(adaptation) = adapter.observe(&progress, Instant::now()) {
+                    tracing::warn!(
+                        "Encoding at {:.2}x real time, lowering the frame rate from {} to {}",
+                        adaptation.speed,
+                        adaptation.from_fps,
+                        adaptation.to_fps
+                    );
+                    adaptations
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push(adaptation);
+                }
+                tracing::trace!(?progress, "FFmpeg progress");
+                sender.send_replace(Some(progress));
+            }
+        });
+
+        monitor
+    }
+
+    pub fn latest(&self) -> Option<EncoderProgress> {
+        self.progress.borrow().clone()
+    }
+
+    pub fn adaptations(&self) -> Vec<EncodingAdaptation> {
+        self.adaptations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// Every report is a block of `key=value` lines ending with `progress=continue` (or
+/// `progress=end`).
+#[derive(Default)]
+pub struct ProgressParser {
+    current: EncoderProgress,
+}
+
+impl ProgressParser {
+    /// Returns the report once its last line is in.
+    pub fn push(&mut self, line: &str) -> Option<EncoderProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+
+        match key {
+            "frame" => self.current.frame = value.parse().unwrap_or_default(),
+            "fps" => self.current.fps = value.parse().unwrap_or_default(),
+            "bitrate" => {
+                self.current.bitrate_kbps = value
+                    .strip_suffix("kbits/s")
+                    .and_then(|value| value.trim().parse().ok())
+            }
+            "out_time_us" => self.current.out_time_us = value.parse().unwrap_or_default(),
+            "dup_frames" => self.current.dup_frames = value.parse().unwrap_or_default(),
+            "drop_frames" => self.current.drop_frames = value.parse().unwrap_or_default(),
+            "speed" => {
+                self.current.speed = value
+                    .strip_suffix('x')
+                    .and_then(|value| value.trim().parse().ok())
+            }
+            "progress" => return Some(self.current.clone()),
+            _ => {}
+        }
+
+        None
+    }
+}
+
+/// Halves the frame rate, down to `MIN_FPS`, whenever encoding stays behind real time.
+/// It's never raised again, so the frame rate doesn't keep going back and forth.
+struct Adapter {
+    frame_rate: SharedFrameRate,
+    last: Option<(u64, Instant)>,
+    slow_reports: u32,
+    slow_speed: f64,
+    settling: u32,
+}
+
+impl Adapter {
+    fn new(frame_rate: SharedFrameRate) -> Self {
+        Self {
+            frame_rate,
+            last: None,
+            slow_reports: 0,
+            slow_speed: 0.0,
+            settling: 0,
+        }
+    }
+
+    fn observe(&mut self, progress: &EncoderProgress, now: Instant) -> Option<EncodingAdaptation> {
+        // FFmpeg's own speed is averaged over the whole recording, which takes ages to
+        // show a change, so it's measured between reports instead
+        let last = self.last.replace((progress.out_time_us, now));
+        let (last_out_time, last_at) = last?;
+        let elapsed = now.duration_since(last_at).as_secs_f64();
+        let out_time = progress.out_time_us as f64 / 1_000_000.0;
+        if elapsed <= 0.0 || out_time < WARM_UP_SECS {
+            return None;
+        }
+        let speed =
+            progress.out_time_us.saturating_sub(last_out_time) as f64 / 1_000_000.0 / elapsed;
+
+        if self.settling > 0 {
+            self.settling -= 1;
+            return None;
+        }
+        if speed >= SLOW_SPEED {
+            self.slow_reports = 0;
+            self.slow_speed = 0.0;
+            return None;
+        }
+
+        self.slow_reports += 1;
+        self.slow_speed += speed;
+        if self.slow_reports < SLOW_REPORTS {
+            return None;
+        }
+
+        let speed = self.slow_speed / self.slow_reports as f64;
+        self.slow_reports = 0;
+        self.slow_speed = 0.0;
+
+        let from_fps = self.frame_rate.get();
+        let to_fps = (from_fps / 2).max(MIN_FPS);
+        if to_fps >= from_fps {
+            return None;
+        }
+
+        self.frame_rate.set(to_fps);
+        self.settling = SETTLE_REPORTS;
+
+        Some(EncodingAdaptation {
+            at_secs: out_time,
+            speed,
+            from_fps,
+            to_fps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const REPORT: &str = "frame=1520
+fps=29.97
+stream_0_0_q=23.0
+bitrate=1843.2kbits/s
+total_size=11534384
+out_time_us=50633333
+out_time_ms=50633333
+out_time=00:00:50.633333
+dup_frames=3
+drop_frames=1
+speed=0.987x
+progress=continue";
+
+    #[test]
+    fn parses_reports() {
+        let mut parser = ProgressParser::default();
+        let mut lines = REPORT.lines();
+        let reports: Vec<_> = lines
+            .by_ref()
+            .filter_map(|line| parser.push(line))
+            .collect();
+
+        assert_eq!(reports.len(), 1);
+        let progress = &reports[0];
+        assert_eq!(progress.frame, 1520);
+        assert_eq!(progress.fps, 29.97);
+        assert_eq!(progress.speed, Some(0.987));
+        assert_eq!(progress.bitrate_kbps, Some(1843.2));
+        assert_eq!(progress.out_time_us, 50_633_333);
+        assert_eq!(progress.dup_frames, 3);
+        assert_eq!(progress.drop_frames, 1);
+    }
+
+    #[test]
+    fn parses_reports_before_encoding_starts() {
+        let mut parser = ProgressParser::default();
+        let report =
+            "frame=0\nfps=0.00\nbitrate=N/A\nout_time_us=N/A\nspeed=N/A\nprogress=continue";
+        let progress = report.lines().find_map(|line| parser.push(line)).unwrap();
+
+        assert_eq!(progress.frame, 0);
+        assert_eq!(progress.speed, None);
+        assert_eq!(progress.bitrate_kbps, None);
+        assert_eq!(progress.out_time_us, 0);
+    }
+
+    #[test]
+    fn ignores_anything_but_key_values() {
+        let mut parser = ProgressParser::default();
+
+        assert!(parser.push("").is_none());
+        assert!(parser
+            .push("[hls @ 0x7f] Opening 'segment_0.ts' for writing")
+            .is_none());
+        assert!(parser.push("progress=end").is_some());
+    }
+
+    /// Feeds reports half a second apart, with the encoder getting `speed` seconds of
+    /// video through for every second.
+    struct Reports {
+        adapter: Adapter,
+        out_time: f64,
+        now: Instant,
+    }
+
+    impl Reports {
+        fn new(fps: u32) -> (Self, SharedFrameRate) {
+            let frame_rate = SharedFrameRate::default();
+            frame_rate.set(fps);
+            let reports = Self {
+                adapter: Adapter::new(frame_rate.clone()),
+                out_time: 0.0,
+                now: Instant::now(),
+            };
+
+            (reports, frame_rate)
+        }
+
+        fn next(&mut self, speed: f64) -> Option<EncodingAdaptation> {
+            self.out_time += speed * 0.5;
+            self.now += Duration::from_millis(500);
+            let progress = EncoderProgress {
+                out_time_us: (self.out_time * 1_000_000.0) as u64,
+                ..EncoderProgress::default()
+            };
+
+            self.adapter.observe(&progress, self.now)
+        }
+
+        /// The adaptations made over `reports` reports.
+        fn run(&mut self, reports: u32, speed: f64) -> Vec<EncodingAdaptation> {
+            (0..reports).filter_map(|_| self.next(speed)).collect()
+        }
+    }
+
+    #[test]
+    fn keeps_the_frame_rate_in_real_time() {
+        let (mut reports, frame_rate) = Reports::new(30);
+
+        assert!(reports.run(100, 1.0).is_empty());
+        assert!(reports.run(100, 0.97).is_empty());
+        assert_eq!(frame_rate.get(), 30);
+    }
+
+    #[test]
+    fn ignores_the_warm_up() {
+        let (mut reports, frame_rate) = Reports::new(30);
+
+        assert!(reports.run(19, 0.5).is_empty());
+        assert_eq!(frame_rate.get(), 30);
+
+        // Only reports past the warm up count
+        assert!(reports.run(SLOW_REPORTS - 1, 0.5).is_empty());
+        assert!(reports.next(0.5).is_some());
+    }
+
+    #[test]
+    fn halves_the_frame_rate_when_falling_behind() {
+        let (mut reports, frame_rate) = Reports::new(30);
+        reports.run(20, 1.0);
+
+        assert!(reports.run(SLOW_REPORTS - 1, 0.5).is_empty());
+        let adaptation = reports.next(0.5).unwrap();
+
+        assert_eq!((adaptation.from_fps, adaptation.to_fps), (30, 15));
+        assert!((adaptation.speed - 0.5).abs() < 0.01);
+        assert!((adaptation.at_secs - 10.0 - 0.25 * SLOW_REPORTS as f64).abs() < 0.01);
+        assert_eq!(frame_rate.get(), 15);
+    }
+
+    #[test]
+    fn slow_reports_have_to_be_in_a_row() {
+        let (mut reports, frame_rate) = Reports::new(30);
+        reports.run(20, 1.0);
+
+        for _ in 0..10 {
+            assert!(reports.run(SLOW_REPORTS - 1, 0.5).is_empty());
+            assert!(reports.next(1.0).is_none());
+        }
+        assert_eq!(frame_rate.get(), 30);
+    }
+
+    #[test]
+    fn settles_before_lowering_again_down_to_the_minimum() {
+        let (mut reports, frame_rate) = Reports::new(30);
+        reports.run(20, 1.0);
+        reports.run(SLOW_REPORTS, 0.5);
+        assert_eq!(frame_rate.get(), 15);
+
+        assert!(reports.run(SETTLE_REPORTS, 0.5).is_empty());
+        let adaptation = reports.run(SLOW_REPORTS, 0.5);
+        assert_eq!(adaptation.len(), 1);
+        assert_eq!(
+            (adaptation[0].from_fps, adaptation[0].to_fps),
+            (15, MIN_FPS)
+        );
+
+        assert!(reports.run(100, 0.5).is_empty());
+        assert_eq!(frame_rate.get(), MIN_FPS);
+    }
+}
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+
+use super::codec::{HlsSegmentType, VideoCodec};
+
+/// Encoding settings for a recording. Validated before any capturing starts, so a bad
+/// profile never leaves a half-started FFmpeg process behind.
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+pub struct RecordingQuality {
+    #[serde(default)]
+    pub codec: VideoCodec,
+    /// Defaults to the first segment type the codec supports when unset.
+    #[serde(default)]
+    pub segment_type: Option<HlsSegmentType>,
+    pub fps: u32,
+    /// Upper bound for the output size. Frames are scaled down (keeping their aspect
+    /// ratio) to fit, but never scaled up.
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub rate_control: RateControl,
+    pub preset: EncoderPreset,
+    pub keyframe_interval_secs: u32,
+    pub audio_bitrate_kbps: u32,
+    /// Only frames that changed get encoded, each lasting until the next one, instead
+    /// of `fps` frames every second. `fps` still caps how many there are.
+    #[serde(default)]
+    pub variable_frame_rate: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, specta::Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateControl {
+    Crf { value: u8 },
+    Bitrate { kbps: u32 },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderPreset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+}
+
+impl EncoderPreset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncoderPreset::Ultrafast => "ultrafast",
+            EncoderPreset::Superfast => "superfast",
+            EncoderPreset::Veryfast => "veryfast",
+            EncoderPreset::Faster => "faster",
+            EncoderPreset::Fast => "fast",
+            EncoderPreset::Medium => "medium",
+        }
+    }
+}
+
+impl Default for RecordingQuality {
+    fn default() -> Self {
+        Self {
+            codec: VideoCodec::default(),
+            segment_type: None,
+            fps: 30,
+            max_width: None,
+            max_height: None,
+            rate_control: RateControl::Crf { value: 23 },
+            preset: EncoderPreset::Ultrafast,
+            keyframe_interval_secs: 3,
+            audio_bitrate_kbps: 128,
+            variable_frame_rate: false,
+        }
+    }
+}
+
+impl RecordingQuality {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.codec.encoder().is_none() {
+            return Err(format!(
+                "The installed FFmpeg has no encoder for {:?}",
+                self.codec
+            ));
+        }
+
+        self.validate_settings()
+    }
+
+    /// Everything but whether FFmpeg can encode the codec.
+    pub fn validate_settings(&self) -> Result<(), String> {
+        if !self.codec.segment_types().contains(&self.segment_type()) {
+            return Err(format!(
+                "{:?} can't be stored in {:?} segments",
+                self.codec,
+                self.segment_type()
+            ));
+        }
+
+        if !(1..=60).contains(&self.fps) {
+            return Err(format!(
+                "Frame rate must be between 1 and 60, got {}",
+                self.fps
+            ));
+        }
+
+        for dimension in [self.max_width, self.max_height].into_iter().flatten() {
+            if dimension < 2 {
+                return Err(format!("Maximum output size is too small: {dimension}"));
+            }
+        }
+
+        match self.rate_control {
+            RateControl::Crf { value } if value > 51 => {
+                return Err(format!("CRF must be between 0 and 51, got {value}"));
+            }
+            RateControl::Bitrate { kbps } if !(100..=100_000).contains(&kbps) => {
+                return Err(format!(
+                    "Video bitrate must be between 100 and 100000 kbps, got {kbps}"
+                ));
+            }
+            _ => {}
+        }
+
+        if !(1..=10).contains(&self.keyframe_interval_secs) {
+            return Err(format!(
+                "Keyframe interval must be between 1 and 10 seconds, got {}",
+                self.keyframe_interval_secs
+            ));
+        }
+
+        if !(32..=320).contains(&self.audio_bitrate_kbps) {
+            return Err(format!(
+                "Audio bitrate must be between 32 and 320 kbps, got {}",
+                self.audio_bitrate_kbps
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The built-in encoder only does H.264 in fMP4 segments, whatever was asked for.
+    pub fn for_builtin_encoder(self) -> Self {
+        Self {
+            codec: VideoCodec::H264,
+            segment_type: Some(HlsSegmentType::Fmp4),
+            ..self
+        }
+    }
+
+    pub fn segment_type(&self) -> HlsSegmentType {
+        self.segment_type
+            .unwrap_or_else(|| self.codec.default_segment_type())
+    }
+
+    /// Works out the encoded size for captured frames, applying both the profile's cap
+    /// and the largest resolution the monitor supports (zero when unknown).
+    pub fn output_size(
+        &self,
+        frame_width: u32,
+        frame_height: u32,
+        max_screen_width: usize,
+        max_screen_height: usize,
+    ) -> (u32, u32) {
+        let limits = [
+            (self.max_width, self.max_height),
+            (
+                (max_screen_width > 0).then_some(max_screen_width as u32),
+                (max_screen_height > 0).then_some(max_screen_height as u32),
+            ),
+        ];
+
+        let scale = limits
+            .iter()
+            .flat_map(|(max_width, max_height)| {
+                [
+                    max_width.map(|max| max as f64 / frame_width as f64),
+                    max_height.map(|max| max as f64 / frame_height as f64),
+                ]
+            })
+            .flatten()
+            .fold(1.0, f64::min);
+
+        let width = ((frame_width as f64 * scale) as u32 & !1).max(2);
+        let height = ((frame_height as f64 * scale) as u32 & !1).max(2);
+
+        (width, height)
+    }
+
+    pub fn video_rate_args(&self) -> Vec<String> {
+        match self.rate_control {
+            RateControl::Crf { value } => vec!["-crf".to_string(), value.to_string()],
+            RateControl::Bitrate { kbps } => vec![
+                "-b:v".to_string(),
+                format!("{kbps}k"),
+                "-maxrate".to_string(),
+                format!("{kbps}k"),
+                "-bufsize".to_string(),
+                format!("{}k", kbps * 2),
+            ],
+        }
+    }
+
+    pub fn keyframe_interval_frames(&self) -> u32 {
+        self.fps * self.keyframe_interval_secs
+    }
+}
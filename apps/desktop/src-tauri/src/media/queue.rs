@@ -0,0 +1,546 @@
+//! Queues between the capture threads and the tasks feeding the encoder. They're
+//! bounded by how many bytes they hold rather than how many buffers, so a stalled
+//! encoder can't run the machine out of memory, however large the frames are.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+use tokio::sync::mpsc;
+
+use super::{pool::Buffer, Captured, Instant, SharedFlag};
+
+/// Enough for a few seconds of 4K frames.
+pub const VIDEO_BUDGET_BYTES: usize = 512 * 1024 * 1024;
+/// Minutes of audio, even for multichannel devices.
+pub const AUDIO_BUDGET_BYTES: usize = 32 * 1024 * 1024;
+/// How much more than its memory budget a queue can spill to disk.
+const SPILL_FACTOR: u64 = 8;
+
+/// What happens to captured data that doesn't fit in its queue's budget.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// The data is dropped, and counted, until the queue has drained enough.
+    #[default]
+    Drop,
+    /// The data goes to a ring buffer on disk, and only gets dropped once that's full
+    /// too.
+    Spill,
+    /// The recording stops, keeping everything recorded up to that point, and fails with
+    /// a `queue_overflow` error.
+    Abort,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueOverflow {
+    pub queue: String,
+    pub budget_bytes: usize,
+}
+
+impl fmt::Display for QueueOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The {} queue went over its {}MB budget, as the encoder couldn't keep up",
+            self.queue,
+            self.budget_bytes / (1024 * 1024)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// Dropped to stay within the budget.
+    Full,
+    /// The recording has been stopped because of this.
+    Overflow(QueueOverflow),
+    /// The receiving end is gone, as the recording has been stopped.
+    Closed,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueOptions {
+    pub policy: OverflowPolicy,
+    /// Where spilled data goes.
+    pub spill_dir: PathBuf,
+}
+
+/// How much a queue holds right now, and what it went through so far.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueDepth {
+    pub queue: String,
+    pub queued_bytes: usize,
+    pub queued_buffers: usize,
+    pub budget_bytes: usize,
+    pub peak_bytes: usize,
+    /// Held on disk rather than in memory.
+    pub spilled_bytes: u64,
+    /// Taken into the queue, in memory or spilled, since it was created.
+    pub sent_buffers: u64,
+    pub dropped_buffers: u64,
+    pub overflowed: bool,
+}
+
+enum Entry {
+    Queued(Captured),
+    Spilled { size: usize, captured_at: Instant },
+}
+
+struct Shared {
+    name: String,
+    budget_bytes: usize,
+    policy: OverflowPolicy,
+    spill_path: PathBuf,
+    should_stop: SharedFlag,
+    queued_bytes: AtomicUsize,
+    queued_buffers: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    spilled_bytes: AtomicU64,
+    sent_buffers: AtomicU64,
+    dropped_buffers: AtomicU64,
+    overflow: OnceLock<QueueOverflow>,
+    /// Only created once something has to be spilled.
+    spill: Mutex<Option<SpillRing>>,
+}
+
+pub fn channel(
+    name: &str,
+    budget_bytes: usize,
+    options: &QueueOptions,
+    should_stop: SharedFlag,
+) -> (QueueSender, QueueReceiver) {
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    let shared = Arc::new(Shared {
+        name: name.to_string(),
+        budget_bytes,
+        policy: options.policy,
+        spill_path: options.spill_dir.join(format!("{file_name}.spill")),
+        should_stop,
+        queued_bytes: AtomicUsize::new(0),
+        queued_buffers: AtomicUsize::new(0),
+        peak_bytes: AtomicUsize::new(0),
+        spilled_bytes: AtomicU64::new(0),
+        sent_buffers: AtomicU64::new(0),
+        dropped_buffers: AtomicU64::new(0),
+        overflow: OnceLock::new(),
+        spill: Mutex::new(None),
+    });
+    // The budget is what bounds the queue
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    (
+        QueueSender {
+            sender,
+            shared: shared.clone(),
+        },
+        QueueReceiver { receiver, shared },
+    )
+}
+
+pub struct QueueSender {
+    sender: mpsc::UnboundedSender<Entry>,
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    pub fn send(&self, captured: Captured) -> Result<(), SendError> {
+        let shared = &self.shared;
+        if self.sender.is_closed() {
+            return Err(SendError::Closed);
+        }
+
+        let size = captured.data.len();
+        let queued = shared.queued_bytes.load(Ordering::Acquire);
+        // A single buffer larger than the budget still goes through an empty queue
+        let entry = if queued > 0 && queued + size > shared.budget_bytes {
+            match shared.policy {
+                OverflowPolicy::Drop => return Err(shared.drop_buffer()),
+                OverflowPolicy::Spill => match shared.spill(&captured.data) {
+                    Ok(true) => Entry::Spilled {
+                        size,
+                        captured_at: captured.captured_at,
+                    },
+                    Ok(false) => return Err(shared.drop_buffer()),
+                    Err(error) => {
+                        tracing::error!("Failed to spill the {} queue: {error}", shared.name);
+                        return Err(shared.drop_buffer());
+                    }
+                },
+                OverflowPolicy::Abort => {
+                    let overflow = shared.overflow.get_or_init(|| QueueOverflow {
+                        queue: shared.name.clone(),
+                        budget_bytes: shared.budget_bytes,
+                    });
+                    shared.should_stop.set(true);
+                    return Err(SendError::Overflow(overflow.clone()));
+                }
+            }
+        } else {
+            let queued = shared.queued_bytes.fetch_add(size, Ordering::AcqRel) + size;
+            shared.queued_buffers.fetch_add(1, Ordering::Relaxed);
+            shared.peak_bytes.fetch_max(queued, Ordering::Relaxed);
+            Entry::Queued(captured)
+        };
+
+        self.sender.send(entry).map_err(|_| SendError::Closed)?;
+        shared.sent_buffers.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+pub struct QueueReceiver {
+    receiver: mpsc::UnboundedReceiver<Entry>,
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    pub async fn recv(&mut self) -> Option<Captured> {
+        loop {
+            match self.receiver.recv().await? {
+                Entry::Queued(captured) => {
+                    self.shared
+                        .queued_bytes
+                        .fetch_sub(captured.data.len(), Ordering::AcqRel);
+                    self.shared.queued_buffers.fetch_sub(1, Ordering::Relaxed);
+                    return Some(captured);
+                }
+                // Reading back is a lot faster than whatever made the queue spill, so
+                // it's not worth moving off the runtime
+                Entry::Spilled { size, captured_at } => match self.shared.unspill(size) {
+                    Ok(data) => {
+                        return Some(Captured {
+                            data: Buffer::from(data),
+                            captured_at,
+                        })
+                    }
+                    Err(error) => {
+                        tracing::error!(
+                            "Failed to read back the {} queue: {error}",
+                            self.shared.name
+                        );
+                        self.shared.dropped_buffers.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.receiver.close();
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        QueueMetrics(self.shared.clone())
+    }
+}
+
+impl Shared {
+    fn drop_buffer(&self) -> SendError {
+        if self.dropped_buffers.fetch_add(1, Ordering::Relaxed) == 0 {
+            tracing::warn!(
+                "The {} queue is over its budget, dropping data until it drains",
+                self.name
+            );
+        }
+        SendError::Full
+    }
+
+    /// `false` when the ring buffer is full as well.
+    fn spill(&self, data: &[u8]) -> io::Result<bool> {
+        let mut spill = self.spill.lock().unwrap_or_else(|e| e.into_inner());
+        let ring = match spill.as_mut() {
+            Some(ring) => ring,
+            None => {
+                tracing::warn!(
+                    "The {} queue is over its budget, spilling to {:?}",
+                    self.name,
+                    self.spill_path
+                );
+                spill.insert(SpillRing::create(
+                    &self.spill_path,
+                    self.budget_bytes as u64 * SPILL_FACTOR,
+                )?)
+            }
+        };
+
+        let spilled = ring.write(data)?;
+        if spilled {
+            self.spilled_bytes
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        Ok(spilled)
+    }
+
+    fn unspill(&self, size: usize) -> io::Result<Vec<u8>> {
+        let mut spill = self.spill.lock().unwrap_or_else(|e| e.into_inner());
+        let ring = spill
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Nothing was spilled"))?;
+
+        let data = ring.read(size)?;
+        self.spilled_bytes.fetch_sub(size as u64, Ordering::Relaxed);
+        Ok(data)
+    }
+}
+
+/// A fixed size file written and read in a circle, with buffers wrapping around its end.
+struct SpillRing {
+    file: File,
+    path: PathBuf,
+    capacity: u64,
+    /// Total bytes written and read, the file offset being these modulo the capacity.
+    written: u64,
+    read: u64,
+}
+
+impl SpillRing {
+    fn create(path: &Path, capacity: u64) -> io::Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            capacity,
+            written: 0,
+            read: 0,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<bool> {
+        if self.written - self.read + data.len() as u64 > self.capacity {
+            return Ok(false);
+        }
+
+        let (first, second) = data.split_at(self.contiguous(self.written, data.len()));
+        self.file
+            .seek(SeekFrom::Start(self.written % self.capacity))?;
+        self.file.write_all(first)?;
+        if !second.is_empty() {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(second)?;
+        }
+
+        self.written += data.len() as u64;
+        Ok(true)
+    }
+
+    fn read(&mut self, size: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; size];
+
+        let (first, second) = data.split_at_mut(self.contiguous(self.read, size));
+        self.file.seek(SeekFrom::Start(self.read % self.capacity))?;
+        self.file.read_exact(first)?;
+        if !second.is_empty() {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.read_exact(second)?;
+        }
+
+        self.read += size as u64;
+        Ok(data)
+    }
+
+    /// How much of a buffer at `offset` fits before the end of the file.
+    fn contiguous(&self, offset: u64, size: usize) -> usize {
+        let until_end = self.capacity - offset % self.capacity;
+        size.min(until_end as usize)
+    }
+}
+
+impl Drop for SpillRing {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// A handle for reading a queue's depth while it's in use.
+#[derive(Clone)]
+pub struct QueueMetrics(Arc<Shared>);
+
+impl QueueMetrics {
+    pub fn depth(&self) -> QueueDepth {
+        let shared = &self.0;
+        QueueDepth {
+            queue: shared.name.clone(),
+            queued_bytes: shared.queued_bytes.load(Ordering::Acquire),
+            queued_buffers: shared.queued_buffers.load(Ordering::Relaxed),
+            budget_bytes: shared.budget_bytes,
+            peak_bytes: shared.peak_bytes.load(Ordering::Relaxed),
+            spilled_bytes: shared.spilled_bytes.load(Ordering::Relaxed),
+            sent_buffers: shared.sent_buffers.load(Ordering::Relaxed),
+            dropped_buffers: shared.dropped_buffers.load(Ordering::Relaxed),
+            overflowed: shared.overflow.get().is_some(),
+        }
+    }
+
+    /// Set once the queue has stopped the recording by going over its budget.
+    pub fn overflow(&self) -> Option<QueueOverflow> {
+        self.0.overflow.get().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: usize = 100;
+
+    fn queue(name: &str, policy: OverflowPolicy) -> (QueueSender, QueueReceiver, SharedFlag) {
+        let options = QueueOptions {
+            policy,
+            spill_dir: std::env::temp_dir(),
+        };
+        let should_stop = SharedFlag::default();
+        let (sender, receiver) = channel(name, BUDGET, &options, should_stop.clone());
+        (sender, receiver, should_stop)
+    }
+
+    fn captured(byte: u8, size: usize) -> Captured {
+        Captured {
+            data: Buffer::from(vec![byte; size]),
+            captured_at: Instant::now(),
+        }
+    }
+
+    fn receive(receiver: &mut QueueReceiver, count: usize) -> Vec<Vec<u8>> {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut received = vec![];
+            for _ in 0..count {
+                received.push(receiver.recv().await.unwrap().data.to_vec());
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn counts_queued_bytes() {
+        let (sender, mut receiver, _) = queue("counting test", OverflowPolicy::Drop);
+        let metrics = receiver.metrics();
+
+        sender.send(captured(1, 30)).unwrap();
+        sender.send(captured(2, 50)).unwrap();
+        let depth = metrics.depth();
+        assert_eq!((depth.queued_bytes, depth.queued_buffers), (80, 2));
+
+        receive(&mut receiver, 1);
+        let depth = metrics.depth();
+        assert_eq!((depth.queued_bytes, depth.queued_buffers), (50, 1));
+        assert_eq!((depth.peak_bytes, depth.sent_buffers), (80, 2));
+    }
+
+    #[test]
+    fn takes_oversized_buffer_into_empty_queue() {
+        let (sender, mut receiver, _) = queue("oversized test", OverflowPolicy::Drop);
+
+        sender.send(captured(1, BUDGET * 2)).unwrap();
+        assert!(matches!(sender.send(captured(2, 1)), Err(SendError::Full)));
+        assert_eq!(receive(&mut receiver, 1), [vec![1; BUDGET * 2]]);
+    }
+
+    #[test]
+    fn drops_when_over_budget() {
+        let (sender, mut receiver, should_stop) = queue("drop test", OverflowPolicy::Drop);
+        let metrics = receiver.metrics();
+
+        sender.send(captured(1, 60)).unwrap();
+        assert!(matches!(sender.send(captured(2, 60)), Err(SendError::Full)));
+        assert!(matches!(sender.send(captured(3, 60)), Err(SendError::Full)));
+        let depth = metrics.depth();
+        assert_eq!((depth.queued_bytes, depth.dropped_buffers), (60, 2));
+        assert!(!should_stop.get());
+
+        // There's room again once the queue has drained
+        assert_eq!(receive(&mut receiver, 1), [vec![1; 60]]);
+        sender.send(captured(4, 60)).unwrap();
+        assert_eq!(receive(&mut receiver, 1), [vec![4; 60]]);
+    }
+
+    #[test]
+    fn spills_to_disk_in_order() {
+        let (sender, mut receiver, _) = queue("spill test", OverflowPolicy::Spill);
+        let metrics = receiver.metrics();
+        let spill_path = std::env::temp_dir().join("spill_test.spill");
+
+        for byte in 1..=4 {
+            sender.send(captured(byte, 60)).unwrap();
+        }
+        let depth = metrics.depth();
+        assert_eq!((depth.queued_bytes, depth.spilled_bytes), (60, 180));
+        assert_eq!(depth.dropped_buffers, 0);
+        assert!(spill_path.exists());
+
+        let received = receive(&mut receiver, 4);
+        assert_eq!(
+            received,
+            (1..=4).map(|byte| vec![byte; 60]).collect::<Vec<_>>()
+        );
+        assert_eq!(metrics.depth().spilled_bytes, 0);
+
+        drop((sender, receiver, metrics));
+        assert!(!spill_path.exists());
+    }
+
+    #[test]
+    fn drops_once_spill_is_full() {
+        let (sender, _receiver, _) = queue("full spill test", OverflowPolicy::Spill);
+
+        sender.send(captured(1, 60)).unwrap();
+        for _ in 0..SPILL_FACTOR {
+            sender.send(captured(2, BUDGET)).unwrap();
+        }
+        assert!(matches!(
+            sender.send(captured(3, BUDGET)),
+            Err(SendError::Full)
+        ));
+    }
+
+    #[test]
+    fn wraps_spill_ring_around() {
+        let path = std::env::temp_dir().join("ring_test.spill");
+        let mut ring = SpillRing::create(&path, 10).unwrap();
+
+        assert!(ring.write(&[1, 2, 3, 4, 5, 6]).unwrap());
+        assert!(!ring.write(&[7; 5]).unwrap());
+        assert_eq!(ring.read(6).unwrap(), [1, 2, 3, 4, 5, 6]);
+
+        assert!(ring.write(&[7, 8, 9, 10, 11, 12, 13]).unwrap());
+        assert_eq!(ring.read(7).unwrap(), [7, 8, 9, 10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn aborts_when_over_budget() {
+        let (sender, receiver, should_stop) = queue("abort test", OverflowPolicy::Abort);
+        let metrics = receiver.metrics();
+
+        sender.send(captured(1, 60)).unwrap();
+        let Err(SendError::Overflow(overflow)) = sender.send(captured(2, 60)) else {
+            panic!("The queue should have overflowed");
+        };
+
+        let expected = QueueOverflow {
+            queue: "abort test".to_string(),
+            budget_bytes: BUDGET,
+        };
+        assert_eq!(overflow, expected);
+        assert_eq!(metrics.overflow(), Some(expected));
+        assert!(metrics.depth().overflowed);
+        assert!(should_stop.get());
+    }
+}
@@ -0,0 +1,124 @@
+//! Events telling the frontend what the recorder is up to while it records, emitted at a
+//! bounded rate so a busy recording doesn't flood the webview.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use tauri_specta::Event;
+use tokio::time::Duration;
+
+use super::audio::LevelMeter;
+use super::progress::ProgressMonitor;
+use super::queue::QueueMetrics;
+use super::sync::SharedTimeline;
+use super::{Instant, SharedFlag};
+
+/// Minimum time between two status events.
+pub const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingStatus {
+    pub video_id: String,
+    /// Not counting pauses.
+    pub elapsed_secs: f64,
+    /// Frames handed to the encoder per second, since the last event.
+    pub fps: f64,
+    /// Frames dropped because the encoder couldn't keep up, since the start.
+    pub dropped_frames: u64,
+    /// The loudest sample of every audio input since the last event, between 0 and 1,
+    /// after its gain.
+    pub audio_levels: Vec<f32>,
+    /// What the encoder has written to the recording directory.
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, specta::Type, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadStatus {
+    pub video_id: String,
+    pub uploaded: u32,
+    /// Written by the encoder and waiting on (or in the middle of) their upload.
+    pub pending: u32,
+    pub failed: u32,
+}
+
+pub fn emit<E: Event + Serialize + Clone>(app: &AppHandle, event: E) {
+    if let Err(error) = event.emit(app) {
+        tracing::warn!("Failed to emit a status event: {error}");
+    }
+}
+
+/// What the status of a recording is put together from.
+pub struct StatusSources {
+    pub video_id: String,
+    pub recording_dir: PathBuf,
+    pub timeline: SharedTimeline,
+    /// The video queue, whose buffers are frames.
+    pub video_queue: Option<QueueMetrics>,
+    pub progress: Option<ProgressMonitor>,
+    pub levels: Vec<LevelMeter>,
+}
+
+/// Emits the status of a recording until it's stopped.
+pub async fn report(app: AppHandle, sources: StatusSources, should_stop: SharedFlag) {
+    let mut last_frames = None;
+    let mut last_at = Instant::now();
+
+    while !should_stop.get() {
+        tokio::time::sleep(STATUS_INTERVAL).await;
+
+        let now = Instant::now();
+        let video = sources.video_queue.as_ref().map(|queue| queue.depth());
+        let frames = video.as_ref().map_or(0, |video| video.sent_buffers);
+        let fps = match last_frames.replace(frames) {
+            Some(last_frames) => {
+                frames.saturating_sub(last_frames) as f64 / (now - last_at).as_secs_f64()
+            }
+            None => 0.0,
+        };
+        last_at = now;
+
+        let encoder_dropped = sources
+            .progress
+            .as_ref()
+            .and_then(|progress| progress.latest())
+            .map_or(0, |progress| progress.drop_frames);
+        let elapsed = sources
+            .timeline
+            .lock()
+            .ok()
+            .and_then(|timeline| timeline.position(now))
+            .unwrap_or_default();
+
+        emit(
+            &app,
+            RecordingStatus {
+                video_id: sources.video_id.clone(),
+                elapsed_secs: elapsed.as_secs_f64(),
+                fps,
+                dropped_frames: video.map_or(0, |video| video.dropped_buffers) + encoder_dropped,
+                audio_levels: sources.levels.iter().map(|level| level.take()).collect(),
+                bytes_written: bytes_written(&sources.recording_dir),
+            },
+        );
+    }
+}
+
+/// The size of every playlist and segment written so far.
+fn bytes_written(recording_dir: &Path) -> u64 {
+    let Ok(files) = std::fs::read_dir(recording_dir) else {
+        return 0;
+    };
+
+    files
+        .flatten()
+        .filter(|file| {
+            file.path().extension().is_some_and(|extension| {
+                ["m3u8", "ts", "m4s", "mp4"].iter().any(|e| extension == *e)
+            })
+        })
+        .filter_map(|file| file.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
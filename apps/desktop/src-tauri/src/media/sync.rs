@@ -0,0 +1,367 @@
+//! Keeps streams timed by the amount of data in them in step with when that data was
+//! captured. Audio devices run on clocks of their own and screen capture doesn't always
+//! deliver frames at the rate asked for, so over a long recording, counting samples
+//! and frames slowly drifts away from the capture timestamps.
+
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::Instant;
+use crate::metadata::StreamSync;
+
+pub type SharedTimeline = Arc<Mutex<Timeline>>;
+
+/// How much of each new audio drift measurement goes into the smoothed one, as the
+/// timing of individual buffers is far noisier than the drift itself.
+const SMOOTHING: f64 = 0.05;
+/// Audio drift tolerated before correcting it.
+const AUDIO_THRESHOLD: f64 = 0.02;
+/// At most this fraction of a buffer is added or removed at once, so the correction
+/// stays inaudible.
+const MAX_STRETCH: f64 = 0.005;
+
+/// Maps capture instants onto the recording's timeline, which stands still while the
+/// recording is paused.
+#[derive(Default)]
+pub struct Timeline {
+    start: Option<Instant>,
+    paused_at: Option<Instant>,
+    pauses: Vec<(Instant, Instant)>,
+}
+
+impl Timeline {
+    pub fn starting_at(start: Instant) -> Self {
+        Self {
+            start: Some(start),
+            ..Self::default()
+        }
+    }
+
+    /// Where a video frame lands on the timeline, starting it with the first one.
+    pub fn video_position(&mut self, captured_at: Instant) -> Option<Duration> {
+        self.start.get_or_insert(captured_at);
+        self.position(captured_at)
+    }
+
+    /// `None` for anything captured before the timeline started.
+    pub fn position(&self, instant: Instant) -> Option<Duration> {
+        let start = self.start?;
+        let mut position = instant.checked_duration_since(start)?;
+
+        for (paused_at, resumed_at) in &self.pauses {
+            if instant >= *resumed_at {
+                position = position.saturating_sub(*resumed_at - *paused_at);
+            } else if instant > *paused_at {
+                position = position.saturating_sub(instant - *paused_at);
+            }
+        }
+        if let Some(paused_at) = self.paused_at {
+            position = position.saturating_sub(instant.saturating_duration_since(paused_at));
+        }
+
+        Some(position)
+    }
+
+    pub fn pause(&mut self, instant: Instant) {
+        self.paused_at.get_or_insert(instant);
+    }
+
+    pub fn resume(&mut self, instant: Instant) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.pauses.push((paused_at, instant));
+        }
+    }
+}
+
+/// Measures how far a stream's data is ahead of (positive) or behind its capture
+/// timestamps, and corrects it by repeating or dropping video frames, or stretching
+/// audio buffers by a few samples.
+pub struct DriftCorrector {
+    timeline: SharedTimeline,
+    /// Video frames or audio samples per second.
+    rate: f64,
+    /// Size of a video frame or a sample for every channel, in bytes.
+    unit_size: usize,
+    /// Timeline position of the stream's first unit.
+    start: Option<f64>,
+    written: u64,
+    /// How much of the timeline the video frames written so far cover, as the frame
+    /// rate can change along the way.
+    written_secs: f64,
+    drift: f64,
+    stats: StreamSync,
+}
+
+impl DriftCorrector {
+    pub fn new(stream: &str, rate: f64, unit_size: usize, timeline: SharedTimeline) -> Self {
+        Self {
+            timeline,
+            rate,
+            unit_size,
+            start: None,
+            written: 0,
+            written_secs: 0.0,
+            drift: 0.0,
+            stats: StreamSync {
+                stream: stream.to_string(),
+                ..StreamSync::default()
+            },
+        }
+    }
+
+    /// Frames from now on are written at this rate, for when encoding fell behind.
+    pub fn set_frame_rate(&mut self, rate: f64) {
+        self.rate = rate;
+    }
+
+    /// When to write a video frame out, relative to the first one: not at all to catch up
+    /// with the clock, or more than once when frames didn't come in fast enough.
+    pub fn frame_timestamps(&mut self, captured_at: Instant) -> Vec<Duration> {
+        let repeats = match self.position(captured_at) {
+            Some(position) => {
+                let start = *self.start.get_or_insert(position);
+
+                // Frames that should have been written before this one
+                let ahead = (self.written_secs - (position - start)) * self.rate;
+                self.measure(ahead / self.rate);
+
+                // A frame of leeway keeps capture jitter from causing corrections
+                match ahead {
+                    ahead if ahead > 1.0 => 0,
+                    ahead if ahead < -1.0 => 1 + (-ahead).floor().min(self.rate) as usize,
+                    _ => 1,
+                }
+            }
+            None => 1,
+        };
+
+        match repeats {
+            0 => self.stats.removed += 1,
+            repeats => self.stats.inserted += repeats as u64 - 1,
+        }
+
+        (0..repeats)
+            .map(|_| {
+                let timestamp = Duration::from_secs_f64(self.written_secs);
+                self.written += 1;
+                self.written_secs += 1.0 / self.rate;
+                timestamp
+            })
+            .collect()
+    }
+
+    /// Stretches or squeezes a buffer of audio samples when the stream drifted off,
+    /// leaving it untouched otherwise.
+    pub fn correct_samples<'a>(&mut self, data: &'a [u8], captured_at: Instant) -> Cow<'a, [u8]> {
+        let units = data.len() / self.unit_size;
+        let Some(position) = self.position(captured_at) else {
+            return Cow::Borrowed(data);
+        };
+
+        // Capture timestamps mark the end of the buffer
+        let duration = units as f64 / self.rate;
+        let start = *self.start.get_or_insert((position - duration).max(0.0));
+        let expected = position - start;
+        let ahead = (self.written + units as u64) as f64 / self.rate - expected;
+
+        self.drift += (ahead - self.drift) * SMOOTHING;
+        self.measure(self.drift);
+
+        let mut adjustment = 0;
+        if self.drift.abs() > AUDIO_THRESHOLD && units > 0 {
+            let limit = ((units as f64 * MAX_STRETCH) as i64).max(1);
+            adjustment = (-self.drift * self.rate)
+                .round()
+                .clamp(-limit as f64, limit as f64) as i64;
+            self.drift += adjustment as f64 / self.rate;
+        }
+
+        let target = (units as i64 + adjustment) as usize;
+        if adjustment > 0 {
+            self.stats.inserted += adjustment as u64;
+        } else {
+            self.stats.removed += (-adjustment) as u64;
+        }
+        self.written += target as u64;
+
+        match adjustment {
+            0 => Cow::Borrowed(data),
+            _ => Cow::Owned(resample(data, self.unit_size, units, target)),
+        }
+    }
+
+    pub fn stats(&self) -> StreamSync {
+        self.stats.clone()
+    }
+
+    fn position(&self, captured_at: Instant) -> Option<f64> {
+        let timeline = self.timeline.lock().ok()?;
+        timeline
+            .position(captured_at)
+            .map(|position| position.as_secs_f64())
+    }
+
+    fn measure(&mut self, offset: f64) {
+        let offset_ms = offset * 1000.0;
+        self.stats.offset_ms = offset_ms;
+        if offset_ms.abs() > self.stats.max_offset_ms.abs() {
+            self.stats.max_offset_ms = offset_ms;
+        }
+    }
+}
+
+/// Nearest neighbour resampling, which is inaudible for the handful of samples a
+/// correction adds or removes, and works for any sample format.
+fn resample(data: &[u8], unit_size: usize, units: usize, target: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(target * unit_size);
+    for index in 0..target {
+        let source = index * units / target;
+        output.extend_from_slice(&data[source * unit_size..(source + 1) * unit_size]);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corrector(rate: f64, unit_size: usize, start: Instant) -> DriftCorrector {
+        let timeline = Arc::new(Mutex::new(Timeline::starting_at(start)));
+        DriftCorrector::new("test", rate, unit_size, timeline)
+    }
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn leaves_out_pauses() {
+        let start = Instant::now();
+        let mut timeline = Timeline::starting_at(start);
+
+        timeline.pause(start + secs(2.0));
+        assert_eq!(timeline.position(start + secs(1.0)), Some(secs(1.0)));
+        // The timeline stands still until it's resumed
+        assert_eq!(timeline.position(start + secs(4.0)), Some(secs(2.0)));
+
+        timeline.resume(start + secs(5.0));
+        assert_eq!(timeline.position(start + secs(3.0)), Some(secs(2.0)));
+        assert_eq!(timeline.position(start + secs(6.0)), Some(secs(3.0)));
+
+        timeline.pause(start + secs(7.0));
+        timeline.resume(start + secs(8.0));
+        assert_eq!(timeline.position(start + secs(10.0)), Some(secs(6.0)));
+    }
+
+    #[test]
+    fn starts_with_first_video_frame() {
+        let start = Instant::now();
+        let mut timeline = Timeline::default();
+
+        assert_eq!(timeline.position(start), None);
+        assert_eq!(timeline.video_position(start + secs(1.0)), Some(secs(0.0)));
+        assert_eq!(timeline.position(start), None);
+        assert_eq!(timeline.position(start + secs(1.5)), Some(secs(0.5)));
+    }
+
+    #[test]
+    fn repeats_frames_of_slow_source() {
+        let start = Instant::now();
+        let mut drift = corrector(30.0, 4, start);
+
+        // Frames arrive at 10 fps for a 30 fps recording
+        let mut timestamps = vec![];
+        for index in 0..20 {
+            let written = drift.frame_timestamps(start + secs(index as f64 / 10.0));
+            assert!(!written.is_empty());
+            timestamps.extend(written);
+        }
+
+        // Two seconds' worth, give or take the frame of leeway
+        assert!(
+            (57..=60).contains(&timestamps.len()),
+            "{}",
+            timestamps.len()
+        );
+        for (index, timestamp) in timestamps.iter().enumerate() {
+            assert!((timestamp.as_secs_f64() - index as f64 / 30.0).abs() < 1e-6);
+        }
+        assert_eq!(drift.stats().inserted, timestamps.len() as u64 - 20);
+        assert_eq!(drift.stats().removed, 0);
+    }
+
+    #[test]
+    fn drops_frames_of_fast_source() {
+        let start = Instant::now();
+        let mut drift = corrector(30.0, 4, start);
+
+        // Frames arrive at 60 fps for a 30 fps recording
+        let mut timestamps = vec![];
+        for index in 0..120 {
+            timestamps.extend(drift.frame_timestamps(start + secs(index as f64 / 60.0)));
+        }
+
+        // Two seconds' worth, give or take the frame of leeway
+        assert!(
+            (59..=62).contains(&timestamps.len()),
+            "{}",
+            timestamps.len()
+        );
+        assert_eq!(drift.stats().removed, 120 - timestamps.len() as u64);
+        assert_eq!(drift.stats().inserted, 0);
+        assert!(drift.stats().max_offset_ms.abs() <= 1000.0 / 30.0 * 2.0);
+    }
+
+    #[test]
+    fn stretches_audio_by_at_most_max_stretch() {
+        let start = Instant::now();
+        let unit_size = 4;
+        let units = 480;
+        let mut drift = corrector(48_000.0, unit_size, start);
+        let data: Vec<u8> = (0..units * unit_size).map(|byte| byte as u8).collect();
+        let limit = (units as f64 * MAX_STRETCH) as usize;
+
+        // The device delivers 10ms of samples every 10.5ms, so it falls behind
+        let mut corrected_units = vec![];
+        for index in 1..=400 {
+            let captured_at = start + secs(index as f64 * 0.0105);
+            let corrected = drift.correct_samples(&data, captured_at);
+            assert_eq!(corrected.len() % unit_size, 0);
+            corrected_units.push(corrected.len() / unit_size);
+        }
+
+        assert!(corrected_units
+            .iter()
+            .all(|corrected| (units..=units + limit).contains(corrected)));
+        assert!(corrected_units.iter().any(|corrected| *corrected > units));
+        assert!(drift.stats().inserted > 0);
+        assert_eq!(drift.stats().removed, 0);
+    }
+
+    #[test]
+    fn leaves_audio_in_sync_alone() {
+        let start = Instant::now();
+        let mut drift = corrector(48_000.0, 4, start);
+        let data = vec![0; 480 * 4];
+
+        for index in 1..=100 {
+            let captured_at = start + secs(index as f64 * 0.01);
+            assert!(matches!(
+                drift.correct_samples(&data, captured_at),
+                Cow::Borrowed(_)
+            ));
+        }
+        assert_eq!(drift.stats().inserted + drift.stats().removed, 0);
+    }
+
+    #[test]
+    fn resamples_whole_units() {
+        let data = [1, 1, 2, 2, 3, 3, 4, 4];
+
+        assert_eq!(resample(&data, 2, 4, 5), [1, 1, 1, 1, 2, 2, 3, 3, 4, 4]);
+        assert_eq!(resample(&data, 2, 4, 3), [1, 1, 2, 2, 3, 3]);
+    }
+}
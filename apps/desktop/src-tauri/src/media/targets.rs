@@ -0,0 +1,323 @@
+use scap::{
+    capturer::{Area, Point, Size},
+    targets, Target,
+};
+use serde::Serialize;
+
+use crate::error::{CapError, ErrorCode};
+use crate::recording::{CaptureMode, CaptureRegion};
+
+#[derive(Debug, Serialize, Clone, specta::Type)]
+pub struct CaptureDisplay {
+    pub id: u32,
+    pub name: String,
+    /// In pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Pixels per point.
+    pub scale_factor: f64,
+}
+
+impl CaptureDisplay {
+    fn from_target(target: &Target) -> Option<Self> {
+        let Target::Display(display) = target else {
+            return None;
+        };
+
+        let (width, height) = targets::get_target_dimensions(target);
+
+        Some(Self {
+            id: display.id,
+            name: display.title.clone(),
+            width: width as u32,
+            height: height as u32,
+            scale_factor: targets::get_scale_factor(target),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone, specta::Type)]
+pub struct WindowBounds {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Serialize, Clone, specta::Type)]
+pub struct CaptureWindow {
+    pub id: u32,
+    pub title: String,
+    pub owner: Option<String>,
+    pub bounds: Option<WindowBounds>,
+}
+
+impl CaptureWindow {
+    fn from_target(target: &Target) -> Option<Self> {
+        let Target::Window(window) = target else {
+            return None;
+        };
+
+        let (owner, bounds) = platform::window_details(window);
+
+        Some(Self {
+            id: window.id,
+            title: window.title.clone(),
+            owner,
+            bounds,
+        })
+    }
+}
+
+pub fn get_displays() -> Vec<CaptureDisplay> {
+    scap::get_all_targets()
+        .iter()
+        .filter_map(CaptureDisplay::from_target)
+        .collect()
+}
+
+pub fn get_windows() -> Vec<CaptureWindow> {
+    scap::get_all_targets()
+        .iter()
+        .filter_map(CaptureWindow::from_target)
+        .collect()
+}
+
+pub fn find_target(screen_id: &str, capture_mode: CaptureMode) -> Result<Target, CapError> {
+    match capture_mode {
+        CaptureMode::Screen => find_display(screen_id),
+        CaptureMode::Window { id } => find_window(id),
+    }
+}
+
+/// Checks that a region fits within the display being captured and converts it into
+/// a crop area for scap, which takes it in points too. The size is rounded down to an
+/// even number of pixels, which the encoder requires for 4:2:0 chroma subsampling.
+pub fn crop_area_for(target: &Target, region: &CaptureRegion) -> Result<Area, CapError> {
+    if let Target::Window(_) = target {
+        return Err(CapError::new(
+            ErrorCode::InvalidOptions,
+            "Regions can only be recorded from a display",
+        ));
+    }
+
+    let (display_width, display_height) = targets::get_target_dimensions(target);
+    let region = fit_region(
+        region,
+        display_width as f64,
+        display_height as f64,
+        targets::get_scale_factor(target),
+    )?;
+
+    Ok(Area {
+        origin: Point {
+            x: region.x,
+            y: region.y,
+        },
+        size: Size {
+            width: region.width,
+            height: region.height,
+        },
+    })
+}
+
+/// Fits a region in points to a display measured in pixels.
+fn fit_region(
+    region: &CaptureRegion,
+    display_width: f64,
+    display_height: f64,
+    scale_factor: f64,
+) -> Result<CaptureRegion, CapError> {
+    let invalid = |message: String| CapError::new(ErrorCode::InvalidOptions, message);
+
+    let CaptureRegion {
+        x,
+        y,
+        width,
+        height,
+    } = *region;
+    if ![x, y, width, height].iter().all(|value| value.is_finite()) {
+        return Err(invalid(format!("Invalid capture region {region:?}")));
+    }
+    let scale_factor = if scale_factor.is_finite() && scale_factor > 0.0 {
+        scale_factor
+    } else {
+        1.0
+    };
+
+    let pixel_width = ((width * scale_factor).floor() as u32 & !1) as f64;
+    let pixel_height = ((height * scale_factor).floor() as u32 & !1) as f64;
+    if x < 0.0 || y < 0.0 || pixel_width < 2.0 || pixel_height < 2.0 {
+        return Err(invalid(format!("Invalid capture region {region:?}")));
+    }
+
+    if x * scale_factor + pixel_width > display_width
+        || y * scale_factor + pixel_height > display_height
+    {
+        return Err(invalid(format!(
+            "Capture region {region:?} is outside of the {}x{} point display",
+            display_width / scale_factor,
+            display_height / scale_factor
+        )));
+    }
+
+    Ok(CaptureRegion {
+        x,
+        y,
+        width: pixel_width / scale_factor,
+        height: pixel_height / scale_factor,
+    })
+}
+
+/// Resolves the display selected in the recording options, by an id from
+/// `enumerate_displays`.
+fn find_display(screen_id: &str) -> Result<Target, CapError> {
+    let id = screen_id.trim().parse::<u32>().map_err(|_| {
+        CapError::new(
+            ErrorCode::InvalidOptions,
+            format!("{screen_id:?} isn't a display id"),
+        )
+    })?;
+
+    scap::get_all_targets()
+        .into_iter()
+        .find(|target| matches!(target, Target::Display(display) if display.id == id))
+        .ok_or_else(|| {
+            CapError::new(
+                ErrorCode::DeviceGone,
+                format!("Display {id} is not available for capture"),
+            )
+        })
+}
+
+fn find_window(id: u32) -> Result<Target, CapError> {
+    scap::get_all_targets()
+        .into_iter()
+        .find(|target| matches!(target, Target::Window(window) if window.id == id))
+        .ok_or_else(|| {
+            CapError::new(
+                ErrorCode::DeviceGone,
+                format!("Window {id} is not available for capture"),
+            )
+        })
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use core_foundation::{
+        base::{CFType, TCFType},
+        dictionary::{CFDictionary, CFDictionaryRef},
+        string::CFString,
+    };
+    use core_graphics::{
+        geometry::CGRect,
+        window::{
+            copy_window_info, kCGWindowBounds, kCGWindowListOptionIncludingWindow,
+            kCGWindowOwnerName,
+        },
+    };
+
+    use super::WindowBounds;
+
+    pub fn window_details(
+        window: &scap::targets::Window,
+    ) -> (Option<String>, Option<WindowBounds>) {
+        let Some(info) = copy_window_info(kCGWindowListOptionIncludingWindow, window.id) else {
+            return (None, None);
+        };
+        let Some(entry) = info.get(0) else {
+            return (None, None);
+        };
+
+        let entry = unsafe {
+            CFDictionary::<CFString, CFType>::wrap_under_get_rule(*entry as CFDictionaryRef)
+        };
+
+        let owner = entry
+            .find(unsafe { CFString::wrap_under_get_rule(kCGWindowOwnerName) })
+            .and_then(|name| name.downcast::<CFString>())
+            .map(|name| name.to_string());
+
+        let bounds = entry
+            .find(unsafe { CFString::wrap_under_get_rule(kCGWindowBounds) })
+            .and_then(|bounds| bounds.downcast::<CFDictionary>())
+            .and_then(|bounds| CGRect::from_dict_representation(&bounds))
+            .map(|rect| WindowBounds {
+                x: rect.origin.x,
+                y: rect.origin.y,
+                width: rect.size.width,
+                height: rect.size.height,
+            });
+
+        (owner, bounds)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt, path::Path};
+    use winapi::{
+        shared::windef::{HWND, RECT},
+        um::{
+            handleapi::CloseHandle,
+            processthreadsapi::OpenProcess,
+            winbase::QueryFullProcessImageNameW,
+            winnt::PROCESS_QUERY_LIMITED_INFORMATION,
+            winuser::{GetWindowRect, GetWindowThreadProcessId},
+        },
+    };
+
+    use super::WindowBounds;
+
+    pub fn window_details(
+        window: &scap::targets::Window,
+    ) -> (Option<String>, Option<WindowBounds>) {
+        let hwnd = window.raw_handle.0 as HWND;
+
+        let mut rect: RECT = unsafe { std::mem::zeroed() };
+        let bounds = (unsafe { GetWindowRect(hwnd, &mut rect) } != 0).then(|| WindowBounds {
+            x: rect.left as f64,
+            y: rect.top as f64,
+            width: (rect.right - rect.left) as f64,
+            height: (rect.bottom - rect.top) as f64,
+        });
+
+        let mut process_id = 0;
+        unsafe { GetWindowThreadProcessId(hwnd, &mut process_id) };
+
+        (owning_process_name(process_id), bounds)
+    }
+
+    fn owning_process_name(process_id: u32) -> Option<String> {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id) };
+        if process.is_null() {
+            return None;
+        }
+
+        let mut buffer = [0u16; 1024];
+        let mut length = buffer.len() as u32;
+        let success =
+            unsafe { QueryFullProcessImageNameW(process, 0, buffer.as_mut_ptr(), &mut length) };
+        unsafe { CloseHandle(process) };
+
+        if success == 0 {
+            return None;
+        }
+
+        let path = OsString::from_wide(&buffer[..length as usize]);
+        Path::new(&path)
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+mod platform {
+    use super::WindowBounds;
+
+    pub fn window_details(
+        _window: &scap::targets::Window,
+    ) -> (Option<String>, Option<WindowBounds>) {
+        (None, None)
+    }
+}
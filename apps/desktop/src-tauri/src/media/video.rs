@@ -1,43 +1,66 @@
 use image::codecs::jpeg::JpegEncoder;
 use image::{ImageBuffer, Rgba};
 use scap::{
-    capturer::{Capturer, Options, Resolution},
+    capturer::{Area, Capturer, Options, Resolution},
     frame::{Frame, FrameType},
+    Target,
 };
 use std::{
     future::Future,
     path::{Path, PathBuf},
-    sync::Arc,
     time::Duration,
 };
-use tokio::sync::mpsc::error::TrySendError;
-use tokio::{fs::File, io::AsyncWriteExt, sync::mpsc};
+use tokio::{fs::File, io::AsyncWriteExt};
 
-use super::{Instant, RecordingOptions, SharedFlag, SharedInstant};
-use crate::app::config;
-use crate::upload::{upload_recording_asset, RecordingAssetType};
+use super::convert::{FrameConverter, PixelFormat};
+use super::encoder::EncoderSender;
+use super::matroska;
+use super::pool::{Buffer, BufferPool};
+use super::queue::{self, QueueMetrics, QueueOptions, QueueReceiver, SendError};
+use super::sync::{DriftCorrector, SharedTimeline};
+use super::{Captured, Instant, RecordingOptions, SharedFlag, SharedFrameRate, SharedInstant};
+use crate::error::CapError;
+use crate::metadata::StreamSync;
+use crate::upload::{upload_recording_asset, uploads_enabled, RecordingAssetType};
 
 pub struct VideoCapturer {
     capturer: Option<Capturer>,
     should_stop: SharedFlag,
+    is_paused: SharedFlag,
     pub frame_width: u32,
     pub frame_height: u32,
-    frame_receiver: Option<mpsc::Receiver<Arc<Vec<u8>>>>,
+    fps: u32,
+    variable_frame_rate: bool,
+    /// Lowered below `fps` when the encoder can't keep up.
+    frame_rate: SharedFrameRate,
+    frame_receiver: Option<QueueReceiver>,
 }
 
-impl VideoCapturer {
-    pub const FPS: u32 = 30;
+/// With a variable frame rate, an unchanged frame is still sent this often, so the
+/// encoder keeps getting to place keyframes and the segments keep coming.
+const MAX_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+/// Frames kept around for reuse, which covers the encoder keeping up.
+const FRAME_POOL_SIZE: usize = 8;
 
-    pub fn new(_width: usize, _height: usize, should_stop: SharedFlag) -> VideoCapturer {
+impl VideoCapturer {
+    pub fn new(
+        target: Option<Target>,
+        crop_area: Option<Area>,
+        fps: u32,
+        variable_frame_rate: bool,
+        should_stop: SharedFlag,
+        is_paused: SharedFlag,
+        frame_rate: SharedFrameRate,
+    ) -> VideoCapturer {
         let mut capturer = Capturer::new(Options {
-            fps: Self::FPS,
-            target: None,
+            fps,
+            target,
             show_cursor: true,
             show_highlight: true,
             excluded_targets: None,
             output_type: FrameType::BGRAFrame,
             output_resolution: Resolution::Captured,
-            crop_area: None,
+            crop_area,
         });
 
         let [frame_width, frame_height] = capturer.get_output_frame_size();
@@ -45,9 +68,13 @@ impl VideoCapturer {
         Self {
             capturer: Some(capturer),
             should_stop,
+            is_paused,
             frame_receiver: None,
             frame_width,
             frame_height,
+            fps,
+            variable_frame_rate,
+            frame_rate,
         }
     }
 
@@ -56,15 +83,30 @@ impl VideoCapturer {
         start_time: SharedInstant,
         screenshot_dir: impl AsRef<Path>,
         recording_options: RecordingOptions,
-    ) {
+        queue_options: &QueueOptions,
+        mut converter: Option<FrameConverter>,
+    ) -> QueueMetrics {
         let mut capturer = self
             .capturer
             .take()
             .expect("Video capturing thread has already been started!");
-        let (sender, receiver) = mpsc::channel(2048);
+        let (sender, receiver) = queue::channel(
+            "video",
+            queue::VIDEO_BUDGET_BYTES,
+            queue_options,
+            self.should_stop.clone(),
+        );
 
+        let metrics = receiver.metrics();
         self.frame_receiver = Some(receiver);
         let screenshot_file_path = screenshot_dir.as_ref().join("screen-capture.jpg");
+        let is_paused = self.is_paused.clone();
+        let (output_width, output_height) = (self.frame_width, self.frame_height);
+        let variable_frame_rate = self.variable_frame_rate;
+        let fps = self.fps;
+        let frame_rate = self.frame_rate.clone();
+        let pool = BufferPool::new(FRAME_POOL_SIZE);
+        let converted_pool = BufferPool::new(FRAME_POOL_SIZE);
 
         std::thread::spawn(move || {
             tracing::trace!("Starting video recording capture thread...");
@@ -75,7 +117,10 @@ impl VideoCapturer {
             let mut fps_sample_time = Instant::now();
             let mut screenshot_captured: bool = false;
             let take_screenshot_delay = Duration::from_secs(3);
-            let mut last_frame: Option<Arc<Vec<u8>>> = None;
+            let mut last_frame: Option<Buffer> = None;
+            // The last frame converted, as idle frames repeat it
+            let mut last_converted: Option<Buffer> = None;
+            let mut last_sent_at: Option<Instant> = None;
 
             capturer.start_capture();
 
@@ -87,9 +132,11 @@ impl VideoCapturer {
                     Ok(Frame::BGRA(frame)) => {
                         let now = Instant::now();
 
-                        let width = frame.width;
-                        let height = frame.height;
-                        let frame_data = match frame.width == 0 && frame.height == 0 {
+                        // Every frame handed to FFmpeg has the size it was started with
+                        let width = output_width;
+                        let height = output_height;
+                        let is_idle = frame.width == 0 && frame.height == 0;
+                        let frame_data = match is_idle {
                             true => match last_frame.take() {
                                 Some(data) => data,
                                 None => {
@@ -99,23 +146,48 @@ impl VideoCapturer {
                                     continue;
                                 }
                             },
-                            false => Arc::new(frame.data),
+                            false
+                                if frame.width as u32 != width || frame.height as u32 != height =>
+                            {
+                                let mut output = pool.take();
+                                fit_frame(
+                                    &frame.data,
+                                    frame.width as u32,
+                                    frame.height as u32,
+                                    width,
+                                    height,
+                                    &mut output,
+                                );
+                                pool.wrap(output)
+                            }
+                            // scap allocates every frame, so it's copied into a pooled
+                            // buffer and freed right away rather than held by the queue
+                            false => pool.copy_from(&frame.data),
                         };
+                        // Not every platform reports idle frames, so check against the
+                        // last one too
+                        let unchanged = variable_frame_rate
+                            && (is_idle
+                                || last_frame
+                                    .as_deref()
+                                    .is_some_and(|last| same_frame(last, &frame_data, height)));
 
                         if now - capture_start_time >= take_screenshot_delay && !screenshot_captured
                         {
                             screenshot_captured = true;
-                            let mut frame_data_clone = (*frame_data).clone();
+                            let screenshot_frame = frame_data.clone();
 
                             std::thread::spawn(move || {
-                                for chunk in frame_data_clone.chunks_mut(4) {
-                                    chunk.swap(0, 2);
-                                }
+                                let rgba = screenshot_frame
+                                    .chunks_exact(4)
+                                    .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+                                    .collect();
+                                drop(screenshot_frame);
 
                                 let image: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(
                                     width.try_into().unwrap(),
                                     height.try_into().unwrap(),
-                                    frame_data_clone,
+                                    rgba,
                                 )
                                 .expect("Failed to create image buffer");
 
@@ -133,7 +205,7 @@ impl VideoCapturer {
                                     );
                                 }
 
-                                if !config::is_local_mode() {
+                                if uploads_enabled() {
                                     let rt = tokio::runtime::Runtime::new().unwrap();
                                     rt.block_on(async move {
                                         let upload_task = tokio::spawn(upload_recording_asset(
@@ -159,8 +231,47 @@ impl VideoCapturer {
                             });
                         }
 
-                        last_frame = Some(Arc::clone(&frame_data));
-                        match sender.try_send(frame_data) {
+                        last_frame = Some(frame_data.clone());
+                        if !is_idle {
+                            last_converted = None;
+                        }
+
+                        if is_paused.get() {
+                            continue;
+                        }
+                        if unchanged
+                            && last_sent_at
+                                .is_some_and(|sent_at| now - sent_at < MAX_FRAME_INTERVAL)
+                        {
+                            continue;
+                        }
+                        // Frames are thinned out while the encoder can't keep up, allowing
+                        // for half a frame of capture jitter
+                        let limit = frame_rate.get();
+                        let min_interval = 1.0 / limit as f64 - 0.5 / fps as f64;
+                        if (1..fps).contains(&limit)
+                            && last_sent_at
+                                .is_some_and(|sent_at| (now - sent_at).as_secs_f64() < min_interval)
+                        {
+                            continue;
+                        }
+                        last_sent_at = Some(now);
+
+                        let data = match converter.as_mut() {
+                            Some(converter) => last_converted
+                                .get_or_insert_with(|| {
+                                    let mut output = converted_pool.take();
+                                    converter.convert(&frame_data, &mut output);
+                                    converted_pool.wrap(output)
+                                })
+                                .clone(),
+                            None => frame_data,
+                        };
+                        let captured = Captured {
+                            data,
+                            captured_at: now,
+                        };
+                        match sender.send(captured) {
                             Ok(_) => {
                                 let mut first_frame_time_guard = start_time.try_lock();
 
@@ -172,11 +283,12 @@ impl VideoCapturer {
                                     }
                                 }
                             }
-                            Err(TrySendError::Full(_)) => {
-                                // TODO: Consider panicking? This should *never* happen
-                                tracing::error!("Channel buffer is full!");
+                            Err(SendError::Full) => {}
+                            Err(SendError::Overflow(overflow)) => {
+                                tracing::error!("{overflow}");
+                                break;
                             }
-                            _ => {
+                            Err(SendError::Closed) => {
                                 tracing::trace!("Recording has been stopped. Dropping data.");
                                 break;
                             }
@@ -203,23 +315,92 @@ impl VideoCapturer {
                 }
             }
         });
+
+        metrics
     }
 
-    pub fn collect_frames(&mut self, destination: PathBuf) -> impl Future<Output = ()> + 'static {
+    /// For a constant frame rate, frames are repeated or dropped to keep in step with the
+    /// clock, and go to FFmpeg timed by the rate they're written at. That's the lowered
+    /// rate once encoding fell behind, so FFmpeg has fewer frames to encode from then on.
+    pub fn collect_frames(
+        &mut self,
+        destination: PathBuf,
+        mut drift: DriftCorrector,
+        format: PixelFormat,
+        (width, height): (u32, u32),
+    ) -> impl Future<Output = Result<Option<StreamSync>, CapError>> + 'static {
         tracing::trace!("Starting video channel senders...");
         let mut receiver = self
             .frame_receiver
             .take()
             .expect("Video frame collection already started!");
         let should_stop = self.should_stop.clone();
+        let frame_rate = self.frame_rate.clone();
 
         async move {
-            let mut pipe = File::create(destination).await.unwrap();
+            let mut pipe = File::create(destination).await.map_err(write_error)?;
+            pipe.write_all(&matroska::header(width, height, format.fourcc()))
+                .await
+                .map_err(write_error)?;
+
+            while let Some(frame) = receiver.recv().await {
+                drift.set_frame_rate(frame_rate.get() as f64);
+                for timestamp in drift.frame_timestamps(frame.captured_at) {
+                    pipe.write_all(&matroska::frame_header(timestamp, frame.data.len()))
+                        .await
+                        .map_err(write_error)?;
+                    pipe.write_all(&frame.data).await.map_err(write_error)?;
+                }
+
+                if should_stop.get() {
+                    receiver.close();
+                }
+            }
 
-            while let Some(bytes) = receiver.recv().await {
-                pipe.write_all(&bytes)
+            let _ = pipe.sync_all().await;
+
+            Ok(Some(drift.stats()))
+        }
+    }
+
+    /// For a variable frame rate, frames go to FFmpeg timed by when they were captured.
+    /// There's nothing to correct then.
+    pub fn collect_timed_frames(
+        &mut self,
+        destination: PathBuf,
+        timeline: SharedTimeline,
+        format: PixelFormat,
+        (width, height): (u32, u32),
+    ) -> impl Future<Output = Result<Option<StreamSync>, CapError>> + 'static {
+        tracing::trace!("Starting video channel senders...");
+        let mut receiver = self
+            .frame_receiver
+            .take()
+            .expect("Video frame collection already started!");
+        let should_stop = self.should_stop.clone();
+
+        async move {
+            let mut pipe = File::create(destination).await.map_err(write_error)?;
+            pipe.write_all(&matroska::header(width, height, format.fourcc()))
+                .await
+                .map_err(write_error)?;
+
+            let mut first_position = None;
+            while let Some(frame) = receiver.recv().await {
+                let position = timeline
+                    .lock()
+                    .ok()
+                    .and_then(|timeline| timeline.position(frame.captured_at));
+                let Some(position) = position else {
+                    continue;
+                };
+
+                // Relative to the first frame, just like frames that are counted
+                let timestamp = position.saturating_sub(*first_position.get_or_insert(position));
+                pipe.write_all(&matroska::frame_header(timestamp, frame.data.len()))
                     .await
-                    .expect("Failed to write video data to FFmpeg stdin");
+                    .map_err(write_error)?;
+                pipe.write_all(&frame.data).await.map_err(write_error)?;
 
                 if should_stop.get() {
                     receiver.close();
@@ -227,6 +408,162 @@ impl VideoCapturer {
             }
 
             let _ = pipe.sync_all().await;
+
+            Ok(None)
         }
     }
+
+    /// Frames are timed by when they were captured, so there's no drift to correct.
+    pub fn encode_frames(
+        &mut self,
+        encoder: EncoderSender,
+    ) -> impl Future<Output = Result<Option<StreamSync>, CapError>> + 'static {
+        tracing::trace!("Starting video encoder senders...");
+        let mut receiver = self
+            .frame_receiver
+            .take()
+            .expect("Video frame collection already started!");
+        let should_stop = self.should_stop.clone();
+
+        async move {
+            while let Some(frame) = receiver.recv().await {
+                if !encoder.send_video(frame).await {
+                    tracing::error!("Encoder has shut down. Dropping video frames.");
+                    break;
+                }
+
+                if should_stop.get() {
+                    receiver.close();
+                }
+            }
+
+            Ok(None)
+        }
+    }
+}
+
+fn write_error(error: std::io::Error) -> CapError {
+    CapError::io("Failed to write video data to FFmpeg", &error)
+}
+
+/// Fits a BGRA frame into a fixed output size, scaling it (nearest neighbour) while
+/// preserving its aspect ratio and filling the remaining area with black bars.
+/// Window captures change size whenever the window is resized, but the raw video
+/// FFmpeg reads can only ever contain frames of a single size.
+fn fit_frame(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    out_width: u32,
+    out_height: u32,
+    output: &mut Vec<u8>,
+) {
+    output.clear();
+    output.resize((out_width * out_height * 4) as usize, 0);
+    for pixel in output.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // Captured rows may be padded, so derive the stride from the buffer itself
+    let stride = data.len() / height as usize;
+    let scale = f64::min(
+        out_width as f64 / width as f64,
+        out_height as f64 / height as f64,
+    );
+    let scaled_width = ((width as f64 * scale) as u32).clamp(1, out_width);
+    let scaled_height = ((height as f64 * scale) as u32).clamp(1, out_height);
+    let offset_x = (out_width - scaled_width) / 2;
+    let offset_y = (out_height - scaled_height) / 2;
+
+    for y in 0..scaled_height {
+        let source_y = ((y as u64 * height as u64) / scaled_height as u64) as usize;
+        let source_row = &data[source_y * stride..];
+        let row_start = (((offset_y + y) * out_width + offset_x) * 4) as usize;
+        let row = &mut output[row_start..row_start + (scaled_width * 4) as usize];
+
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let source_x = ((x as u64 * width as u64) / scaled_width as u64) as usize;
+            pixel.copy_from_slice(&source_row[source_x * 4..source_x * 4 + 4]);
+        }
+    }
+}
+
+/// Whether a BGRA frame is identical to the last one. Sampling every eighth pixel of
+/// every other row finds most changes without reading through the whole frame, but a
+/// caret or a small cursor can fall between the samples, so frames that look the same
+/// there are compared in full.
+fn same_frame(last: &[u8], frame: &[u8], height: u32) -> bool {
+    if last.len() != frame.len() {
+        return false;
+    }
+
+    if height > 0 {
+        // Captured rows may be padded, so derive the stride from the buffer itself
+        let stride = (frame.len() / height as usize).max(1);
+        let sample_differs = last
+            .chunks_exact(stride)
+            .zip(frame.chunks_exact(stride))
+            .step_by(2)
+            .any(|(last_row, row)| {
+                last_row
+                    .chunks_exact(4)
+                    .step_by(8)
+                    .zip(row.chunks_exact(4).step_by(8))
+                    .any(|(last_pixel, pixel)| last_pixel != pixel)
+            });
+        if sample_differs {
+            return false;
+        }
+    }
+
+    last == frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: u32 = 16;
+
+    fn frame() -> Vec<u8> {
+        (0..WIDTH * HEIGHT as usize * 4)
+            .map(|byte| (byte % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn finds_identical_frames() {
+        assert!(same_frame(&frame(), &frame(), HEIGHT));
+    }
+
+    #[test]
+    fn finds_changes_between_samples() {
+        // A one pixel wide caret on an odd row, in a column that's never sampled
+        let mut changed = frame();
+        for row in [3, 5, 7] {
+            changed[(row * WIDTH + 5) * 4] ^= 0xff;
+        }
+        assert!(!same_frame(&frame(), &changed, HEIGHT));
+
+        let mut changed = frame();
+        changed[(WIDTH + 1) * 4 + 2] ^= 1;
+        assert!(!same_frame(&frame(), &changed, HEIGHT));
+    }
+
+    #[test]
+    fn finds_sampled_changes() {
+        let mut changed = frame();
+        changed[0] ^= 0xff;
+        assert!(!same_frame(&frame(), &changed, HEIGHT));
+    }
+
+    #[test]
+    fn finds_resized_frames() {
+        assert!(!same_frame(&frame(), &frame()[..WIDTH * 4], HEIGHT));
+    }
 }
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::{CapError, ErrorCode};
+use crate::media::{AudioSource, EncodingAdaptation, HlsSegmentType, QueueDepth, VideoCodec};
+use crate::recording::{CaptureMode, CaptureRegion, RecordingOptions, RecordingPhase};
+
+/// Describes what a recording captured, so the web app doesn't have to guess from the
+/// video stream itself. Stored next to the HLS output and uploaded alongside it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingMeta {
+    pub video_id: String,
+    pub capture_mode: CaptureMode,
+    pub display_id: Option<u32>,
+    pub crop_area: Option<CaptureRegion>,
+    pub width: u32,
+    pub height: u32,
+    pub video_codec: VideoCodec,
+    pub segment_type: HlsSegmentType,
+    /// Empty when the audio is muxed in with the video.
+    #[serde(default)]
+    pub audio_renditions: Vec<AudioRendition>,
+    /// Filled in once the recording has stopped.
+    #[serde(default)]
+    pub session_stats: Option<SessionStats>,
+}
+
+/// An input recorded as its own alternate audio rendition, named after the files
+/// holding it (`stream_audio_0.m3u8`, ...).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioRendition {
+    pub name: String,
+    pub source: AudioSource,
+    pub gain: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStats {
+    pub sync: Vec<StreamSync>,
+    /// Where captured data waited to be encoded, video first.
+    #[serde(default)]
+    pub queues: Vec<QueueDepth>,
+    /// Why the recording was cut short, when a queue went over its budget.
+    #[serde(default)]
+    pub overflow: Option<String>,
+    /// What was given up to keep encoding in real time, in order.
+    #[serde(default)]
+    pub adaptations: Vec<EncodingAdaptation>,
+}
+
+/// How far a stream drifted from its capture timestamps, in milliseconds, where
+/// positive means its data ran ahead of the clock. The difference between the video's
+/// and an audio input's offset is how far apart they are.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamSync {
+    pub stream: String,
+    /// The last measurement, after the corrections made until then.
+    pub offset_ms: f64,
+    /// The largest measurement, before it got corrected.
+    pub max_offset_ms: f64,
+    /// Video frames or audio samples added or removed to stay in sync.
+    pub inserted: u64,
+    pub removed: u64,
+}
+
+impl RecordingMeta {
+    pub const FILE_NAME: &'static str = "recording-meta.json";
+
+    pub fn load(recording_dir: &Path) -> Result<Self, CapError> {
+        let json = std::fs::read_to_string(recording_dir.join(Self::FILE_NAME))
+            .map_err(|e| CapError::io("Failed to read recording metadata", &e))?;
+
+        serde_json::from_str(&json).map_err(|e| {
+            CapError::new(ErrorCode::Io, "Failed to deserialize recording metadata").caused_by(e)
+        })
+    }
+
+    pub fn save(&self, recording_dir: &Path) -> Result<(), CapError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize recording metadata: {}", e))?;
+
+        std::fs::write(recording_dir.join(Self::FILE_NAME), json)
+            .map_err(|e| CapError::io("Failed to write recording metadata", &e))
+    }
+}
+
+/// Where a recording got to, kept up to date as it goes, so one cut short by a crash
+/// can be told apart from one that finished on the next launch. Stays on this computer.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingManifest {
+    pub video_id: String,
+    pub phase: RecordingPhase,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    /// What uploading the recording takes, should it have to be picked up again.
+    pub options: RecordingOptions,
+    /// Only there while they might still be running.
+    #[serde(default)]
+    pub child_processes: Vec<ChildProcess>,
+    #[serde(default)]
+    pub error: Option<CapError>,
+}
+
+/// A process started for a recording, like FFmpeg or `parec`, to be stopped on the next
+/// launch should the app quit without stopping it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChildProcess {
+    pub pid: u32,
+    /// Tells the process apart from whatever got its pid since.
+    pub name: String,
+}
+
+impl RecordingManifest {
+    pub const FILE_NAME: &'static str = "recording-manifest.json";
+
+    pub fn new(options: RecordingOptions) -> Self {
+        let started_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            video_id: options.video_id.clone(),
+            phase: RecordingPhase::Starting,
+            started_at,
+            options,
+            child_processes: vec![],
+            error: None,
+        }
+    }
+
+    pub fn load(recording_dir: &Path) -> Result<Self, CapError> {
+        let json = std::fs::read_to_string(recording_dir.join(Self::FILE_NAME))
+            .map_err(|e| CapError::io("Failed to read recording manifest", &e))?;
+
+        serde_json::from_str(&json).map_err(|e| {
+            CapError::new(ErrorCode::Io, "Failed to deserialize recording manifest").caused_by(e)
+        })
+    }
+
+    pub fn save(&self, recording_dir: &Path) -> Result<(), CapError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize recording manifest: {}", e))?;
+
+        // Written in full before it replaces the last one, so a crash never leaves half
+        // a manifest behind
+        let temp_path = recording_dir.join(format!("{}.tmp", Self::FILE_NAME));
+        std::fs::write(&temp_path, json)
+            .and_then(|_| std::fs::rename(&temp_path, recording_dir.join(Self::FILE_NAME)))
+            .map_err(|e| CapError::io("Failed to write recording manifest", &e))
+    }
+
+    pub fn update(recording_dir: &Path, change: impl FnOnce(&mut Self)) -> Result<(), CapError> {
+        let mut manifest = Self::load(recording_dir)?;
+        change(&mut manifest);
+        manifest.save(recording_dir)
+    }
+}
@@ -1,4 +1,3 @@
-use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
@@ -6,27 +5,193 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use tauri::State;
+use tauri::{AppHandle, State};
+use tauri_specta::Event;
 use tokio::sync::{oneshot, Mutex};
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 
-use crate::app::config;
-use crate::upload::{upload_recording_asset, RecordingAssetType};
+use crate::error::{CapError, ErrorCode};
+use crate::metadata::{RecordingManifest, RecordingMeta};
+use crate::upload::{uploads_enabled, RecordingAssetType, UploadQueue};
 
-use crate::media::MediaRecorder;
+use crate::media::{
+    hls, AudioInputOptions, AudioMixMode, AudioSource, EncoderProgress, MediaRecorder,
+    OverflowPolicy, QueueDepth, QueueMetrics, RecordingQuality, UploadStatus, STATUS_INTERVAL,
+};
 
 pub struct ActiveRecording {
     pub media_process: MediaRecorder,
     pub recording_options: RecordingOptions,
     pub shutdown_flag: Arc<AtomicBool>,
-    pub uploading_finished: oneshot::Receiver<()>,
+    /// Resolves once every segment has been through its upload, with why the uploads
+    /// were given up on otherwise.
+    pub uploading_finished: oneshot::Receiver<Result<(), CapError>>,
 }
 
 pub struct RecordingState {
+    pub lifecycle: RecordingLifecycle,
+    /// Only there while recording or paused.
     pub active_recording: Option<ActiveRecording>,
     pub data_dir: PathBuf,
     pub max_screen_width: usize,
     pub max_screen_height: usize,
+    /// Recordings go through the built-in encoder when FFmpeg couldn't be installed.
+    pub ffmpeg_installed: bool,
+    pub upload_queue: Arc<UploadQueue>,
+}
+
+/// Where a recording is in its lifecycle. A new recording can only start once the last
+/// one has completed or failed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingPhase {
+    #[default]
+    Idle,
+    Starting,
+    Recording,
+    Paused,
+    /// Writing out whatever is left to encode.
+    Finalizing,
+    /// Waiting on the last segments to be uploaded.
+    Uploading,
+    Completed,
+    Failed,
+}
+
+impl RecordingPhase {
+    fn can_become(self, to: RecordingPhase) -> bool {
+        use RecordingPhase::*;
+
+        match self {
+            Idle | Completed => to == Starting,
+            // Its uploads can be picked up again
+            Failed => matches!(to, Starting | Uploading),
+            // Anything underway can fail
+            _ if to == Failed => true,
+            Starting => to == Recording,
+            Recording => matches!(to, Paused | Finalizing),
+            Paused => matches!(to, Recording | Finalizing),
+            Finalizing => to == Uploading,
+            Uploading => to == Completed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingLifecycle {
+    pub phase: RecordingPhase,
+    /// The recording the phase is about, kept once it has completed or failed.
+    pub video_id: Option<String>,
+    /// Why the recording failed.
+    pub error: Option<CapError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingStateChanged {
+    pub video_id: String,
+    pub phase: RecordingPhase,
+}
+
+impl RecordingState {
+    fn ensure_can_become(&self, to: RecordingPhase) -> Result<(), CapError> {
+        let from = self.lifecycle.phase;
+        if !from.can_become(to) {
+            return Err(illegal_transition(from, to));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn transition(
+        &mut self,
+        app: &AppHandle,
+        to: RecordingPhase,
+    ) -> Result<(), CapError> {
+        self.ensure_can_become(to)?;
+
+        tracing::info!("Recording {:?} -> {:?}", self.lifecycle.phase, to);
+        self.lifecycle.phase = to;
+        if let Some(video_id) = self.lifecycle.video_id.clone() {
+            let event = RecordingStateChanged {
+                video_id,
+                phase: to,
+            };
+            if let Err(error) = event.emit(app) {
+                tracing::warn!("Failed to emit recording state: {error}");
+            }
+        }
+        self.update_manifest();
+
+        Ok(())
+    }
+
+    pub(crate) fn fail(&mut self, app: &AppHandle, error: CapError) {
+        tracing::error!("Recording failed: {error}");
+        self.active_recording = None;
+        if self.transition(app, RecordingPhase::Failed).is_ok() {
+            self.lifecycle.error = Some(error);
+            self.update_manifest();
+        }
+    }
+
+    /// Whether `video_id` is the recording being made (or uploaded) right now.
+    pub fn is_underway(&self, video_id: &str) -> bool {
+        self.lifecycle.video_id.as_deref() == Some(video_id)
+            && !matches!(
+                self.lifecycle.phase,
+                RecordingPhase::Idle | RecordingPhase::Completed | RecordingPhase::Failed
+            )
+    }
+
+    fn update_manifest(&self) {
+        let Some(video_id) = &self.lifecycle.video_id else {
+            return;
+        };
+        // The manifest is written along with the directory, once the recording has started
+        if self.lifecycle.phase == RecordingPhase::Starting {
+            return;
+        }
+
+        let (phase, error) = (self.lifecycle.phase, self.lifecycle.error.clone());
+        let updated =
+            RecordingManifest::update(&recording_dir(&self.data_dir, video_id), |manifest| {
+                manifest.phase = phase;
+                manifest.error = error;
+                // Its processes are done with the recording once it's finalized
+                if !matches!(
+                    phase,
+                    RecordingPhase::Recording | RecordingPhase::Paused | RecordingPhase::Finalizing
+                ) {
+                    manifest.child_processes.clear();
+                }
+            });
+        if let Err(error) = updated {
+            tracing::warn!("Failed to update the recording manifest: {error}");
+        }
+    }
+}
+
+/// Every recording gets a directory of its own in there, named after its video id.
+pub fn recordings_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("recordings")
+}
+
+pub fn recording_dir(data_dir: &Path, video_id: &str) -> PathBuf {
+    recordings_dir(data_dir).join(video_id)
+}
+
+/// Video ids name directories, so they can't point anywhere else.
+pub fn validate_video_id(video_id: &str) -> Result<(), CapError> {
+    if video_id.is_empty() || video_id.starts_with('.') || video_id.contains(['/', '\\']) {
+        return Err(CapError::new(
+            ErrorCode::InvalidOptions,
+            format!("Invalid video id {video_id:?}"),
+        ));
+    }
+
+    Ok(())
 }
 
 unsafe impl Send for RecordingState {}
@@ -34,6 +199,20 @@ unsafe impl Sync for RecordingState {}
 unsafe impl Send for MediaRecorder {}
 unsafe impl Sync for MediaRecorder {}
 
+fn illegal_transition(from: RecordingPhase, to: RecordingPhase) -> CapError {
+    CapError::new(
+        ErrorCode::IllegalTransition,
+        format!("A recording can't go from {from:?} to {to:?}"),
+    )
+}
+
+fn not_recording() -> CapError {
+    CapError::new(
+        ErrorCode::IllegalTransition,
+        "No recording is currently in progress.",
+    )
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
 pub struct RecordingOptions {
     pub user_id: String,
@@ -43,70 +222,157 @@ pub struct RecordingOptions {
     pub audio_name: String,
     pub aws_region: String,
     pub aws_bucket: String,
+    #[serde(default)]
+    pub capture_mode: CaptureMode,
+    #[serde(default)]
+    pub crop_area: Option<CaptureRegion>,
+    #[serde(default)]
+    pub quality: Option<RecordingQuality>,
+    /// Takes precedence over `audio_name` when not empty.
+    #[serde(default)]
+    pub audio_inputs: Vec<AudioInputOptions>,
+    #[serde(default)]
+    pub audio_mix: AudioMixMode,
+    /// What happens to captured frames and samples when the encoder can't keep up.
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl RecordingOptions {
+    /// `audio_name` predates multiple audio inputs. It holds the name of a microphone,
+    /// "None" when recording without one, or nothing to use the default microphone.
+    fn audio_inputs(&self) -> Vec<AudioInputOptions> {
+        if !self.audio_inputs.is_empty() {
+            return self.audio_inputs.clone();
+        }
+
+        if self.audio_name == "None" {
+            return vec![];
+        }
+
+        vec![AudioInputOptions {
+            source: AudioSource::Microphone {
+                name: self.audio_name.clone(),
+            },
+            gain: 1.0,
+        }]
+    }
+}
+
+/// What gets recorded. Window captures follow the window around when it's moved or
+/// partially covered, and get letterboxed into the initial frame size when it's resized.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, specta::Type)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaptureMode {
+    #[default]
+    Screen,
+    Window {
+        id: u32,
+    },
+}
+
+/// A rectangle of a display, in points: the display sizes `enumerate_displays` returns
+/// divided by their scale factor.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, specta::Type)]
+pub struct CaptureRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
 }
 
 #[tauri::command]
 #[specta::specta]
-#[tracing::instrument(skip(state))]
+#[tracing::instrument(skip(app, state))]
 pub async fn start_dual_recording(
+    app: AppHandle,
     state: State<'_, Arc<Mutex<RecordingState>>>,
     options: RecordingOptions,
-) -> Result<(), String> {
+) -> Result<(), CapError> {
     tracing::info!("Starting screen recording...");
-    let mut state = state.lock().await;
+    let recording_state = state.inner().clone();
 
-    if state.active_recording.is_some() {
-        return Err("A recording is already in progress.".to_string());
+    validate_video_id(&options.video_id)?;
+    for input in &options.audio_inputs {
+        input.source.check_supported()?;
     }
 
-    let shutdown_flag = Arc::new(AtomicBool::new(false));
-
-    let data_dir = state.data_dir.clone();
-
-    tracing::debug!("data_dir: {:?}", data_dir);
-
-    let screenshot_dir = data_dir.join("screenshots");
-    let recording_dir = data_dir.join("recording");
-
-    clean_and_create_dir(&screenshot_dir)?;
-    clean_and_create_dir(&recording_dir)?;
-
-    let audio_name = if options.audio_name.is_empty() {
-        None
-    } else {
-        Some(options.audio_name.clone())
+    let (data_dir, max_screen_width, max_screen_height, ffmpeg_installed, upload_queue) = {
+        let mut state = state.lock().await;
+        state.ensure_can_become(RecordingPhase::Starting)?;
+        state.lifecycle.video_id = Some(options.video_id.clone());
+        state.lifecycle.error = None;
+        state.transition(&app, RecordingPhase::Starting)?;
+        // Whatever an earlier attempt at the same recording left is about to be removed
+        state.upload_queue.discard(&options.video_id);
+
+        (
+            state.data_dir.clone(),
+            state.max_screen_width,
+            state.max_screen_height,
+            state.ffmpeg_installed,
+            state.upload_queue.clone(),
+        )
     };
 
-    let media_recording_result = prepare_media_recording(
+    // Nothing else can start or stop a recording while it's starting, so the lock
+    // isn't held while waiting on the capturers and the encoder
+    let media_process = start_media(
+        app.clone(),
         &options,
-        &screenshot_dir,
-        &recording_dir,
-        audio_name,
-        state.max_screen_width,
-        state.max_screen_height,
+        &data_dir,
+        max_screen_width,
+        max_screen_height,
+        ffmpeg_installed,
     )
-    .await
-    .map_err(|e| e.to_string())?;
+    .await;
 
+    let mut state = state.lock().await;
+    let media_process = match media_process {
+        Ok(media_process) => media_process,
+        Err(error) => {
+            state.fail(&app, error.clone());
+            return Err(error);
+        }
+    };
+
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
     let uploading_finished = oneshot::channel();
 
+    if options.overflow_policy == OverflowPolicy::Abort {
+        tokio::spawn(watch_queues(
+            app.clone(),
+            recording_state,
+            media_process.queue_metrics(),
+            shutdown_flag.clone(),
+        ));
+    }
+
     state.active_recording = Some(ActiveRecording {
-        media_process: media_recording_result,
+        media_process,
         recording_options: options.clone(),
         shutdown_flag: shutdown_flag.clone(),
         uploading_finished: uploading_finished.1,
     });
+    state.transition(&app, RecordingPhase::Recording)?;
 
     drop(state);
 
+    let recording_dir = recording_dir(&data_dir, &options.video_id);
     tokio::spawn(async move {
-        if !config::is_local_mode() {
-            let video_upload =
-                hls_upload_loop(&recording_dir, shutdown_flag.clone(), options.clone());
+        let result = if uploads_enabled() {
+            let video_upload = hls_upload_loop(
+                app,
+                upload_queue,
+                &recording_dir,
+                shutdown_flag.clone(),
+                options.clone(),
+            );
 
             tracing::info!("Starting upload loop...");
 
-            match video_upload.await {
+            let result = video_upload.await;
+            match &result {
                 Ok(_) => {
                     tracing::info!("Upload loop completed successfully.");
                 }
@@ -114,76 +380,303 @@ pub async fn start_dual_recording(
                     tracing::error!("An error occurred: {}", e);
                 }
             }
+            result
         } else {
             tracing::info!(
-                "Skipping upload loops due to NEXT_PUBLIC_LOCAL_MODE being set to 'true'."
+                "Skipping upload loops, as NEXT_PUBLIC_LOCAL_MODE is 'true' and there's no storage config."
             );
-        }
+            Ok(())
+        };
 
-        uploading_finished.0.send(()).ok();
+        uploading_finished.0.send(result).ok();
     });
 
     Ok(())
 }
 
+async fn start_media(
+    app: AppHandle,
+    options: &RecordingOptions,
+    data_dir: &Path,
+    max_screen_width: usize,
+    max_screen_height: usize,
+    ffmpeg_installed: bool,
+) -> Result<MediaRecorder, CapError> {
+    let quality = options.quality.clone().unwrap_or_default();
+    let quality = if ffmpeg_installed {
+        quality.validate().map(|_| quality)
+    } else {
+        let quality = quality.for_builtin_encoder();
+        quality.validate_settings().map(|_| quality)
+    }
+    .map_err(|error| CapError::new(ErrorCode::InvalidOptions, error))?;
+
+    tracing::debug!("data_dir: {:?}", data_dir);
+
+    // Only ever cleans out an earlier attempt at the same recording
+    let recording_dir = recording_dir(data_dir, &options.video_id);
+    let screenshot_dir = recording_dir.join("screenshots");
+
+    clean_and_create_dir(&recording_dir)?;
+    clean_and_create_dir(&screenshot_dir)?;
+    RecordingManifest::new(options.clone()).save(&recording_dir)?;
+
+    let media_recorder = prepare_media_recording(
+        app,
+        options,
+        &screenshot_dir,
+        &recording_dir,
+        quality,
+        max_screen_width,
+        max_screen_height,
+        ffmpeg_installed,
+    )
+    .await?;
+
+    let child_processes = media_recorder.child_processes();
+    if let Err(error) = RecordingManifest::update(&recording_dir, |manifest| {
+        manifest.child_processes = child_processes;
+    }) {
+        tracing::warn!("Failed to update the recording manifest: {error}");
+    }
+
+    Ok(media_recorder)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn stop_all_recordings(
+    app: AppHandle,
     state: State<'_, Arc<Mutex<RecordingState>>>,
-) -> Result<(), String> {
-    let mut state = state.lock().await;
+) -> Result<(), CapError> {
+    stop_recording(app, state.inner().clone()).await
+}
 
-    let Some(mut active_recording) = state.active_recording.take() else {
-        return Err("No recording is currently in progress.".to_string());
+/// Stops capturing, leaving the remaining segments and the playlists to upload in the
+/// background. The recording completes once they're all through.
+async fn stop_recording(app: AppHandle, state: Arc<Mutex<RecordingState>>) -> Result<(), CapError> {
+    let mut active_recording = {
+        let mut state = state.lock().await;
+        state.transition(&app, RecordingPhase::Finalizing)?;
+        let Some(active_recording) = state.active_recording.take() else {
+            state.fail(&app, not_recording());
+            return Err(not_recording());
+        };
+
+        active_recording
     };
 
     tracing::info!("Stopping media recording...");
-    active_recording
-        .media_process
-        .stop_media_recording()
+    let stopped = active_recording.media_process.stop_media_recording().await;
+    active_recording.shutdown_flag.store(true, Ordering::SeqCst);
+    if let Err(error) = stopped {
+        state.lock().await.fail(&app, error.clone());
+        return Err(error);
+    }
+
+    state
+        .lock()
         .await
-        .expect("Failed to stop media recording");
+        .transition(&app, RecordingPhase::Uploading)?;
 
-    tracing::info!("Uploading stream.m3u8");
-    upload_recording_asset(
-        active_recording.recording_options,
-        state.data_dir.join("recording/stream.m3u8"),
-        RecordingAssetType::CombinedSourcePlaylist,
-    )
-    .await
-    .ok();
+    tokio::spawn(async move {
+        tracing::debug!("Waiting for uploads to finish...");
+        let uploaded = active_recording
+            .uploading_finished
+            .await
+            .unwrap_or_else(|_| Err("The upload loop went away.".into()));
+
+        let mut state = state.lock().await;
+        match uploaded {
+            Ok(()) => {
+                if let Err(error) = state.transition(&app, RecordingPhase::Completed) {
+                    tracing::error!("{error}");
+                }
+                tracing::info!("All recordings and uploads stopped.");
+            }
+            Err(error) => state.fail(&app, error),
+        }
+    });
 
-    active_recording.shutdown_flag.store(true, Ordering::SeqCst);
+    Ok(())
+}
 
-    // if !config::is_local_mode() {
-    //     tracing::debug!("Waiting for uploads to finish...");
-    //     active_recording.uploading_finished.await.ok();
-    // }
+/// Queues up the playlists and the metadata, which describe the recording as a whole.
+fn queue_playlists(queue: &UploadQueue, options: &RecordingOptions, recording_dir: &Path) {
+    let playlists = playlists(recording_dir).unwrap_or_else(|error| {
+        tracing::error!("Failed to list playlists: {error}");
+        vec![]
+    });
+    for (playlist_path, asset_type) in playlists {
+        tracing::info!("Queueing {playlist_path:?}");
+        queue.enqueue(options, playlist_path, asset_type);
+    }
 
-    tracing::info!("All recordings and uploads stopped.");
+    let meta_path = recording_dir.join(RecordingMeta::FILE_NAME);
+    if meta_path.exists() {
+        tracing::info!("Queueing {}", RecordingMeta::FILE_NAME);
+        queue.enqueue(options, meta_path, RecordingAssetType::RecordingMetadata);
+    }
+}
 
-    Ok(())
+/// With the `abort` overflow policy, a queue going over its budget stops capturing and
+/// fails the recording with a `QueueOverflow` error. Everything recorded until then is
+/// kept, and still uploads in the background.
+async fn watch_queues(
+    app: AppHandle,
+    state: Arc<Mutex<RecordingState>>,
+    queues: Vec<QueueMetrics>,
+    shutdown_flag: Arc<AtomicBool>,
+) {
+    loop {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        if shutdown_flag.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let Some(overflow) = queues.iter().find_map(|queue| queue.overflow()) else {
+            continue;
+        };
+        tracing::error!("{overflow}");
+        let error = CapError::new(ErrorCode::QueueOverflow, overflow.to_string());
+
+        let mut active_recording = {
+            let mut state = state.lock().await;
+            // The recording might have been stopped in the meantime
+            if !matches!(
+                state.lifecycle.phase,
+                RecordingPhase::Recording | RecordingPhase::Paused
+            ) {
+                return;
+            }
+            if let Err(error) = state.transition(&app, RecordingPhase::Finalizing) {
+                tracing::error!("{error}");
+            }
+            let Some(active_recording) = state.active_recording.take() else {
+                state.fail(&app, error);
+                return;
+            };
+
+            active_recording
+        };
+
+        tracing::info!("Stopping media recording...");
+        let stopped = active_recording.media_process.stop_media_recording().await;
+        active_recording.shutdown_flag.store(true, Ordering::SeqCst);
+        if let Err(error) = stopped {
+            tracing::error!("Failed to stop the recording: {error}");
+        }
+
+        state.lock().await.fail(&app, error);
+        return;
+    }
+}
+
+/// Where the current (or last) recording is in its lifecycle.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_recording_status(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<RecordingLifecycle, CapError> {
+    Ok(state.lock().await.lifecycle.clone())
+}
+
+/// How much captured data is waiting to be encoded, video first. Empty when not
+/// recording.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_queue_depths(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<Vec<QueueDepth>, CapError> {
+    let state = state.lock().await;
+
+    Ok(state
+        .active_recording
+        .as_ref()
+        .map(|recording| recording.media_process.queue_depths())
+        .unwrap_or_default())
 }
 
-fn clean_and_create_dir(dir: &Path) -> Result<(), String> {
+/// How FFmpeg is keeping up with the recording. Nothing when not recording, or not
+/// recording through FFmpeg.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_encoder_progress(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<Option<EncoderProgress>, CapError> {
+    let state = state.lock().await;
+
+    Ok(state
+        .active_recording
+        .as_ref()
+        .and_then(|recording| recording.media_process.encoder_progress()))
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn pause_recording(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<(), CapError> {
+    let mut state = state.lock().await;
+    state.ensure_can_become(RecordingPhase::Paused)?;
+
+    let Some(active_recording) = state.active_recording.as_mut() else {
+        return Err(not_recording());
+    };
+
+    tracing::info!("Pausing media recording...");
+    active_recording.media_process.pause_media_recording()?;
+    state.transition(&app, RecordingPhase::Paused)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn resume_recording(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<(), CapError> {
+    let mut state = state.lock().await;
+    // A recording that's starting becomes one on its own
+    let from = state.lifecycle.phase;
+    if from != RecordingPhase::Paused {
+        return Err(illegal_transition(from, RecordingPhase::Recording));
+    }
+
+    let Some(active_recording) = state.active_recording.as_mut() else {
+        return Err(not_recording());
+    };
+
+    tracing::info!("Resuming media recording...");
+    active_recording.media_process.resume_media_recording()?;
+    state.transition(&app, RecordingPhase::Recording)
+}
+
+fn clean_and_create_dir(dir: &Path) -> Result<(), CapError> {
     if dir.exists() {
         // Instead of just reading the directory, this will also handle subdirectories.
-        std::fs::remove_dir_all(dir).map_err(|e| e.to_string())?;
+        std::fs::remove_dir_all(dir)
+            .map_err(|e| CapError::io(format!("Failed to clean up {}", dir.display()), &e))?;
     }
-    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| CapError::io(format!("Failed to create {}", dir.display()), &e))?;
 
     Ok(())
 }
 
-async fn hls_upload_loop(
+/// Queues up every segment as it's written until the recording is stopped, and then
+/// the playlists, which are only complete by then. Resolves once they've all been
+/// uploaded, or given up on.
+pub(crate) async fn hls_upload_loop(
+    app: AppHandle,
+    queue: Arc<UploadQueue>,
     recording_dir: &Path,
     shutdown_flag: Arc<AtomicBool>,
     options: RecordingOptions,
-) -> Result<(), String> {
-    let mut uploaded_segments: HashSet<PathBuf> = HashSet::new();
+) -> Result<(), CapError> {
+    let mut queued_segments: HashSet<PathBuf> = HashSet::new();
     let mut is_final_loop = false;
-
-    let mut upload_tasks = vec![];
+    let mut status = UploadProgress::new(&options.video_id);
 
     loop {
         if shutdown_flag.load(Ordering::SeqCst) {
@@ -193,67 +686,154 @@ async fn hls_upload_loop(
             is_final_loop = true;
         }
 
-        let files = std::fs::read_dir(recording_dir).map_err(|e| e.to_string())?;
+        let files = std::fs::read_dir(recording_dir)
+            .map_err(|e| CapError::io("Failed to list the recorded segments", &e))?;
 
         for file in files {
-            let file = file.map_err(|e| e.to_string())?;
+            let file =
+                file.map_err(|e| CapError::io("Failed to list the recorded segments", &e))?;
             let file_path = file.path().to_owned();
 
-            if let Some(ext) = file_path.extension() {
-                if ext != "ts" {
-                    continue;
+            let file_name = file.file_name().to_string_lossy().into_owned();
+
+            let asset_type = match file_path.extension() {
+                Some(ext) if ext == "ts" || ext == "m4s" => match hls::rendition_of(&file_name) {
+                    Some(rendition) if hls::is_audio_rendition(rendition) => {
+                        RecordingAssetType::AudioRenditionSegment
+                    }
+                    _ => RecordingAssetType::CombinedSourceSegment,
+                },
+                _ if hls::is_init_segment(&file_name) => {
+                    RecordingAssetType::CombinedSourceInitSegment
                 }
-            } else {
-                continue;
-            }
+                _ => continue,
+            };
 
-            if uploaded_segments.contains(&file_path) {
+            if queued_segments.contains(&file_path) {
                 continue;
             }
 
-            let options = options.clone();
+            tracing::debug!("Queueing segment {:?}", file_path);
+            queue.enqueue(&options, file_path.clone(), asset_type);
+            queued_segments.insert(file_path);
+        }
+
+        status.update(&app, &queue, false);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
 
-            upload_tasks.push(tokio::spawn(async move {
-                tracing::debug!("Uploading segment {:?}", file.path());
-                upload_recording_asset(
-                    options,
-                    file.path().to_owned(),
-                    RecordingAssetType::CombinedSourceSegment,
-                )
-                .await
-                .ok();
-            }));
+    queue_playlists(&queue, &options, recording_dir);
+    queue.seal(&options);
 
-            uploaded_segments.insert(file_path);
+    let outcome = loop {
+        if let Some(outcome) = queue.outcome(&options.video_id) {
+            break outcome;
         }
+        status.update(&app, &queue, false);
+        tokio::time::sleep(STATUS_INTERVAL).await;
+    };
+    status.update(&app, &queue, true);
+    if outcome.is_ok() {
+        queue.discard(&options.video_id);
+    }
 
-        tokio::time::sleep(Duration::from_millis(50)).await;
+    outcome
+}
+
+/// Emits how far the uploads are along whenever that changes, but no more often than
+/// `STATUS_INTERVAL`.
+struct UploadProgress {
+    last: UploadStatus,
+    emitted_at: Option<Instant>,
+}
+
+impl UploadProgress {
+    fn new(video_id: &str) -> Self {
+        Self {
+            last: UploadStatus {
+                video_id: video_id.to_string(),
+                uploaded: 0,
+                pending: 0,
+                failed: 0,
+            },
+            emitted_at: None,
+        }
     }
 
-    if !upload_tasks.is_empty() {
-        join_all(upload_tasks).await;
+    /// `last` emits regardless, for the final numbers.
+    fn update(&mut self, app: &AppHandle, queue: &UploadQueue, last: bool) {
+        let too_soon = self
+            .emitted_at
+            .is_some_and(|emitted_at| emitted_at.elapsed() < STATUS_INTERVAL);
+        if too_soon && !last {
+            return;
+        }
+
+        let status = queue.progress(&self.last.video_id);
+        if status == self.last && !last {
+            return;
+        }
+
+        if let Err(error) = status.emit(app) {
+            tracing::warn!("Failed to emit upload status: {error}");
+        }
+        self.last = status;
+        self.emitted_at = Some(Instant::now());
+    }
+}
+
+/// The playlists of every rendition, along with the master playlist when the audio
+/// has been split off into renditions of its own.
+fn playlists(recording_dir: &Path) -> Result<Vec<(PathBuf, RecordingAssetType)>, String> {
+    let files = std::fs::read_dir(recording_dir).map_err(|e| e.to_string())?;
+
+    let mut playlists = vec![];
+    let mut has_renditions = false;
+    for file in files {
+        let file_path = file.map_err(|e| e.to_string())?.path();
+        let Some(file_name) = file_path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if file_name == hls::MASTER_PLAYLIST_NAME || !file_name.ends_with(".m3u8") {
+            continue;
+        }
+        has_renditions |= hls::rendition_of(file_name).is_some();
+        playlists.push((file_path, RecordingAssetType::CombinedSourcePlaylist));
     }
 
-    Ok(())
+    if has_renditions {
+        playlists.push((
+            recording_dir.join(hls::MASTER_PLAYLIST_NAME),
+            RecordingAssetType::MasterPlaylist,
+        ));
+    }
+
+    Ok(playlists)
 }
 
 async fn prepare_media_recording(
+    app: AppHandle,
     options: &RecordingOptions,
     screenshot_dir: &Path,
     recording_dir: &Path,
-    audio_name: Option<String>,
+    quality: RecordingQuality,
     max_screen_width: usize,
     max_screen_height: usize,
-) -> Result<MediaRecorder, String> {
-    let mut media_recorder = MediaRecorder::new();
+    ffmpeg_installed: bool,
+) -> Result<MediaRecorder, CapError> {
+    let mut media_recorder = MediaRecorder::new(app);
     media_recorder
         .start_media_recording(
             options.clone(),
             screenshot_dir,
             recording_dir,
-            audio_name.as_ref().map(String::as_str),
+            &options.audio_inputs(),
+            options.audio_mix,
+            quality,
             max_screen_width,
             max_screen_height,
+            ffmpeg_installed,
         )
         .await?;
     Ok(media_recorder)
@@ -0,0 +1,323 @@
+//! Picks up after recordings that never got to finish, because the app crashed or was
+//! quit while recording or uploading them.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{atomic::AtomicBool, Arc};
+use tauri::{AppHandle, State};
+use tokio::sync::Mutex;
+
+use crate::error::{CapError, ErrorCode};
+use crate::media::hls;
+use crate::metadata::RecordingManifest;
+use crate::recording::{
+    hls_upload_loop, recording_dir, recordings_dir, validate_video_id, RecordingPhase,
+    RecordingState,
+};
+use crate::upload::UploadQueue;
+
+/// A recording that's yet to be fully uploaded.
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UnfinishedRecording {
+    pub video_id: String,
+    /// `uploading` while its upload is being resumed, `failed` otherwise.
+    pub phase: RecordingPhase,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub error: Option<CapError>,
+    pub segments: u32,
+}
+
+/// Goes through the recordings of the previous run, stopping whatever FFmpeg (or
+/// `parec`) processes they left behind and making playable recordings out of what
+/// they got to write. Runs at launch, before any new recording can start.
+///
+/// Recordings that were done queueing their uploads are left to the upload queue.
+pub fn recover(data_dir: &Path, upload_queue: &UploadQueue) {
+    let Ok(recordings) = std::fs::read_dir(recordings_dir(data_dir)) else {
+        return;
+    };
+
+    for recording in recordings.flatten() {
+        let recording_dir = recording.path();
+        let mut manifest = match RecordingManifest::load(&recording_dir) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                tracing::warn!("Skipping {recording_dir:?}: {error}");
+                continue;
+            }
+        };
+
+        let error = match manifest.phase {
+            RecordingPhase::Idle | RecordingPhase::Completed | RecordingPhase::Failed => continue,
+            RecordingPhase::Uploading if upload_queue.is_sealed(&manifest.video_id) => continue,
+            RecordingPhase::Uploading => "The app quit before the recording was uploaded",
+            RecordingPhase::Starting
+            | RecordingPhase::Recording
+            | RecordingPhase::Paused
+            | RecordingPhase::Finalizing => "The app quit before the recording was finished",
+        };
+        tracing::warn!("Recovering {}: {error}", manifest.video_id);
+
+        // FFmpeg goes first, as it finishes off the playlists once it's told to stop
+        for process in std::mem::take(&mut manifest.child_processes) {
+            if crate::utils::stop_orphaned_process(process.pid, &process.name) {
+                tracing::warn!(
+                    "Stopped {} process {} left behind by {}",
+                    process.name,
+                    process.pid,
+                    manifest.video_id
+                );
+            }
+        }
+        finish_playlists(&recording_dir);
+
+        manifest.phase = RecordingPhase::Failed;
+        manifest.error = Some(CapError::new(ErrorCode::Interrupted, error));
+        if let Err(error) = manifest.save(&recording_dir) {
+            tracing::error!(
+                "Failed to save the manifest of {}: {error}",
+                manifest.video_id
+            );
+        }
+    }
+}
+
+/// Ends the playlists FFmpeg didn't get to end, and removes the files it was in the
+/// middle of writing.
+fn finish_playlists(recording_dir: &Path) {
+    let Ok(files) = std::fs::read_dir(recording_dir) else {
+        return;
+    };
+
+    for file in files.flatten() {
+        let path = file.path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if file_name.ends_with(".tmp") || file_name.ends_with(".pipe") {
+            std::fs::remove_file(&path).ok();
+            continue;
+        }
+        if file_name == hls::MASTER_PLAYLIST_NAME || !file_name.ends_with(".m3u8") {
+            continue;
+        }
+
+        let Ok(mut playlist) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        if playlist.contains("#EXT-X-ENDLIST") {
+            continue;
+        }
+        if !playlist.ends_with('\n') {
+            playlist.push('\n');
+        }
+        playlist.push_str("#EXT-X-ENDLIST\n");
+        if let Err(error) = std::fs::write(&path, playlist) {
+            tracing::error!("Failed to finish {path:?}: {error}");
+        }
+    }
+}
+
+/// Recordings left unfinished by an earlier run, or whose upload failed, most recent
+/// first.
+#[tauri::command]
+#[specta::specta]
+pub async fn list_unfinished_recordings(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+) -> Result<Vec<UnfinishedRecording>, CapError> {
+    let data_dir = state.lock().await.data_dir.clone();
+    let Ok(recordings) = std::fs::read_dir(recordings_dir(&data_dir)) else {
+        return Ok(vec![]);
+    };
+
+    let mut unfinished: Vec<_> = recordings
+        .flatten()
+        .filter_map(|recording| {
+            let recording_dir = recording.path();
+            let manifest = RecordingManifest::load(&recording_dir).ok()?;
+            if !matches!(
+                manifest.phase,
+                RecordingPhase::Failed | RecordingPhase::Uploading
+            ) {
+                return None;
+            }
+
+            Some(UnfinishedRecording {
+                segments: count_segments(&recording_dir),
+                video_id: manifest.video_id,
+                phase: manifest.phase,
+                started_at: manifest.started_at,
+                error: manifest.error,
+            })
+        })
+        .collect();
+    unfinished.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+    Ok(unfinished)
+}
+
+/// Uploads a recording that didn't get to be uploaded in full, in the background.
+#[tauri::command]
+#[specta::specta]
+pub async fn resume_upload(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+) -> Result<(), CapError> {
+    let recording_state = state.inner().clone();
+    let mut state = state.lock().await;
+
+    let recording_dir = recording_dir(&state.data_dir, &video_id);
+    let mut manifest = load_manifest(&recording_dir, &video_id)?;
+    if state.is_underway(&video_id) || manifest.phase != RecordingPhase::Failed {
+        return Err(CapError::new(
+            ErrorCode::IllegalTransition,
+            format!("{video_id} isn't waiting on its upload"),
+        ));
+    }
+
+    let is_current = state.lifecycle.video_id.as_deref() == Some(&video_id);
+    if is_current {
+        state.lifecycle.error = None;
+        state.transition(&app, RecordingPhase::Uploading)?;
+    } else {
+        manifest.phase = RecordingPhase::Uploading;
+        manifest.error = None;
+        manifest.save(&recording_dir)?;
+    }
+    let upload_queue = state.upload_queue.clone();
+    drop(state);
+
+    tracing::info!("Resuming the upload of {video_id}");
+    upload_in_background(app, recording_state, upload_queue, manifest, recording_dir);
+
+    Ok(())
+}
+
+/// Follows the uploads the upload queue picked up from the last run through to the end
+/// of their recordings.
+pub fn follow_queued_uploads(app: AppHandle, state: Arc<Mutex<RecordingState>>) {
+    tauri::async_runtime::spawn(async move {
+        let (data_dir, upload_queue) = {
+            let state = state.lock().await;
+            (state.data_dir.clone(), state.upload_queue.clone())
+        };
+
+        for video_id in upload_queue.sealed_recordings() {
+            let recording_dir = recording_dir(&data_dir, &video_id);
+            match RecordingManifest::load(&recording_dir) {
+                Ok(manifest) if manifest.phase == RecordingPhase::Uploading => {
+                    tracing::info!("Following the upload of {video_id}");
+                    upload_in_background(
+                        app.clone(),
+                        state.clone(),
+                        upload_queue.clone(),
+                        manifest,
+                        recording_dir,
+                    );
+                }
+                // Whatever's left of it isn't needed anymore
+                _ => upload_queue.discard(&video_id),
+            }
+        }
+    });
+}
+
+/// Queues up whatever of a recording isn't yet, and moves it to `completed` (or
+/// `failed`) once it's all been through its upload.
+fn upload_in_background(
+    app: AppHandle,
+    recording_state: Arc<Mutex<RecordingState>>,
+    upload_queue: Arc<UploadQueue>,
+    mut manifest: RecordingManifest,
+    recording_dir: PathBuf,
+) {
+    tauri::async_runtime::spawn(async move {
+        let video_id = manifest.video_id.clone();
+        // A single pass over what's there, as nothing's being added anymore
+        let shutdown_flag = Arc::new(AtomicBool::new(true));
+        let uploaded = hls_upload_loop(
+            app.clone(),
+            upload_queue,
+            &recording_dir,
+            shutdown_flag,
+            manifest.options.clone(),
+        )
+        .await;
+
+        let mut state = recording_state.lock().await;
+        let is_current = state.lifecycle.video_id.as_deref() == Some(&video_id);
+        match uploaded {
+            Ok(()) if is_current => {
+                if let Err(error) = state.transition(&app, RecordingPhase::Completed) {
+                    tracing::error!("{error}");
+                }
+            }
+            Err(error) if is_current => state.fail(&app, error),
+            uploaded => {
+                manifest.phase = match uploaded {
+                    Ok(()) => RecordingPhase::Completed,
+                    Err(_) => RecordingPhase::Failed,
+                };
+                manifest.error = uploaded.err();
+                if let Err(error) = manifest.save(&recording_dir) {
+                    tracing::error!("Failed to save the manifest of {video_id}: {error}");
+                }
+            }
+        }
+        tracing::info!("Finished the upload of {video_id}");
+    });
+}
+
+/// Deletes what's on disk of a recording that won't be uploaded.
+#[tauri::command]
+#[specta::specta]
+pub async fn discard_recording(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+) -> Result<(), CapError> {
+    let state = state.lock().await;
+
+    let recording_dir = recording_dir(&state.data_dir, &video_id);
+    let manifest = load_manifest(&recording_dir, &video_id)?;
+    if state.is_underway(&video_id) || manifest.phase == RecordingPhase::Uploading {
+        return Err(CapError::new(
+            ErrorCode::RecordingInProgress,
+            format!("{video_id} is still being recorded or uploaded"),
+        ));
+    }
+
+    tracing::info!("Discarding {video_id}");
+    state.upload_queue.discard(&video_id);
+    std::fs::remove_dir_all(&recording_dir)
+        .map_err(|e| CapError::io(format!("Failed to remove {video_id}"), &e))
+}
+
+fn load_manifest(recording_dir: &Path, video_id: &str) -> Result<RecordingManifest, CapError> {
+    validate_video_id(video_id)?;
+    RecordingManifest::load(recording_dir).map_err(|error| {
+        CapError::new(
+            ErrorCode::NotFound,
+            format!("There's no recording of {video_id}"),
+        )
+        .caused_by(error)
+    })
+}
+
+fn count_segments(recording_dir: &Path) -> u32 {
+    let Ok(files) = std::fs::read_dir(recording_dir) else {
+        return 0;
+    };
+
+    files
+        .flatten()
+        .filter(|file| {
+            file.path()
+                .extension()
+                .is_some_and(|extension| extension == "ts" || extension == "m4s")
+        })
+        .count() as u32
+}
@@ -0,0 +1,543 @@
+use core::fmt;
+use futures::TryStreamExt;
+use regex::Regex;
+use reqwest::{self, header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use crate::app::config;
+use crate::error::{CapError, ErrorCode};
+use crate::media::{hls, HlsSegmentType};
+use crate::metadata::RecordingMeta;
+use crate::recording::RecordingOptions;
+use crate::utils::ffmpeg_path_as_str;
+
+mod multipart;
+mod queue;
+pub mod s3;
+
+use multipart::Destination;
+pub use multipart::MultipartUpload;
+pub use queue::{
+    get_upload_queue, UploadConnectivity, UploadItem, UploadItemProgress, UploadItemState,
+    UploadQueue, UploadQueueState,
+};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct S3UploadBody {
+    user_id: String,
+    file_key: String,
+    aws_bucket: String,
+    aws_region: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct S3VideoUploadBody {
+    #[serde(flatten)]
+    base: S3UploadBody,
+    duration: String,
+    resolution: String,
+    framerate: String,
+    bandwidth: String,
+    video_codec: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingAssetType {
+    ScreenCapture,
+    CombinedSourceSegment,
+    CombinedSourceInitSegment,
+    CombinedSourcePlaylist,
+    AudioRenditionSegment,
+    MasterPlaylist,
+    RecordingMetadata,
+}
+
+impl fmt::Display for RecordingAssetType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingAssetType::ScreenCapture => write!(f, "ScreenCapture"),
+            RecordingAssetType::CombinedSourceSegment => write!(f, "CombinedSourceSegment"),
+            RecordingAssetType::CombinedSourceInitSegment => {
+                write!(f, "CombinedSourceInitSegment")
+            }
+            RecordingAssetType::CombinedSourcePlaylist => write!(f, "CombinedSourcePlaylist"),
+            RecordingAssetType::AudioRenditionSegment => write!(f, "AudioRenditionSegment"),
+            RecordingAssetType::MasterPlaylist => write!(f, "MasterPlaylist"),
+            RecordingAssetType::RecordingMetadata => write!(f, "RecordingMetadata"),
+        }
+    }
+}
+
+/// An upload that didn't go through, and whether it's worth another go.
+#[derive(Debug)]
+pub struct UploadError {
+    pub error: CapError,
+    pub retry: Retry,
+    /// What the server or storage answered with, when it got that far.
+    pub status: Option<StatusCode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retry {
+    /// The request never got anywhere, most likely because there's no connection.
+    Offline,
+    /// The server had trouble, or asked to slow down, possibly saying for how long.
+    Later(Option<Duration>),
+    /// Trying again won't go any better.
+    Never,
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl From<CapError> for UploadError {
+    fn from(error: CapError) -> Self {
+        Self {
+            error,
+            retry: Retry::Never,
+            status: None,
+        }
+    }
+}
+
+impl From<UploadError> for CapError {
+    fn from(error: UploadError) -> Self {
+        error.error
+    }
+}
+
+/// Told how an upload is going, so it can be shown, and picked up where it left off.
+pub trait UploadObserver: Send + Sync {
+    /// `sent` out of `total` bytes of the file are through.
+    fn progress(&self, _sent: u64, _total: u64) {}
+    /// A multipart upload was started or got another part through, or is done with
+    /// (`None`).
+    fn multipart_changed(&self, _upload: Option<&MultipartUpload>) {}
+}
+
+impl UploadObserver for () {}
+
+/// Whether recordings are uploaded at all: local mode has no Cap server to upload
+/// through, but can still upload straight to storage.
+pub fn uploads_enabled() -> bool {
+    !config::is_local_mode() || s3::storage().is_some()
+}
+
+pub async fn upload_recording_asset(
+    options: RecordingOptions,
+    file_path: PathBuf,
+    file_type: RecordingAssetType,
+) -> Result<String, UploadError> {
+    upload_recording_asset_resumable(options, file_path, file_type, None, Arc::new(())).await
+}
+
+/// Uploads a file in a single request, or in parts when it's large, carrying on with
+/// the multipart upload an earlier attempt got started. Goes straight to storage when
+/// it's set up, and through the Cap server otherwise.
+#[tracing::instrument(skip(observer))]
+pub async fn upload_recording_asset_resumable(
+    options: RecordingOptions,
+    file_path: PathBuf,
+    file_type: RecordingAssetType,
+    multipart: Option<MultipartUpload>,
+    observer: Arc<dyn UploadObserver>,
+) -> Result<String, UploadError> {
+    tracing::info!("Uploading recording asset {file_type}...");
+
+    let file_name = file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| CapError::from("Invalid file path"))?
+        .to_string();
+
+    let file_key_base = format!("{}/{}", options.user_id, options.video_id);
+    let file_key = match file_type {
+        RecordingAssetType::ScreenCapture => {
+            format!("{file_key_base}/screenshot/screen-capture.jpg")
+        }
+        // Every rendition's files are named after it, and playlists reference them
+        // relatively, so they keep their names
+        RecordingAssetType::CombinedSourceSegment
+        | RecordingAssetType::CombinedSourceInitSegment
+        | RecordingAssetType::CombinedSourcePlaylist
+        | RecordingAssetType::AudioRenditionSegment => {
+            format!("{file_key_base}/combined-source/{}", file_name)
+        }
+        RecordingAssetType::MasterPlaylist => {
+            format!(
+                "{file_key_base}/combined-source/{}",
+                hls::MASTER_PLAYLIST_NAME
+            )
+        }
+        RecordingAssetType::RecordingMetadata => {
+            format!("{file_key_base}/{}", RecordingMeta::FILE_NAME)
+        }
+    };
+
+    tracing::info!("File key: {file_key}");
+
+    let body = S3UploadBody {
+        user_id: options.user_id,
+        file_key: file_key.clone(),
+        aws_bucket: options.aws_bucket,
+        aws_region: options.aws_region,
+    };
+
+    let body_json = match file_type {
+        RecordingAssetType::ScreenCapture
+        | RecordingAssetType::CombinedSourceInitSegment
+        | RecordingAssetType::CombinedSourcePlaylist
+        | RecordingAssetType::AudioRenditionSegment
+        | RecordingAssetType::MasterPlaylist
+        | RecordingAssetType::RecordingMetadata => {
+            serde_json::json!(body)
+        }
+        RecordingAssetType::CombinedSourceSegment => {
+            let video_info = log_video_info(&file_path)
+                .map_err(|e| format!("Failed to log video info: {}", e))
+                .and_then(|info| {
+                    get_video_duration(&file_path)
+                        .map(|duration| (info, duration))
+                        .map_err(|e| format!("Failed to get video duration: {}", e))
+                });
+
+            match video_info {
+                Ok(((codec_name, width, height, frame_rate, bit_rate), duration)) => {
+                    serde_json::json!(S3VideoUploadBody {
+                        base: body,
+                        duration: duration.to_string(),
+                        resolution: format!("{}x{}", width, height),
+                        framerate: frame_rate,
+                        bandwidth: bit_rate,
+                        video_codec: codec_name,
+                    })
+                }
+                // Segment metadata is optional, and there's no FFmpeg to get it from
+                // when recording with the built-in encoder
+                Err(error) => {
+                    tracing::warn!("Uploading segment without its metadata: {error}");
+                    serde_json::json!(body)
+                }
+            }
+        }
+    };
+
+    let client = reqwest::Client::new();
+    let file_size = tokio::fs::metadata(&file_path)
+        .await
+        .map_err(|e| CapError::io("Failed to read file", &e))?
+        .len();
+    if let Some(storage) = s3::storage() {
+        let object = s3::Object::new(
+            storage,
+            file_key.clone(),
+            content_type(&file_path),
+            &body_json,
+        );
+        if file_size < multipart::THRESHOLD {
+            object.put(&client, &file_path, file_size, observer).await?;
+        } else {
+            multipart::upload(
+                &client,
+                Destination::Storage(&object),
+                &file_path,
+                file_size,
+                multipart,
+                observer,
+            )
+            .await?;
+        }
+    } else if file_size < multipart::THRESHOLD {
+        post_presigned(
+            &client, &body_json, &file_path, file_name, file_size, observer,
+        )
+        .await?;
+    } else {
+        multipart::upload(
+            &client,
+            Destination::Server(&body_json),
+            &file_path,
+            file_size,
+            multipart,
+            observer,
+        )
+        .await?;
+    }
+
+    // Uploaded files stay on disk, as they're the local copy of the recording that
+    // `export_recording` works from
+    Ok(file_key)
+}
+
+/// Sends the file through the presigned POST the server hands out for it.
+async fn post_presigned(
+    client: &reqwest::Client,
+    body_json: &JsonValue,
+    file_path: &Path,
+    file_name: String,
+    file_size: u64,
+    observer: Arc<dyn UploadObserver>,
+) -> Result<(), UploadError> {
+    let server_response = client
+        .post(server_url("/api/upload/signed"))
+        .json(body_json)
+        .send()
+        .await
+        .map_err(|e| upload_failed("Failed to send request to Next.js handler", e))?;
+    if !server_response.status().is_success() {
+        return Err(rejected("The Next.js handler refused the upload", server_response).await);
+    }
+    let server_response = server_response
+        .text()
+        .await
+        .map_err(|e| upload_failed("Failed to read response from Next.js handler", e))?;
+
+    tracing::info!("Server response: {}", server_response);
+
+    // Anything but presigned post data means the server wouldn't have the upload
+    let presigned_post_data: JsonValue = serde_json::from_str(&server_response)
+        .map_err(|e| upload_rejected("Failed to deserialize server response").caused_by(e))?;
+
+    // Construct the multipart form for the file upload
+    let fields = presigned_post_data["presignedPostData"]["fields"]
+        .as_object()
+        .ok_or_else(|| {
+            upload_rejected("Fields object is missing or not an object").caused_by(&server_response)
+        })?;
+
+    let mut form = reqwest::multipart::Form::new();
+
+    for (key, value) in fields.iter() {
+        let value_str = value
+            .as_str()
+            .ok_or_else(|| upload_rejected(format!("Value for key '{}' is not a string", key)))?;
+        form = form.text(key.to_string(), value_str.to_owned());
+    }
+
+    tracing::info!("Uploading file: {file_path:?}");
+
+    // Streamed from disk, as the file can be anything up to the multipart threshold
+    let file = tokio::fs::File::open(file_path)
+        .await
+        .map_err(|e| CapError::io("Failed to read file", &e))?;
+    let file_part = reqwest::multipart::Part::stream_with_length(
+        file_body(file, 0, file_size, observer),
+        file_size,
+    )
+    .file_name(file_name)
+    .mime_str(content_type(file_path))
+    .map_err(|e| CapError::from(format!("Error setting MIME type: {}", e)))?;
+
+    form = form.part("file", file_part);
+
+    let post_url = presigned_post_data["presignedPostData"]["url"]
+        .as_str()
+        .ok_or_else(|| upload_rejected("URL is missing or not a string"))?;
+
+    tracing::info!("Uploading file to: {}", post_url);
+
+    let response = client.post(post_url).multipart(form).send().await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            tracing::info!("File uploaded successfully");
+            Ok(())
+        }
+        Ok(response) => Err(rejected("Failed to upload file", response).await),
+        Err(e) => Err(upload_failed("Failed to send upload file request", e)),
+    }
+}
+
+fn content_type(file_path: &Path) -> &'static str {
+    match file_path.extension() {
+        Some(ext) if ext == "aac" => "audio/aac",
+        Some(ext) if ext == "mp3" => "audio/mpeg",
+        Some(ext) if ext == "webm" => "audio/webm",
+        Some(ext) if ext == "m3u8" => "application/x-mpegURL",
+        Some(ext) if ext == "json" => "application/json",
+        Some(ext) if ext == "ts" => "video/mp2t",
+        Some(ext) if ext == "m4s" => "video/iso.segment",
+        Some(ext) if ext == "mp4" => "video/mp4",
+        Some(ext) if ext == "jpg" => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Streams what's read to the request, telling the observer how far along it is. `sent`
+/// is what's been sent of the file before, for uploads made in parts.
+fn file_body(
+    reader: impl AsyncRead + Send + Sync + 'static,
+    mut sent: u64,
+    total: u64,
+    observer: Arc<dyn UploadObserver>,
+) -> reqwest::Body {
+    let stream = ReaderStream::new(reader).inspect_ok(move |chunk| {
+        sent += chunk.len() as u64;
+        observer.progress(sent, total);
+    });
+
+    reqwest::Body::wrap_stream(stream)
+}
+
+fn server_url(path: &str) -> String {
+    let server_url_base: &'static str = dotenvy_macro::dotenv!("NEXT_PUBLIC_URL");
+    format!("{server_url_base}{path}")
+}
+
+fn upload_rejected(message: impl Into<String>) -> CapError {
+    CapError::new(ErrorCode::UploadRejected, message)
+}
+
+fn upload_failed(message: &str, error: reqwest::Error) -> UploadError {
+    let retry = if error.is_connect() {
+        Retry::Offline
+    } else {
+        Retry::Later(None)
+    };
+
+    UploadError {
+        error: CapError::new(ErrorCode::UploadFailed, message).caused_by(error),
+        retry,
+        status: None,
+    }
+}
+
+/// Server errors and rate limits are worth another go, whatever else the server
+/// refused won't go any better.
+async fn rejected(message: &str, response: reqwest::Response) -> UploadError {
+    let status = response.status();
+    let retry = match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT => {
+            Retry::Later(retry_after(response.headers()))
+        }
+        status if status.is_server_error() => Retry::Later(retry_after(response.headers())),
+        _ => Retry::Never,
+    };
+
+    let error_body = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "<no response body>".to_string());
+    tracing::error!("{message}. Status: {status}. Body: {error_body}");
+
+    UploadError {
+        error: upload_rejected(format!("{message}. Status: {status}")).caused_by(error_body),
+        retry,
+        status: Some(status),
+    }
+}
+
+/// `Retry-After` holds either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+/// fMP4 segments can't be inspected on their own, as the codec parameters live in the
+/// init segment. FFmpeg's `concat:` protocol lets us probe them as if they were joined.
+fn probe_input(file_path: &Path) -> OsString {
+    let rendition = file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(hls::rendition_of);
+    let init_segment = file_path.with_file_name(hls::init_segment_name(rendition));
+
+    match file_path.extension() {
+        Some(ext) if ext == HlsSegmentType::Fmp4.extension() && init_segment.exists() => {
+            let mut input = OsString::from("concat:");
+            input.push(init_segment);
+            input.push("|");
+            input.push(file_path);
+            input
+        }
+        _ => file_path.as_os_str().to_owned(),
+    }
+}
+
+pub fn get_video_duration(file_path: &Path) -> Result<f64, std::io::Error> {
+    let ffmpeg_binary_path_str = ffmpeg_path_as_str().unwrap().to_owned();
+
+    let output = Command::new(ffmpeg_binary_path_str)
+        .arg("-i")
+        .arg(probe_input(file_path))
+        .output()?;
+
+    let output_str = str::from_utf8(&output.stderr).unwrap();
+    let duration_regex = Regex::new(r"Duration: (\d{2}):(\d{2}):(\d{2})\.(\d{2})").unwrap();
+    let caps = duration_regex.captures(output_str).unwrap();
+
+    let hours: f64 = caps.get(1).unwrap().as_str().parse().unwrap();
+    let minutes: f64 = caps.get(2).unwrap().as_str().parse().unwrap();
+    let seconds: f64 = caps.get(3).unwrap().as_str().parse::<f64>().unwrap();
+    let milliseconds: f64 = caps.get(4).unwrap().as_str().parse::<f64>().unwrap() / 100.0;
+    let duration = hours * 3600.0 + minutes * 60.0 + seconds + milliseconds;
+
+    Ok(duration)
+}
+
+fn log_video_info(file_path: &Path) -> Result<(String, String, String, String, String), String> {
+    let ffprobe_binary_path_str = ffmpeg_path_as_str()
+        .unwrap()
+        .replace("ffmpeg", "ffprobe")
+        .to_owned();
+
+    let output: Output = Command::new(ffprobe_binary_path_str)
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("stream=bit_rate,codec_name,height,width,r_frame_rate")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg(probe_input(file_path))
+        .output()
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe exited with non-zero status: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let info = String::from_utf8_lossy(&output.stdout);
+    let info_parts: Vec<&str> = info.split('\n').collect();
+    let codec_name = info_parts[0].to_string();
+    let width: String = info_parts[1].to_string();
+    let height: String = info_parts[2].to_string();
+
+    // Parse frame rate as a fraction and convert to float
+    let frame_rate_parts: Vec<&str> = info_parts[3].split('/').collect();
+    let frame_rate: f64 =
+        frame_rate_parts[0].parse::<f64>().unwrap() / frame_rate_parts[1].parse::<f64>().unwrap();
+    let frame_rate: String = frame_rate.to_string();
+
+    let bit_rate: String = info_parts[4].to_string();
+
+    Ok((codec_name, width, height, frame_rate, bit_rate))
+}
@@ -0,0 +1,292 @@
+//! Large files go up in parts, each through a URL the server presigns for it or signed
+//! here for storage, so a dropped connection only costs the part that was in flight and
+//! an upload can be picked up again after the app restarts.
+
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::s3::Object;
+use super::{
+    file_body, rejected, server_url, upload_failed, upload_rejected, Retry, UploadError,
+    UploadObserver,
+};
+use crate::error::CapError;
+
+/// Files this large or larger are uploaded in parts.
+pub const THRESHOLD: u64 = 64 * 1024 * 1024;
+const PART_SIZE: u64 = 16 * 1024 * 1024;
+/// S3 takes no more parts than this, which files over 160 GB need larger parts for.
+const MAX_PARTS: u64 = 10_000;
+
+/// A multipart upload under way, kept so it can be resumed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MultipartUpload {
+    pub upload_id: String,
+    pub part_size: u64,
+    /// The upload is only resumed while the file is still this size.
+    pub file_size: u64,
+    pub parts: Vec<CompletedPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletedPart {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+impl MultipartUpload {
+    fn part_count(&self) -> u32 {
+        self.file_size.div_ceil(self.part_size) as u32
+    }
+
+    /// Where a part starts in the file, and how long it is.
+    fn part_range(&self, part_number: u32) -> (u64, u64) {
+        let offset = (part_number - 1) as u64 * self.part_size;
+        (offset, self.part_size.min(self.file_size - offset))
+    }
+
+    fn sent_bytes(&self) -> u64 {
+        self.parts
+            .iter()
+            .map(|part| self.part_range(part.part_number).1)
+            .sum()
+    }
+}
+
+/// Where the parts go.
+pub(super) enum Destination<'a> {
+    /// Through the server, to the key the request describes, the same way the server
+    /// expects for a presigned POST.
+    Server(&'a JsonValue),
+    /// Straight to storage.
+    Storage(&'a Object),
+}
+
+impl Destination<'_> {
+    /// Starts a multipart upload, returning its id.
+    async fn initiate(&self, client: &reqwest::Client) -> Result<String, UploadError> {
+        match self {
+            Self::Server(request) => {
+                let response: JsonValue = call(client, "initiate", (*request).clone()).await?;
+                let upload_id = response["uploadId"]
+                    .as_str()
+                    .ok_or_else(|| upload_rejected("Upload id is missing or not a string"))?;
+                Ok(upload_id.to_string())
+            }
+            Self::Storage(object) => object.create_multipart(client).await,
+        }
+    }
+
+    /// A request to send a part with, still missing its body.
+    async fn part_request(
+        &self,
+        client: &reqwest::Client,
+        upload_id: &str,
+        part_number: u32,
+    ) -> Result<reqwest::RequestBuilder, UploadError> {
+        match self {
+            Self::Server(request) => {
+                let response = call(
+                    client,
+                    "presign-part",
+                    with_fields(
+                        request,
+                        serde_json::json!({
+                            "uploadId": upload_id,
+                            "partNumber": part_number,
+                        }),
+                    ),
+                )
+                .await?;
+                let part_url = response["presignedUrl"]
+                    .as_str()
+                    .ok_or_else(|| upload_rejected("Part URL is missing or not a string"))?;
+                Ok(client.put(part_url))
+            }
+            Self::Storage(object) => object.part_request(client, upload_id, part_number),
+        }
+    }
+
+    async fn complete(
+        &self,
+        client: &reqwest::Client,
+        upload: &MultipartUpload,
+    ) -> Result<(), UploadError> {
+        match self {
+            Self::Server(request) => call(
+                client,
+                "complete",
+                with_fields(
+                    request,
+                    serde_json::json!({
+                        "uploadId": upload.upload_id,
+                        "parts": upload.parts,
+                    }),
+                ),
+            )
+            .await
+            .map(|_| ()),
+            Self::Storage(object) => object.complete_multipart(client, upload).await,
+        }
+    }
+}
+
+/// Uploads the file to the destination in parts.
+pub(super) async fn upload(
+    client: &reqwest::Client,
+    destination: Destination<'_>,
+    file_path: &Path,
+    file_size: u64,
+    resume: Option<MultipartUpload>,
+    observer: Arc<dyn UploadObserver>,
+) -> Result<(), UploadError> {
+    let mut upload = match resume.filter(|upload| upload.file_size == file_size) {
+        Some(upload) => {
+            tracing::info!(
+                "Resuming multipart upload of {file_path:?} with {} of {} parts done",
+                upload.parts.len(),
+                upload.part_count()
+            );
+            upload
+        }
+        None => {
+            let upload_id = destination.initiate(client).await?;
+            tracing::info!("Started multipart upload of {file_path:?}");
+
+            MultipartUpload {
+                upload_id,
+                part_size: PART_SIZE.max(file_size.div_ceil(MAX_PARTS)),
+                file_size,
+                parts: vec![],
+            }
+        }
+    };
+    observer.multipart_changed(Some(&upload));
+
+    for part_number in 1..=upload.part_count() {
+        if upload
+            .parts
+            .iter()
+            .any(|part| part.part_number == part_number)
+        {
+            continue;
+        }
+
+        let request = destination
+            .part_request(client, &upload.upload_id, part_number)
+            .await?;
+        let etag = upload_part(file_path, request, &upload, part_number, &observer)
+            .await
+            .map_err(|error| forget_if_gone(error, &*observer))?;
+        upload.parts.push(CompletedPart { part_number, etag });
+        observer.multipart_changed(Some(&upload));
+    }
+
+    upload.parts.sort_by_key(|part| part.part_number);
+    destination
+        .complete(client, &upload)
+        .await
+        .map_err(|error| forget_if_gone(error, &*observer))?;
+    observer.multipart_changed(None);
+    tracing::info!("Completed multipart upload of {file_path:?}");
+
+    Ok(())
+}
+
+/// Sends a part straight from the file, returning the ETag S3 gave it.
+async fn upload_part(
+    file_path: &Path,
+    request: reqwest::RequestBuilder,
+    upload: &MultipartUpload,
+    part_number: u32,
+    observer: &Arc<dyn UploadObserver>,
+) -> Result<String, UploadError> {
+    let (offset, length) = upload.part_range(part_number);
+    let mut file = tokio::fs::File::open(file_path)
+        .await
+        .map_err(|e| CapError::io("Failed to read file", &e))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| CapError::io("Failed to read file", &e))?;
+
+    tracing::debug!("Uploading part {part_number} of {file_path:?}");
+    let response = request
+        .header(header::CONTENT_LENGTH, length)
+        .body(file_body(
+            file.take(length),
+            upload.sent_bytes(),
+            upload.file_size,
+            observer.clone(),
+        ))
+        .send()
+        .await
+        .map_err(|e| upload_failed("Failed to send upload part request", e))?;
+    if !response.status().is_success() {
+        return Err(rejected(&format!("Failed to upload part {part_number}"), response).await);
+    }
+
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .ok_or_else(|| upload_rejected(format!("Part {part_number} came back without an ETag")))?;
+
+    Ok(etag.to_string())
+}
+
+/// Calls one of the server's multipart endpoints.
+async fn call(
+    client: &reqwest::Client,
+    action: &str,
+    body: JsonValue,
+) -> Result<JsonValue, UploadError> {
+    let response = client
+        .post(server_url(&format!("/api/upload/multipart/{action}")))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| upload_failed("Failed to send request to Next.js handler", e))?;
+    if !response.status().is_success() {
+        return Err(rejected(
+            &format!("The server refused to {action} the upload"),
+            response,
+        )
+        .await);
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| upload_failed("Failed to read response from Next.js handler", e))
+}
+
+/// The upload id is no good anymore once S3 has given up on the upload, so the next
+/// attempt starts over.
+fn forget_if_gone(error: UploadError, observer: &dyn UploadObserver) -> UploadError {
+    if error.status != Some(StatusCode::NOT_FOUND) {
+        return error;
+    }
+
+    tracing::warn!("The multipart upload is gone, starting over");
+    observer.multipart_changed(None);
+    UploadError {
+        retry: Retry::Later(None),
+        ..error
+    }
+}
+
+fn with_fields(request: &JsonValue, fields: JsonValue) -> JsonValue {
+    let mut request = request.clone();
+    if let (Some(request), JsonValue::Object(fields)) = (request.as_object_mut(), fields) {
+        request.extend(fields);
+    }
+
+    request
+}
@@ -0,0 +1,703 @@
+//! Uploads go through a queue kept on disk, so they survive the app quitting and the
+//! connection dropping. Failed uploads are retried with exponential backoff, and the
+//! whole queue holds off while there's no connection, picking up where it left off once
+//! there is again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, State};
+use tauri_specta::Event;
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use super::{
+    upload_recording_asset_resumable, MultipartUpload, RecordingAssetType, Retry, UploadError,
+    UploadObserver,
+};
+use crate::error::{CapError, ErrorCode};
+use crate::media::{UploadStatus, STATUS_INTERVAL};
+use crate::recording::{RecordingOptions, RecordingState};
+
+const FILE_NAME: &str = "upload-queue.json";
+/// Uploads in flight at once.
+const CONCURRENCY: usize = 4;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Attempts at an upload before it's given up on. Those made while offline don't count.
+const MAX_ATTEMPTS: u32 = 10;
+/// How long to wait before checking whether the connection is back, doubling every
+/// time it isn't.
+const OFFLINE_MIN_DELAY: Duration = Duration::from_secs(2);
+const OFFLINE_MAX_DELAY: Duration = Duration::from_secs(60);
+/// How often the worker looks at the queue when nothing else wakes it up.
+const IDLE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, specta::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadItemState {
+    Queued,
+    Uploading,
+    /// Waiting out the backoff after a failed attempt.
+    Retrying,
+    Uploaded,
+    /// Given up on, until the recording's upload is resumed.
+    Failed,
+}
+
+impl UploadItemState {
+    fn is_pending(self) -> bool {
+        matches!(self, Self::Queued | Self::Uploading | Self::Retrying)
+    }
+}
+
+/// A file waiting on (or done with) its upload. Emitted whenever its state changes.
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadItem {
+    pub id: u32,
+    pub video_id: String,
+    pub file_path: PathBuf,
+    pub asset_type: RecordingAssetType,
+    pub state: UploadItemState,
+    /// Failed attempts so far.
+    pub attempts: u32,
+    /// When the next attempt is due while retrying, in milliseconds since the Unix epoch.
+    pub retry_at: Option<u64>,
+    /// Why the last attempt failed.
+    pub error: Option<CapError>,
+}
+
+/// How far along the upload of a file is, emitted every so often while it's sent.
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadItemProgress {
+    pub id: u32,
+    pub video_id: String,
+    pub sent_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Emitted when the queue loses its connection, and when it gets it back.
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadConnectivity {
+    pub online: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadQueueState {
+    /// Uploads are held off while offline.
+    pub online: bool,
+    pub items: Vec<UploadItem>,
+}
+
+/// What's kept on disk.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct QueueContents {
+    next_id: u32,
+    items: Vec<UploadItem>,
+    /// The recordings with files in the queue, as the server needs their options to
+    /// know where the files go.
+    recordings: BTreeMap<String, QueuedRecording>,
+    /// The multipart uploads under way, by item, to carry on with.
+    #[serde(default)]
+    multipart: BTreeMap<u32, MultipartUpload>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueuedRecording {
+    options: RecordingOptions,
+    /// Everything of the recording has been queued.
+    sealed: bool,
+}
+
+pub struct UploadQueue {
+    path: PathBuf,
+    contents: Mutex<QueueContents>,
+    /// Wakes the worker up when there's something new to upload.
+    wake: Notify,
+    online: AtomicBool,
+    app: OnceLock<AppHandle>,
+    /// The latest contents yet to be written to disk.
+    unsaved: Mutex<Option<String>>,
+    save_requested: Notify,
+}
+
+impl UploadQueue {
+    /// Picks up the queue of the last run, with the uploads it was in the middle of
+    /// starting over.
+    pub fn load(data_dir: &Path) -> Arc<Self> {
+        let path = data_dir.join(FILE_NAME);
+        let mut contents = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|error| {
+                tracing::error!("Starting over with an empty upload queue: {error}");
+                QueueContents::default()
+            }),
+            Err(_) => QueueContents::default(),
+        };
+
+        for item in &mut contents.items {
+            if item.state == UploadItemState::Uploading {
+                item.state = UploadItemState::Queued;
+            }
+        }
+        let pending = contents
+            .items
+            .iter()
+            .filter(|item| item.state.is_pending())
+            .count();
+        if pending > 0 {
+            tracing::info!("{pending} uploads left over from the last run");
+        }
+
+        Arc::new(Self {
+            path,
+            contents: Mutex::new(contents),
+            wake: Notify::new(),
+            online: AtomicBool::new(true),
+            app: OnceLock::new(),
+            unsaved: Mutex::new(None),
+            save_requested: Notify::new(),
+        })
+    }
+
+    pub fn start(self: &Arc<Self>, app: AppHandle) {
+        self.app.set(app).ok();
+        tauri::async_runtime::spawn(self.clone().run());
+        tauri::async_runtime::spawn(self.clone().write_saves());
+    }
+
+    /// Adds a file to the queue, unless it's already there. A file whose upload has
+    /// been given up on gets another go.
+    pub fn enqueue(
+        &self,
+        options: &RecordingOptions,
+        file_path: PathBuf,
+        asset_type: RecordingAssetType,
+    ) {
+        let mut contents = self.lock();
+        contents
+            .recordings
+            .entry(options.video_id.clone())
+            .or_insert_with(|| QueuedRecording {
+                options: options.clone(),
+                sealed: false,
+            });
+
+        let item = match contents
+            .items
+            .iter_mut()
+            .find(|item| item.file_path == file_path)
+        {
+            Some(item) if item.state == UploadItemState::Failed => {
+                item.state = UploadItemState::Queued;
+                item.attempts = 0;
+                item.retry_at = None;
+                item.error = None;
+                item.clone()
+            }
+            Some(_) => return,
+            None => {
+                let item = UploadItem {
+                    id: contents.next_id,
+                    video_id: options.video_id.clone(),
+                    file_path,
+                    asset_type,
+                    state: UploadItemState::Queued,
+                    attempts: 0,
+                    retry_at: None,
+                    error: None,
+                };
+                contents.next_id += 1;
+                contents.items.push(item.clone());
+                item
+            }
+        };
+        self.save(&contents);
+        drop(contents);
+
+        self.emit(item);
+        self.wake.notify_one();
+    }
+
+    /// Marks every file of a recording as queued, so it's done once they're uploaded.
+    pub fn seal(&self, options: &RecordingOptions) {
+        let mut contents = self.lock();
+        contents
+            .recordings
+            .entry(options.video_id.clone())
+            .or_insert_with(|| QueuedRecording {
+                options: options.clone(),
+                sealed: false,
+            })
+            .sealed = true;
+        self.save(&contents);
+    }
+
+    pub fn is_sealed(&self, video_id: &str) -> bool {
+        self.lock()
+            .recordings
+            .get(video_id)
+            .is_some_and(|recording| recording.sealed)
+    }
+
+    /// Recordings that were done being queued, but not uploaded yet.
+    pub fn sealed_recordings(&self) -> Vec<String> {
+        self.lock()
+            .recordings
+            .iter()
+            .filter(|(_, recording)| recording.sealed)
+            .map(|(video_id, _)| video_id.clone())
+            .collect()
+    }
+
+    pub fn progress(&self, video_id: &str) -> UploadStatus {
+        let contents = self.lock();
+        let mut status = UploadStatus {
+            video_id: video_id.to_string(),
+            uploaded: 0,
+            pending: 0,
+            failed: 0,
+        };
+        for item in contents
+            .items
+            .iter()
+            .filter(|item| item.video_id == video_id)
+        {
+            match item.state {
+                UploadItemState::Uploaded => status.uploaded += 1,
+                UploadItemState::Failed => status.failed += 1,
+                _ => status.pending += 1,
+            }
+        }
+
+        status
+    }
+
+    /// How the upload of a sealed recording went, once nothing of it is pending.
+    pub fn outcome(&self, video_id: &str) -> Option<Result<(), CapError>> {
+        let contents = self.lock();
+        let Some(recording) = contents.recordings.get(video_id) else {
+            return Some(Err(CapError::new(
+                ErrorCode::NotFound,
+                format!("The uploads of {video_id} were discarded"),
+            )));
+        };
+        if !recording.sealed {
+            return None;
+        }
+
+        let mut items = contents
+            .items
+            .iter()
+            .filter(|item| item.video_id == video_id);
+        if items.clone().any(|item| item.state.is_pending()) {
+            return None;
+        }
+        // A recording missing files is no good, so any of them failing fails it
+        match items.find(|item| item.state == UploadItemState::Failed) {
+            Some(failed) => Some(Err(failed.error.clone().unwrap_or_else(|| {
+                CapError::new(ErrorCode::UploadFailed, "An upload was given up on")
+            }))),
+            None => Some(Ok(())),
+        }
+    }
+
+    /// Drops a recording from the queue along with its uploads, once they're done or
+    /// won't be needed. Those in flight still finish.
+    pub fn discard(&self, video_id: &str) {
+        let mut contents = self.lock();
+        contents.items.retain(|item| item.video_id != video_id);
+        let QueueContents {
+            items, multipart, ..
+        } = &mut *contents;
+        multipart.retain(|id, _| items.iter().any(|item| item.id == *id));
+        if contents.recordings.remove(video_id).is_some() {
+            self.save(&contents);
+        }
+    }
+
+    pub fn snapshot(&self) -> UploadQueueState {
+        UploadQueueState {
+            online: self.online.load(Ordering::SeqCst),
+            items: self.lock().items.clone(),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut uploads = JoinSet::new();
+        // While offline, a single upload at a time checks whether the connection's back
+        let mut offline_until: Option<Instant> = None;
+        let mut offline_delay = OFFLINE_MIN_DELAY;
+
+        loop {
+            let now = Instant::now();
+            let limit = match offline_until {
+                Some(until) if until > now => 0,
+                Some(_) => 1,
+                None => CONCURRENCY,
+            };
+            while uploads.len() < limit {
+                let Some((item, options, multipart)) = self.start_next() else {
+                    break;
+                };
+                self.emit(item.clone());
+
+                let observer = Arc::new(ItemObserver {
+                    queue: self.clone(),
+                    id: item.id,
+                    video_id: item.video_id.clone(),
+                    emitted_at: Mutex::new(None),
+                });
+                uploads.spawn(async move {
+                    let upload = tokio::spawn(upload_recording_asset_resumable(
+                        options,
+                        item.file_path,
+                        item.asset_type,
+                        multipart,
+                        observer,
+                    ));
+                    let result = upload
+                        .await
+                        .unwrap_or_else(|error| Err(CapError::from(error.to_string()).into()));
+                    (item.id, result)
+                });
+            }
+
+            let wake_at = match offline_until {
+                Some(until) if uploads.is_empty() => until,
+                _ => now + self.next_retry().unwrap_or(IDLE_INTERVAL),
+            };
+            tokio::select! {
+                Some(Ok((id, result))) = uploads.join_next() => {
+                    match &result {
+                        Ok(_) => {
+                            offline_until = None;
+                            if !self.online.swap(true, Ordering::SeqCst) {
+                                tracing::info!("Back online, resuming uploads");
+                                self.emit(UploadConnectivity { online: true });
+                            }
+                        }
+                        Err(UploadError { retry: Retry::Offline, .. }) => {
+                            if self.online.swap(false, Ordering::SeqCst) {
+                                tracing::warn!("Offline, holding off uploads");
+                                self.emit(UploadConnectivity { online: false });
+                                offline_delay = OFFLINE_MIN_DELAY;
+                            } else if uploads.is_empty() {
+                                offline_delay = (offline_delay * 2).min(OFFLINE_MAX_DELAY);
+                            }
+                            offline_until = Some(Instant::now() + offline_delay);
+                        }
+                        Err(_) => {}
+                    }
+                    self.finish(id, result);
+                }
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep_until(wake_at) => {}
+            }
+        }
+    }
+
+    /// Takes the next upload that's due, in the order they were queued.
+    fn start_next(&self) -> Option<(UploadItem, RecordingOptions, Option<MultipartUpload>)> {
+        let mut contents = self.lock();
+        let now = now_millis();
+
+        let QueueContents {
+            items,
+            recordings,
+            multipart,
+            ..
+        } = &mut *contents;
+        let (item, options) = items.iter_mut().find_map(|item| {
+            let due = match item.state {
+                UploadItemState::Queued => true,
+                UploadItemState::Retrying => item.retry_at.is_some_and(|at| at <= now),
+                _ => false,
+            };
+            let options = recordings.get(&item.video_id).filter(|_| due)?;
+            Some((item, options.options.clone()))
+        })?;
+
+        item.state = UploadItemState::Uploading;
+        item.retry_at = None;
+        let item = item.clone();
+        let multipart = multipart.get(&item.id).cloned();
+        self.save(&contents);
+
+        Some((item, options, multipart))
+    }
+
+    fn set_multipart(&self, id: u32, upload: Option<&MultipartUpload>) {
+        let mut contents = self.lock();
+        // It might have been discarded in the meantime
+        if !contents.items.iter().any(|item| item.id == id) {
+            return;
+        }
+
+        match upload {
+            Some(upload) => contents.multipart.insert(id, upload.clone()),
+            None => contents.multipart.remove(&id),
+        };
+        self.save(&contents);
+    }
+
+    fn finish(&self, id: u32, result: Result<String, UploadError>) {
+        let mut contents = self.lock();
+        // It might have been discarded in the meantime
+        let Some(item) = contents.items.iter_mut().find(|item| item.id == id) else {
+            return;
+        };
+
+        match result {
+            Ok(_) => {
+                item.state = UploadItemState::Uploaded;
+                item.error = None;
+            }
+            Err(UploadError { error, retry, .. }) => {
+                tracing::warn!("Failed to upload {:?}: {error}", item.file_path);
+                let random = RandomState::new().build_hasher().finish();
+                match after_failure(item.attempts, &retry, random) {
+                    AfterFailure::Requeue => item.state = UploadItemState::Queued,
+                    AfterFailure::Retry(delay) => {
+                        item.attempts += 1;
+                        item.state = UploadItemState::Retrying;
+                        item.retry_at = Some(now_millis() + delay.as_millis() as u64);
+                    }
+                    AfterFailure::GiveUp => {
+                        item.attempts += 1;
+                        item.state = UploadItemState::Failed;
+                    }
+                }
+                item.error = Some(error);
+            }
+        }
+        let item = item.clone();
+        self.save(&contents);
+        drop(contents);
+
+        self.emit(item);
+    }
+
+    /// Until the earliest retry is due.
+    fn next_retry(&self) -> Option<Duration> {
+        let now = now_millis();
+        self.lock()
+            .items
+            .iter()
+            .filter(|item| item.state == UploadItemState::Retrying)
+            .filter_map(|item| item.retry_at)
+            .min()
+            .map(|at| Duration::from_millis(at.saturating_sub(now)))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueContents> {
+        self.contents.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Leaves the writing to `write_saves`, as the queue changes with every part that's
+    /// uploaded.
+    fn save(&self, contents: &QueueContents) {
+        match serde_json::to_string(contents) {
+            Ok(json) => {
+                *self.unsaved.lock().unwrap_or_else(|e| e.into_inner()) = Some(json);
+                self.save_requested.notify_one();
+            }
+            Err(error) => tracing::error!("Failed to save the upload queue: {error}"),
+        }
+    }
+
+    /// Writes the queue to disk off the runtime. Changes made while a write is under way
+    /// are written together once it's done, rather than one by one.
+    async fn write_saves(self: Arc<Self>) {
+        loop {
+            self.save_requested.notified().await;
+            let Some(json) = self
+                .unsaved
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take()
+            else {
+                continue;
+            };
+
+            let path = self.path.clone();
+            let written = tokio::task::spawn_blocking(move || {
+                let tmp_path = path.with_extension("json.tmp");
+                std::fs::write(&tmp_path, json).and_then(|_| std::fs::rename(&tmp_path, &path))
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|written| written.map_err(|e| e.to_string()));
+            if let Err(error) = written {
+                tracing::error!("Failed to save the upload queue: {error}");
+            }
+        }
+    }
+
+    fn emit<E: Event + Serialize + Clone>(&self, event: E) {
+        let Some(app) = self.app.get() else {
+            return;
+        };
+        if let Err(error) = event.emit(app) {
+            tracing::warn!("Failed to emit upload queue event: {error}");
+        }
+    }
+}
+
+/// Follows the upload of an item along for the queue.
+struct ItemObserver {
+    queue: Arc<UploadQueue>,
+    id: u32,
+    video_id: String,
+    emitted_at: Mutex<Option<Instant>>,
+}
+
+impl UploadObserver for ItemObserver {
+    fn progress(&self, sent: u64, total: u64) {
+        let mut emitted_at = self.emitted_at.lock().unwrap_or_else(|e| e.into_inner());
+        let too_soon = emitted_at.is_some_and(|at| at.elapsed() < STATUS_INTERVAL);
+        if too_soon && sent < total {
+            return;
+        }
+        *emitted_at = Some(Instant::now());
+
+        self.queue.emit(UploadItemProgress {
+            id: self.id,
+            video_id: self.video_id.clone(),
+            sent_bytes: sent,
+            total_bytes: total,
+        });
+    }
+
+    fn multipart_changed(&self, upload: Option<&MultipartUpload>) {
+        self.queue.set_multipart(self.id, upload);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AfterFailure {
+    /// Back in line without counting as an attempt, as it never got to the server.
+    Requeue,
+    Retry(Duration),
+    GiveUp,
+}
+
+/// What to do about an upload that just failed, after failing `attempts` times before.
+/// `random` picks the jitter.
+fn after_failure(attempts: u32, retry: &Retry, random: u64) -> AfterFailure {
+    match retry {
+        Retry::Offline => AfterFailure::Requeue,
+        Retry::Later(after) if attempts + 1 < MAX_ATTEMPTS => {
+            AfterFailure::Retry(after.unwrap_or_else(|| backoff(attempts + 1, random)))
+        }
+        Retry::Later(_) | Retry::Never => AfterFailure::GiveUp,
+    }
+}
+
+/// Doubles with every attempt, with up to half of it left to chance so uploads that
+/// failed together don't all come back at once.
+fn backoff(attempts: u32, random: u64) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(MAX_DELAY);
+    let jitter = random % (delay.as_millis() as u64 / 2 + 1);
+
+    delay - Duration::from_millis(jitter)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Every upload in the queue, and whether there's a connection to upload them over.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_upload_queue(
+    state: State<'_, Arc<tokio::sync::Mutex<RecordingState>>>,
+) -> Result<UploadQueueState, CapError> {
+    Ok(state.lock().await.upload_queue.snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn doubles_backoff_up_to_max() {
+        assert_eq!(backoff(1, 0), secs(1));
+        assert_eq!(backoff(2, 0), secs(2));
+        assert_eq!(backoff(5, 0), secs(16));
+        assert_eq!(backoff(9, 0), secs(256));
+        assert_eq!(backoff(10, 0), MAX_DELAY);
+        assert_eq!(backoff(u32::MAX, 0), MAX_DELAY);
+    }
+
+    #[test]
+    fn jitters_by_at_most_half() {
+        for random in [1, 999, 2000, 12_345, u64::MAX] {
+            let delay = backoff(3, random);
+            assert!((secs(2)..=secs(4)).contains(&delay), "{delay:?}");
+        }
+        assert_eq!(backoff(3, 2000), secs(2));
+        assert_eq!(backoff(3, 2001), secs(4));
+    }
+
+    #[test]
+    fn retries_until_max_attempts() {
+        for attempts in 0..MAX_ATTEMPTS - 1 {
+            assert_eq!(
+                after_failure(attempts, &Retry::Later(None), 0),
+                AfterFailure::Retry(backoff(attempts + 1, 0))
+            );
+        }
+        assert_eq!(
+            after_failure(MAX_ATTEMPTS - 1, &Retry::Later(None), 0),
+            AfterFailure::GiveUp
+        );
+    }
+
+    #[test]
+    fn honors_retry_after() {
+        assert_eq!(
+            after_failure(4, &Retry::Later(Some(secs(30))), 0),
+            AfterFailure::Retry(secs(30))
+        );
+        assert_eq!(
+            after_failure(MAX_ATTEMPTS - 1, &Retry::Later(Some(secs(30))), 0),
+            AfterFailure::GiveUp
+        );
+    }
+
+    #[test]
+    fn gives_up_on_errors_that_would_happen_again() {
+        assert_eq!(after_failure(0, &Retry::Never, 0), AfterFailure::GiveUp);
+    }
+
+    #[test]
+    fn requeues_offline_without_counting() {
+        for attempts in [0, MAX_ATTEMPTS - 1, MAX_ATTEMPTS] {
+            assert_eq!(
+                after_failure(attempts, &Retry::Offline, 0),
+                AfterFailure::Requeue
+            );
+        }
+    }
+}
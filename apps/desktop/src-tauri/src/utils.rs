@@ -71,6 +71,56 @@ pub fn create_named_pipe(path: &Path) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
+/// Stops a process left running by a previous run of the app, as long as it's still
+/// `name` rather than something else that got its pid since. Returns whether there was
+/// anything to stop.
+#[cfg(unix)]
+pub fn stop_orphaned_process(pid: u32, name: &str) -> bool {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let is_running = || {
+        Command::new("ps")
+            .args(["-p", &pid.to_string(), "-o", "comm="])
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(name))
+    };
+    if !is_running() {
+        return false;
+    }
+
+    // FFmpeg writes out what it has on SIGTERM, finishing off the playlists
+    let process = Pid::from_raw(pid as i32);
+    kill(process, Signal::SIGTERM).ok();
+    for _ in 0..30 {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        if !is_running() {
+            return true;
+        }
+    }
+    kill(process, Signal::SIGKILL).ok();
+
+    true
+}
+
+#[cfg(windows)]
+pub fn stop_orphaned_process(pid: u32, name: &str) -> bool {
+    let is_running = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(name));
+    if !is_running {
+        return false;
+    }
+
+    Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/F"])
+        .status()
+        .ok();
+
+    true
+}
+
 pub fn log_debug_error(error: impl std::fmt::Display) {
     tracing::debug!("Error: {error}")
 }
@@ -15,6 +15,7 @@ import { Window } from "@/components/icons/Window";
 import { Logo } from "@/components/icons/Logo";
 import { ActionButton } from "./ActionButton";
 import { ActionSelect } from "./ActionSelect";
+import { UnfinishedRecordings } from "./UnfinishedRecordings";
 import { Button } from "@cap/ui";
 import { emit, listen, UnlistenFn } from "@tauri-apps/api/event";
 import { register, unregister } from "@tauri-apps/plugin-global-shortcut";
@@ -26,7 +27,7 @@ import {
   isUserPro,
 } from "@cap/utils";
 import { openLinkInBrowser } from "@/utils/helpers";
-import { commands } from "@/utils/commands";
+import { commands, CaptureDisplay } from "@/utils/commands";
 import toast, { Toaster } from "react-hot-toast";
 import { authFetch } from "@/utils/auth/helpers";
 import { setTrayStopIcon } from "@/utils/tray";
@@ -59,11 +60,29 @@ export const Recorder = () => {
   const proCheckPromise = isUserPro();
   const [proCheck, setProCheck] = useState<boolean>(false);
   const [limitReached, setLimitReached] = useState(false);
+  const [displays, setDisplays] = useState<CaptureDisplay[]>([]);
+  const [selectedDisplayId, setSelectedDisplayId] = useState<number | null>(
+    null
+  );
 
   useEffect(() => {
     proCheckPromise.then((result) => setProCheck(Boolean(result)));
   }, [proCheckPromise]);
 
+  useEffect(() => {
+    commands
+      .enumerateDisplays()
+      .then((displays) => {
+        setDisplays(displays);
+        setSelectedDisplayId((id) =>
+          displays.some((display) => display.id === id)
+            ? id
+            : displays[0]?.id ?? null
+        );
+      })
+      .catch((error) => console.error("Failed to enumerate displays:", error));
+  }, []);
+
   const selectDevice = (kind: DeviceKind, device: Device | null) =>
     emit("cap://av/set-device", { type: kind, device: device }).catch((error) =>
       console.log("Failed to emit cap://av/set-device event:", error)
@@ -163,6 +182,11 @@ export const Recorder = () => {
       console.log("Recording has already started.");
       return;
     }
+    if (selectedDisplayId === null) {
+      toast.error("No display to record - please try again.");
+      setStartingRecording(false);
+      return;
+    }
     console.log("Starting dual recording...");
     setIsRecording(true);
     setStartingRecording(false);
@@ -179,19 +203,29 @@ export const Recorder = () => {
     });
     setTrayStopIcon(true);
     try {
-      await commands
+      const result = await commands
         .startDualRecording({
           user_id: videoData.user_id,
           video_id: videoData.id,
           audio_name: selectedAudioDevice?.label ?? "None",
           aws_region: videoData.aws_region,
           aws_bucket: videoData.aws_bucket,
-          screen_index: "Capture screen 0",
+          screen_index: String(selectedDisplayId),
+          capture_mode: { type: "screen" },
+          crop_area: null,
+          quality: null,
           video_index: String(selectedVideoDevice?.index),
         })
         .catch((error) => {
           console.error("Error invoking start_screen_recording:", error);
         });
+      if (result?.status === "error") {
+        console.error("Error starting screen recording:", result.error);
+        toast.error(result.error.message);
+        setIsRecording(false);
+        setHasStartedRecording(false);
+        setTrayStopIcon(false);
+      }
     } catch (error) {
       console.error("Error starting screen recording:", error);
       setStartingRecording(false);
@@ -407,6 +441,22 @@ export const Recorder = () => {
                     active={selectedDisplayType === "window"}
                   />
                 </div>
+                {displays.length > 1 && (
+                  <div className="mt-2">
+                    <ActionSelect
+                      options={displays.map((display) => ({
+                        value: display.id,
+                        label: `${display.name || `Display ${display.id}`} (${
+                          display.width
+                        }x${display.height})`,
+                      }))}
+                      status="on"
+                      iconEnabled={<Screen className="w-5 h-5" />}
+                      selectedValue={selectedDisplayId ?? undefined}
+                      onSelect={(value) => setSelectedDisplayId(Number(value))}
+                    />
+                  </div>
+                )}
               </div>
               <div>
                 <label className="text-sm font-medium">Webcam / Video</label>
@@ -449,6 +499,7 @@ export const Recorder = () => {
               </div>
             </div>
           </div>
+          {!isRecording && <UnfinishedRecordings />}
           <Button
             {...(isRecording && { variant: "destructive" })}
             className="w-full flex mx-auto"
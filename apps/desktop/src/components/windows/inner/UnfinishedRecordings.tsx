@@ -0,0 +1,105 @@
+"use client";
+
+import { useState, useEffect } from "react";
+import { Button } from "@cap/ui";
+import toast from "react-hot-toast";
+import { commands, UnfinishedRecording } from "@/utils/commands";
+
+/**
+ * Offers to upload (or throw away) recordings an earlier run didn't get to finish,
+ * or whose upload failed. Recordings whose upload is already being resumed aren't
+ * shown.
+ */
+export const UnfinishedRecordings = () => {
+  const [recordings, setRecordings] = useState<UnfinishedRecording[]>([]);
+  const [busyVideoId, setBusyVideoId] = useState<string | null>(null);
+
+  useEffect(() => {
+    commands
+      .listUnfinishedRecordings()
+      .then((result) => {
+        if (result.status === "error") {
+          console.error("Failed to list unfinished recordings:", result.error);
+          return;
+        }
+        setRecordings(
+          result.data.filter((recording) => recording.phase === "failed")
+        );
+      })
+      .catch((error) =>
+        console.error("Failed to list unfinished recordings:", error)
+      );
+  }, []);
+
+  const handle = async (
+    recording: UnfinishedRecording,
+    action: "resume" | "discard"
+  ) => {
+    setBusyVideoId(recording.videoId);
+    const result =
+      action === "resume"
+        ? await commands.resumeUpload(recording.videoId)
+        : await commands.discardRecording(recording.videoId);
+    setBusyVideoId(null);
+
+    if (result.status === "error") {
+      toast.error(result.error.message);
+      return;
+    }
+    if (action === "resume") {
+      toast.success("Uploading the recording in the background.");
+    }
+    setRecordings((recordings) =>
+      recordings.filter(({ videoId }) => videoId !== recording.videoId)
+    );
+  };
+
+  if (recordings.length === 0) {
+    return null;
+  }
+
+  return (
+    <div className="mb-4 p-3 rounded-[15px] border-2 border-gray-200 space-y-3">
+      <p className="text-sm font-medium">
+        {recordings.length === 1
+          ? "A recording didn't finish uploading"
+          : `${recordings.length} recordings didn't finish uploading`}
+      </p>
+      {recordings.map((recording) => (
+        <div key={recording.videoId} className="space-y-2">
+          <div>
+            <p className="text-sm">
+              {new Date(recording.startedAt * 1000).toLocaleString()} ·{" "}
+              {recording.segments}{" "}
+              {recording.segments === 1 ? "segment" : "segments"}
+            </p>
+            {recording.error && (
+              <p className="text-xs text-gray-600">
+                {recording.error.message}
+              </p>
+            )}
+          </div>
+          <div className="flex items-center space-x-2">
+            <Button
+              size="sm"
+              className="flex-1"
+              disabled={busyVideoId !== null}
+              onClick={() => handle(recording, "resume")}
+            >
+              Upload
+            </Button>
+            <Button
+              size="sm"
+              variant="outline"
+              className="flex-1"
+              disabled={busyVideoId !== null}
+              onClick={() => handle(recording, "discard")}
+            >
+              Discard
+            </Button>
+          </div>
+        </div>
+      ))}
+    </div>
+  );
+};
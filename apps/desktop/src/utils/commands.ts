@@ -5,7 +5,7 @@
 
 
 export const commands = {
-async startDualRecording(options: RecordingOptions) : Promise<Result<null, string>> {
+async startDualRecording(options: RecordingOptions) : Promise<Result<null, CapError>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("start_dual_recording", { options }) };
 } catch (e) {
@@ -13,7 +13,7 @@ async startDualRecording(options: RecordingOptions) : Promise<Result<null, strin
     else return { status: "error", error: e  as any };
 }
 },
-async stopAllRecordings() : Promise<Result<null, string>> {
+async stopAllRecordings() : Promise<Result<null, CapError>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("stop_all_recordings") };
 } catch (e) {
@@ -21,10 +21,70 @@ async stopAllRecordings() : Promise<Result<null, string>> {
     else return { status: "error", error: e  as any };
 }
 },
+async pauseRecording() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("pause_recording") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async resumeRecording() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("resume_recording") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * How much captured data is waiting to be encoded, video first. Empty when not
+ * recording.
+ */
+async getQueueDepths() : Promise<Result<QueueDepth[], CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_queue_depths") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * How FFmpeg is keeping up with the recording. Nothing when not recording, or not
+ * recording through FFmpeg.
+ */
+async getEncoderProgress() : Promise<Result<EncoderProgress | null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_encoder_progress") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * Where the current (or last) recording is in its lifecycle.
+ */
+async getRecordingStatus() : Promise<Result<RecordingLifecycle, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_recording_status") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
 async enumerateAudioDevices() : Promise<string[]> {
     return await TAURI_INVOKE("enumerate_audio_devices");
 },
-async startServer() : Promise<Result<number, string>> {
+async enumerateDisplays() : Promise<CaptureDisplay[]> {
+    return await TAURI_INVOKE("enumerate_displays");
+},
+async enumerateWindows() : Promise<CaptureWindow[]> {
+    return await TAURI_INVOKE("enumerate_windows");
+},
+async enumerateVideoCodecs() : Promise<VideoCodecInfo[]> {
+    return await TAURI_INVOKE("enumerate_video_codecs");
+},
+async startServer() : Promise<Result<number, CapError>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("start_server") };
 } catch (e) {
@@ -32,28 +92,58 @@ async startServer() : Promise<Result<number, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async openScreenCapturePreferences() : Promise<void> {
-    await TAURI_INVOKE("open_screen_capture_preferences");
+async openScreenCapturePreferences() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("open_screen_capture_preferences") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 },
-async openMicPreferences() : Promise<void> {
-    await TAURI_INVOKE("open_mic_preferences");
+async openMicPreferences() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("open_mic_preferences") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 },
-async openCameraPreferences() : Promise<void> {
-    await TAURI_INVOKE("open_camera_preferences");
+async openCameraPreferences() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("open_camera_preferences") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 },
 async hasScreenCaptureAccess() : Promise<boolean> {
     return await TAURI_INVOKE("has_screen_capture_access");
 },
-async resetScreenPermissions() : Promise<void> {
-    await TAURI_INVOKE("reset_screen_permissions");
+async resetScreenPermissions() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("reset_screen_permissions") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 },
-async resetMicrophonePermissions() : Promise<void> {
-    await TAURI_INVOKE("reset_microphone_permissions");
+async resetMicrophonePermissions() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("reset_microphone_permissions") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 },
-async resetCameraPermissions() : Promise<void> {
-    await TAURI_INVOKE("reset_camera_permissions");
+async resetCameraPermissions() : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("reset_camera_permissions") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 },
-async closeWebview(label: string) : Promise<Result<null, string>> {
+async closeWebview(label: string) : Promise<Result<null, CapError>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("close_webview", { label }) };
 } catch (e) {
@@ -61,19 +151,112 @@ async closeWebview(label: string) : Promise<Result<null, string>> {
     else return { status: "error", error: e  as any };
 }
 },
-async makeWebviewTransparent(label: string) : Promise<Result<null, string>> {
+async makeWebviewTransparent(label: string) : Promise<Result<null, CapError>> {
     try {
     return { status: "ok", data: await TAURI_INVOKE("make_webview_transparent", { label }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
 }
+},
+async exportRecording(videoId: string, destination: string, format: ExportFormat) : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("export_recording", { videoId, destination, format }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * Recordings left unfinished by an earlier run, or whose upload failed, most recent
+ * first.
+ */
+async listUnfinishedRecordings() : Promise<Result<UnfinishedRecording[], CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("list_unfinished_recordings") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * Uploads a recording that didn't get to be uploaded in full, in the background.
+ */
+async resumeUpload(videoId: string) : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("resume_upload", { videoId }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * Deletes what's on disk of a recording that won't be uploaded.
+ */
+async discardRecording(videoId: string) : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("discard_recording", { videoId }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * Every upload in the queue, and whether there's a connection to upload them over.
+ */
+async getUploadQueue() : Promise<Result<UploadQueueState, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_upload_queue") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * The storage uploads go straight to, if they skip the Cap server, without its secret key.
+ */
+async getStorageConfig() : Promise<Result<StorageConfig | null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_storage_config") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+/**
+ * Has uploads go straight to storage, or back through the Cap server for `None`, from
+ * the next upload on.
+ */
+async setStorageConfig(config: StorageConfig | null) : Promise<Result<null, CapError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("set_storage_config", { config }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 }
 }
 
 /** user-defined events **/
 
 
+export const events = __makeEvents__<{
+exportProgress: ExportProgress,
+recordingStateChanged: RecordingStateChanged,
+recordingStatus: RecordingStatus,
+uploadConnectivity: UploadConnectivity,
+uploadItem: UploadItem,
+uploadItemProgress: UploadItemProgress,
+uploadStatus: UploadStatus
+}>({
+exportProgress: "export-progress",
+recordingStateChanged: "recording-state-changed",
+recordingStatus: "recording-status",
+uploadConnectivity: "upload-connectivity",
+uploadItem: "upload-item",
+uploadItemProgress: "upload-item-progress",
+uploadStatus: "upload-status"
+})
 
 /** user-defined constants **/
 
@@ -81,7 +264,295 @@ async makeWebviewTransparent(label: string) : Promise<Result<null, string>> {
 
 /** user-defined types **/
 
-export type RecordingOptions = { user_id: string; video_id: string; screen_index: string; video_index: string; audio_name: string; aws_region: string; aws_bucket: string }
+export type AudioInputOptions = { source: AudioSource; 
+/**
+ * Linear gain applied before mixing, where 1.0 leaves the input untouched.
+ */
+gain: number }
+export type AudioMixMode = 
+/**
+ * All inputs are mixed down into a single audio track.
+ */
+"mixed" | 
+/**
+ * Every input is written as its own alternate audio rendition in the HLS output,
+ * so they can be rebalanced after the fact.
+ */
+"separate_tracks"
+export type AudioSource = { type: "microphone"; name: string } | 
+/**
+ * Whatever the computer is playing, which can't be recorded on macOS yet. On Linux,
+ * `monitor` is the PulseAudio/PipeWire monitor source to record from, defaulting to
+ * the one of the default sink.
+ */
+{ type: "system_audio"; monitor: string | null }
+export type CapError = { code: ErrorCode; 
+/**
+ * Fit to show the user.
+ */
+message: string; 
+/**
+ * What led to the error, outermost first. Meant for logs and bug reports.
+ */
+causes: string[] }
+export type CaptureDisplay = { id: number; name: string; 
+/**
+ * In pixels.
+ */
+width: number; height: number; 
+/**
+ * Pixels per point.
+ */
+scale_factor: number }
+/**
+ * What gets recorded. Window captures follow the window around when it's moved or
+ * partially covered, and get letterboxed into the initial frame size when it's resized.
+ */
+export type CaptureMode = { type: "screen" } | { type: "window"; id: number }
+/**
+ * A rectangle of a display, in points: the display sizes `enumerate_displays` returns
+ * divided by their scale factor.
+ */
+export type CaptureRegion = { x: number; y: number; width: number; height: number }
+export type CaptureWindow = { id: number; title: string; owner: string | null; bounds: WindowBounds | null }
+export type EncoderPreset = "ultrafast" | "superfast" | "veryfast" | "faster" | "fast" | "medium"
+export type EncoderProgress = { frame: number; fps: number; 
+/**
+ * How fast encoding goes compared to real time, since the start.
+ */
+speed: number | null; bitrateKbps: number | null; outTimeUs: number; 
+/**
+ * Frames FFmpeg duplicated or dropped to hold the output frame rate.
+ */
+dupFrames: number; dropFrames: number }
+/**
+ * Stable across releases: the frontend switches on these.
+ */
+export type ErrorCode = 
+/**
+ * Screen recording (or another system permission) hasn't been granted.
+ */
+"permission_missing" | 
+/**
+ * The display, window or audio device went away.
+ */
+"device_gone" | "disk_full" | 
+/**
+ * FFmpeg (or the encoder standing in for it) wouldn't start, or exited before it
+ * was done.
+ */
+"ffmpeg_crashed" | 
+/**
+ * The server or storage refused an upload.
+ */
+"upload_rejected" | 
+/**
+ * An upload didn't make it to the server.
+ */
+"upload_failed" | "invalid_options" | 
+/**
+ * The recording can't do that in its current phase, like stopping one that's still
+ * starting.
+ */
+"illegal_transition" | "recording_in_progress" | 
+/**
+ * A capture queue went over its budget with the `abort` overflow policy, as the
+ * encoder couldn't keep up.
+ */
+"queue_overflow" | 
+/**
+ * The app quit before the recording (or its upload) was done.
+ */
+"interrupted" | "not_found" | 
+/**
+ * Not available on this platform.
+ */
+"unsupported" | "io" | "internal"
+export type ExportFormat = "mp4" | "webm"
+export type ExportProgress = { video_id: string; 
+/**
+ * Between 0 and 1.
+ */
+progress: number; exported_secs: number; duration_secs: number }
+export type HlsSegmentType = "mpeg_ts" | "fmp4"
+/**
+ * What happens to captured data that doesn't fit in its queue's budget.
+ */
+export type OverflowPolicy = 
+/**
+ * The data is dropped, and counted, until the queue has drained enough.
+ */
+"drop" | 
+/**
+ * The data goes to a ring buffer on disk, and only gets dropped once that's full
+ * too.
+ */
+"spill" | 
+/**
+ * The recording stops, keeping everything recorded up to that point, and fails with
+ * a `queue_overflow` error.
+ */
+"abort"
+/**
+ * How much a queue holds right now, and what it went through so far.
+ */
+export type QueueDepth = { queue: string; queuedBytes: number; queuedBuffers: number; budgetBytes: number; peakBytes: number; 
+/**
+ * Held on disk rather than in memory.
+ */
+spilledBytes: number; 
+/**
+ * Taken into the queue, in memory or spilled, since it was created.
+ */
+sentBuffers: number; droppedBuffers: number; overflowed: boolean }
+export type RateControl = { type: "crf"; value: number } | { type: "bitrate"; kbps: number }
+export type RecordingAssetType = "screen_capture" | "combined_source_segment" | "combined_source_init_segment" | "combined_source_playlist" | "audio_rendition_segment" | "master_playlist" | "recording_metadata"
+export type RecordingLifecycle = { phase: RecordingPhase; 
+/**
+ * The recording the phase is about, kept once it has completed or failed.
+ */
+videoId: string | null; 
+/**
+ * Why the recording failed.
+ */
+error: CapError | null }
+export type RecordingOptions = { user_id: string; video_id: string; screen_index: string; video_index: string; audio_name: string; aws_region: string; aws_bucket: string; capture_mode: CaptureMode; crop_area: CaptureRegion | null; quality: RecordingQuality | null; 
+/**
+ * Takes precedence over `audio_name` when not empty.
+ */
+audio_inputs?: AudioInputOptions[]; audio_mix?: AudioMixMode; 
+/**
+ * What happens to captured frames and samples when the encoder can't keep up.
+ */
+overflow_policy?: OverflowPolicy }
+/**
+ * Where a recording is in its lifecycle. A new recording can only start once the last
+ * one has completed or failed.
+ */
+export type RecordingPhase = "idle" | "starting" | "recording" | "paused" | 
+/**
+ * Writing out whatever is left to encode.
+ */
+"finalizing" | 
+/**
+ * Waiting on the last segments to be uploaded.
+ */
+"uploading" | "completed" | "failed"
+/**
+ * Encoding settings for a recording. Validated before any capturing starts, so a bad
+ * profile never leaves a half-started FFmpeg process behind.
+ */
+export type RecordingQuality = { codec?: VideoCodec; 
+/**
+ * Defaults to the first segment type the codec supports when unset.
+ */
+segment_type?: HlsSegmentType | null; fps: number; 
+/**
+ * Upper bound for the output size. Frames are scaled down (keeping their aspect
+ * ratio) to fit, but never scaled up.
+ */
+max_width: number | null; max_height: number | null; rate_control: RateControl; preset: EncoderPreset; keyframe_interval_secs: number; audio_bitrate_kbps: number; 
+/**
+ * Only frames that changed get encoded, each lasting until the next one, instead
+ * of `fps` frames every second. `fps` still caps how many there are.
+ */
+variable_frame_rate?: boolean }
+export type RecordingStateChanged = { videoId: string; phase: RecordingPhase }
+export type RecordingStatus = { videoId: string; 
+/**
+ * Not counting pauses.
+ */
+elapsedSecs: number; 
+/**
+ * Frames handed to the encoder per second, since the last event.
+ */
+fps: number; 
+/**
+ * Frames dropped because the encoder couldn't keep up, since the start.
+ */
+droppedFrames: number; 
+/**
+ * The loudest sample of every audio input since the last event, between 0 and 1,
+ * after its gain.
+ */
+audioLevels: number[]; 
+/**
+ * What the encoder has written to the recording directory.
+ */
+bytesWritten: number }
+/**
+ * Where uploads go when they skip the Cap server, and the credentials to sign them with.
+ */
+export type StorageConfig = { 
+endpoint?: string | null; region: string; bucket: string; accessKeyId: string; 
+/**
+ * Left out by `get_storage_config`. Setting the config without one keeps the
+ * current key.
+ */
+secretAccessKey?: string; 
+/**
+ * Puts the bucket in the path (`endpoint/bucket/key`) rather than the host
+ * (`bucket.endpoint/key`), as MinIO and most self-hosted storage expect.
+ */
+pathStyle?: boolean }
+/**
+ * Like `http://localhost:9000` for MinIO. AWS's endpoint for the region when not set.
+ */
+export type UnfinishedRecording = { videoId: string; 
+/**
+ * `uploading` while its upload is being resumed, `failed` otherwise.
+ */
+phase: RecordingPhase; 
+/**
+ * Seconds since the Unix epoch.
+ */
+startedAt: number; error: CapError | null; segments: number }
+/**
+ * Emitted when the queue loses its connection, and when it gets it back.
+ */
+export type UploadConnectivity = { online: boolean }
+/**
+ * A file waiting on (or done with) its upload. Emitted whenever its state changes.
+ */
+export type UploadItem = { id: number; videoId: string; filePath: string; assetType: RecordingAssetType; state: UploadItemState; 
+/**
+ * Failed attempts so far.
+ */
+attempts: number; 
+/**
+ * When the next attempt is due while retrying, in milliseconds since the Unix epoch.
+ */
+retryAt: number | null; 
+/**
+ * Why the last attempt failed.
+ */
+error: CapError | null }
+/**
+ * How far along the upload of a file is, emitted every so often while it's sent.
+ */
+export type UploadItemProgress = { id: number; videoId: string; sentBytes: number; totalBytes: number }
+export type UploadItemState = "queued" | "uploading" | 
+/**
+ * Waiting out the backoff after a failed attempt.
+ */
+"retrying" | "uploaded" | 
+/**
+ * Given up on, until the recording's upload is resumed.
+ */
+"failed"
+export type UploadQueueState = { 
+online: boolean; items: UploadItem[] }
+/**
+ * Uploads are held off while offline.
+ */
+export type UploadStatus = { videoId: string; uploaded: number; 
+/**
+ * Written by the encoder and waiting on (or in the middle of) their upload.
+ */
+pending: number; failed: number }
+export type VideoCodec = "h264" | "hevc" | "vp9" | "av1"
+export type VideoCodecInfo = { codec: VideoCodec; encoder: string; segment_types: HlsSegmentType[] }
+export type WindowBounds = { x: number; y: number; width: number; height: number }
 
 /** tauri-specta globals **/
 
@@ -0,0 +1,85 @@
+import {
+  CompleteMultipartUploadCommand,
+  NoSuchUpload,
+  S3Client,
+} from "@aws-sdk/client-s3";
+import { NextRequest } from "next/server";
+
+const s3Client = new S3Client({
+  region: process.env.NEXT_PUBLIC_CAP_AWS_REGION || "",
+  credentials: {
+    accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+    secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+  },
+});
+
+type CompletedPart = {
+  partNumber: number;
+  etag: string;
+};
+
+export async function POST(request: NextRequest) {
+  try {
+    const { userId, fileKey, uploadId, parts, awsBucket, awsRegion } =
+      await request.json();
+
+    if (
+      !userId ||
+      !fileKey ||
+      !uploadId ||
+      !Array.isArray(parts) ||
+      !awsBucket ||
+      !awsRegion
+    ) {
+      console.error(
+        "Missing required fields in /api/upload/multipart/complete/route.ts"
+      );
+
+      return new Response(
+        JSON.stringify({ error: "Missing required fields" }),
+        {
+          status: 400,
+          headers: {
+            "Content-Type": "application/json",
+          },
+        }
+      );
+    }
+
+    const { Location } = await s3Client.send(
+      new CompleteMultipartUploadCommand({
+        Bucket: awsBucket,
+        Key: fileKey,
+        UploadId: uploadId,
+        MultipartUpload: {
+          Parts: (parts as CompletedPart[]).map(({ partNumber, etag }) => ({
+            PartNumber: partNumber,
+            ETag: etag,
+          })),
+        },
+      })
+    );
+
+    console.log("Multipart upload completed successfully");
+
+    return new Response(JSON.stringify({ location: Location }), {
+      headers: {
+        "Content-Type": "application/json",
+      },
+    });
+  } catch (error) {
+    console.error("Error completing multipart upload", error);
+
+    // The desktop app starts the upload over when S3 has given up on it
+    const status = error instanceof NoSuchUpload ? 404 : 500;
+    return new Response(
+      JSON.stringify({ error: "Error completing multipart upload" }),
+      {
+        status,
+        headers: {
+          "Content-Type": "application/json",
+        },
+      }
+    );
+  }
+}
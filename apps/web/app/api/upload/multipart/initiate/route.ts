@@ -0,0 +1,80 @@
+import { CreateMultipartUploadCommand, S3Client } from "@aws-sdk/client-s3";
+import { NextRequest } from "next/server";
+import { getUploadContentType } from "@/utils/video/upload/helpers";
+
+const s3Client = new S3Client({
+  region: process.env.NEXT_PUBLIC_CAP_AWS_REGION || "",
+  credentials: {
+    accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+    secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+  },
+});
+
+export async function POST(request: NextRequest) {
+  try {
+    const {
+      userId,
+      fileKey,
+      duration,
+      bandwidth,
+      resolution,
+      videoCodec,
+      audioCodec,
+      awsBucket,
+      awsRegion,
+    } = await request.json();
+
+    if (!userId || !fileKey || !awsBucket || !awsRegion) {
+      console.error(
+        "Missing required fields in /api/upload/multipart/initiate/route.ts"
+      );
+
+      return new Response(
+        JSON.stringify({ error: "Missing required fields" }),
+        {
+          status: 400,
+          headers: {
+            "Content-Type": "application/json",
+          },
+        }
+      );
+    }
+
+    const contentType = getUploadContentType(fileKey);
+
+    const { UploadId } = await s3Client.send(
+      new CreateMultipartUploadCommand({
+        Bucket: awsBucket,
+        Key: fileKey,
+        ContentType: contentType,
+        Metadata: {
+          userid: userId,
+          duration: duration ?? "",
+          bandwidth: bandwidth ?? "",
+          resolution: resolution ?? "",
+          videocodec: videoCodec ?? "",
+          audiocodec: audioCodec ?? "",
+        },
+      })
+    );
+
+    console.log("Multipart upload started successfully");
+
+    return new Response(JSON.stringify({ uploadId: UploadId }), {
+      headers: {
+        "Content-Type": "application/json",
+      },
+    });
+  } catch (error) {
+    console.error("Error starting multipart upload", error);
+    return new Response(
+      JSON.stringify({ error: "Error starting multipart upload" }),
+      {
+        status: 500,
+        headers: {
+          "Content-Type": "application/json",
+        },
+      }
+    );
+  }
+}
@@ -0,0 +1,70 @@
+import { S3Client, UploadPartCommand } from "@aws-sdk/client-s3";
+import { getSignedUrl } from "@aws-sdk/s3-request-presigner";
+import { NextRequest } from "next/server";
+
+const s3Client = new S3Client({
+  region: process.env.NEXT_PUBLIC_CAP_AWS_REGION || "",
+  credentials: {
+    accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+    secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+  },
+});
+
+export async function POST(request: NextRequest) {
+  try {
+    const { userId, fileKey, uploadId, partNumber, awsBucket, awsRegion } =
+      await request.json();
+
+    if (
+      !userId ||
+      !fileKey ||
+      !uploadId ||
+      !partNumber ||
+      !awsBucket ||
+      !awsRegion
+    ) {
+      console.error(
+        "Missing required fields in /api/upload/multipart/presign-part/route.ts"
+      );
+
+      return new Response(
+        JSON.stringify({ error: "Missing required fields" }),
+        {
+          status: 400,
+          headers: {
+            "Content-Type": "application/json",
+          },
+        }
+      );
+    }
+
+    // Parts are requested one at a time, right before they're sent
+    const presignedUrl = await getSignedUrl(
+      s3Client,
+      new UploadPartCommand({
+        Bucket: awsBucket,
+        Key: fileKey,
+        UploadId: uploadId,
+        PartNumber: partNumber,
+      }),
+      { expiresIn: 3600 }
+    );
+
+    return new Response(JSON.stringify({ presignedUrl }), {
+      headers: {
+        "Content-Type": "application/json",
+      },
+    });
+  } catch (error) {
+    console.error("Error creating presigned part URL", error);
+    return new Response(
+      JSON.stringify({ error: "Error creating presigned part URL" }),
+      {
+        status: 500,
+        headers: {
+          "Content-Type": "application/json",
+        },
+      }
+    );
+  }
+}
@@ -1,6 +1,7 @@
 import { S3Client } from "@aws-sdk/client-s3";
 import { createPresignedPost, PresignedPost } from "@aws-sdk/s3-presigned-post";
 import { NextRequest } from "next/server";
+import { getUploadContentType } from "@/utils/video/upload/helpers";
 
 const s3Client = new S3Client({
   region: process.env.NEXT_PUBLIC_CAP_AWS_REGION || "",
@@ -38,17 +39,7 @@ export async function POST(request: NextRequest) {
       );
     }
 
-    const contentType = fileKey.endsWith(".aac")
-      ? "audio/aac"
-      : fileKey.endsWith(".webm")
-      ? "audio/webm"
-      : fileKey.endsWith(".mp4")
-      ? "video/mp4"
-      : fileKey.endsWith(".mp3")
-      ? "audio/mpeg"
-      : fileKey.endsWith(".m3u8")
-      ? "application/x-mpegURL"
-      : "video/mp2t";
+    const contentType = getUploadContentType(fileKey);
 
     const Fields = {
       "Content-Type": contentType,
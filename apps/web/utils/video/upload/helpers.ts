@@ -58,3 +58,24 @@ export async function uploadToS3({
 
   return true;
 }
+
+/**
+ * The content type an uploaded recording file is stored with, by its extension.
+ */
+export function getUploadContentType(fileKey: string) {
+  return fileKey.endsWith(".aac")
+    ? "audio/aac"
+    : fileKey.endsWith(".webm")
+    ? "audio/webm"
+    : fileKey.endsWith(".mp4")
+    ? "video/mp4"
+    : fileKey.endsWith(".mp3")
+    ? "audio/mpeg"
+    : fileKey.endsWith(".m3u8")
+    ? "application/x-mpegURL"
+    : fileKey.endsWith(".json")
+    ? "application/json"
+    : fileKey.endsWith(".m4s")
+    ? "video/iso.segment"
+    : "video/mp2t";
+}